@@ -0,0 +1,240 @@
+use crate::{
+    interval::{self, Interval},
+    packet::{LANES, Mask, RayPacket},
+    ray::Ray,
+    vec3::Vec3,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        EMPTY
+    }
+}
+
+#[allow(dead_code)]
+impl Aabb {
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
+        Aabb { x, y, z }
+    }
+
+    /// Box spanning two opposite corners, given in any order
+    pub fn from_points(a: Vec3, b: Vec3) -> Self {
+        Aabb {
+            x: Interval::new(a.0.min(b.0), a.0.max(b.0)),
+            y: Interval::new(a.1.min(b.1), a.1.max(b.1)),
+            z: Interval::new(a.2.min(b.2), a.2.max(b.2)),
+        }
+    }
+
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Self {
+        Aabb {
+            x: Interval::enclosing(a.x, b.x),
+            y: Interval::enclosing(a.y, b.y),
+            z: Interval::enclosing(a.z, b.z),
+        }
+    }
+
+    /// Widens any side thinner than `delta` so flat objects still get a box with volume
+    pub fn pad_to_minimums(&self) -> Aabb {
+        let delta = 0.0001;
+        let pad = |i: Interval| if i.size() < delta { i.expand(delta) } else { i };
+
+        Aabb {
+            x: pad(self.x),
+            y: pad(self.y),
+            z: pad(self.z),
+        }
+    }
+
+    pub fn axis_interval(&self, n: usize) -> Interval {
+        match n {
+            1 => self.y,
+            2 => self.z,
+            _ => self.x,
+        }
+    }
+
+    /// True if the boxes share any space, touching faces included
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        let meet = |a: Interval, b: Interval| a.min <= b.max && b.min <= a.max;
+        meet(self.x, other.x) && meet(self.y, other.y) && meet(self.z, other.z)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.size() < 0. || self.y.size() < 0. || self.z.size() < 0.
+    }
+
+    pub fn min(&self) -> Vec3 {
+        Vec3(self.x.min, self.y.min, self.z.min)
+    }
+
+    pub fn max(&self) -> Vec3 {
+        Vec3(self.x.max, self.y.max, self.z.max)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min() + self.max())
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        if x > y {
+            if x > z { 0 } else { 2 }
+        } else if y > z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        2. * (x * y + y * z + z * x)
+    }
+
+    /// Slab test - true if the ray passes through the box anywhere within `ray_t`
+    pub fn hit(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.clip(r, ray_t).is_some()
+    }
+
+    /// The part of `ray_t` during which the ray is inside the box, if any
+    pub fn clip(&self, r: &Ray, ray_t: Interval) -> Option<Interval> {
+        let mut t_min = ray_t.min;
+        let mut t_max = ray_t.max;
+
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis);
+
+            // Parallel to the slab. Dividing would give 0 * inf = NaN for a ray lying right on
+            // one of its planes
+            if r.direction[axis] == 0. {
+                if !ax.contains(r.origin[axis]) {
+                    return None;
+                }
+                continue;
+            }
+            let ad_inv = 1.0 / r.direction[axis];
+
+            let t0 = (ax.min - r.origin[axis]) * ad_inv;
+            let t1 = (ax.max - r.origin[axis]) * ad_inv;
+
+            let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            if near > t_min {
+                t_min = near;
+            }
+            if far < t_max {
+                t_max = far;
+            }
+
+            if t_max <= t_min {
+                return None;
+            }
+        }
+
+        Some(Interval::new(t_min, t_max))
+    }
+
+    /// Slab test for every lane of a packet at once, each within its own interval. Agrees with
+    /// `hit` lane by lane
+    pub fn hit_packet(&self, packet: &RayPacket, ray_t: &[Interval; LANES]) -> Mask {
+        let mut t_min: [f64; LANES] = std::array::from_fn(|i| ray_t[i].min);
+        let mut t_max: [f64; LANES] = std::array::from_fn(|i| ray_t[i].max);
+
+        let slabs = [
+            (
+                self.x,
+                &packet.origin.x,
+                &packet.direction.x,
+                &packet.inv_direction.x,
+            ),
+            (
+                self.y,
+                &packet.origin.y,
+                &packet.direction.y,
+                &packet.inv_direction.y,
+            ),
+            (
+                self.z,
+                &packet.origin.z,
+                &packet.direction.z,
+                &packet.inv_direction.z,
+            ),
+        ];
+        for (ax, origin, direction, inv) in slabs {
+            for i in 0..LANES {
+                let (near, far) = if direction[i] == 0. {
+                    // Parallel, so either always between the planes or never
+                    if ax.contains(origin[i]) {
+                        (f64::NEG_INFINITY, f64::INFINITY)
+                    } else {
+                        (f64::INFINITY, f64::NEG_INFINITY)
+                    }
+                } else {
+                    let t0 = (ax.min - origin[i]) * inv[i];
+                    let t1 = (ax.max - origin[i]) * inv[i];
+                    if t0 < t1 { (t0, t1) } else { (t1, t0) }
+                };
+                t_min[i] = t_min[i].max(near);
+                t_max[i] = t_max[i].min(far);
+            }
+        }
+
+        std::array::from_fn(|i| t_max[i] > t_min[i])
+    }
+}
+
+pub const EMPTY: Aabb = Aabb {
+    x: interval::EMPTY,
+    y: interval::EMPTY,
+    z: interval::EMPTY,
+};
+
+#[cfg(test)]
+mod aabb_tests {
+    use super::*;
+
+    #[test]
+    fn ray_through_box() {
+        let bbox = Aabb::from_points(Vec3(1., 1., 1.), Vec3(-1., -1., -1.));
+        let r = Ray::new(Vec3(0., 0., -5.), Vec3(0., 0., 1.));
+        assert!(bbox.hit(&r, Interval::new(0.001, f64::INFINITY)));
+
+        let inside = bbox.clip(&r, Interval::new(0.001, f64::INFINITY)).unwrap();
+        assert_eq!((inside.min, inside.max), (4., 6.));
+
+        let miss = Ray::new(Vec3(0., 2., -5.), Vec3(0., 0., 1.));
+        assert!(!bbox.hit(&miss, Interval::new(0.001, f64::INFINITY)));
+
+        // Lying exactly in the plane of a face still counts as passing through
+        let on_face = Ray::new(Vec3(0., 1., -5.), Vec3(0., 0., 1.));
+        assert!(bbox.hit(&on_face, Interval::new(0.001, f64::INFINITY)));
+
+        // Box is behind the ray
+        let behind = Ray::new(Vec3(0., 0., 5.), Vec3(0., 0., 1.));
+        assert!(!bbox.hit(&behind, Interval::new(0.001, f64::INFINITY)));
+    }
+
+    #[test]
+    fn surrounding_and_area() {
+        let a = Aabb::from_points(Vec3(0., 0., 0.), Vec3(1., 1., 1.));
+        let b = Aabb::from_points(Vec3(2., 0., 0.), Vec3(3., 1., 1.));
+        let c = Aabb::surrounding(&a, &b);
+
+        assert_eq!(c.min(), Vec3(0., 0., 0.));
+        assert_eq!(c.max(), Vec3(3., 1., 1.));
+        assert_eq!(c.longest_axis(), 0);
+        assert_eq!(a.surface_area(), 6.);
+        assert_eq!(EMPTY.surface_area(), 0.);
+    }
+}
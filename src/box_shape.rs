@@ -0,0 +1,264 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    random::Random,
+    ray::Ray,
+    vec3::{Vec3, dot},
+};
+
+/// Axis-aligned box between two opposite corners, intersected analytically with a slab test.
+///
+/// Rotate or move it with an `Instance`.
+pub struct BoxShape {
+    bbox: Aabb,
+    mat: Material,
+}
+
+#[allow(dead_code)]
+impl BoxShape {
+    pub fn new(a: Vec3, b: Vec3, mat: Material) -> Self {
+        BoxShape {
+            bbox: Aabb::from_points(a, b),
+            mat,
+        }
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    /// Area of the two faces perpendicular to each axis
+    fn face_areas(&self) -> [f64; 3] {
+        let size = |axis: usize| self.bbox.axis_interval(axis).size();
+        [0, 1, 2].map(|axis| 2. * size((axis + 1) % 3) * size((axis + 2) % 3))
+    }
+
+    /// Uniformly random point on the surface
+    fn random_point(&self) -> Vec3 {
+        let areas = self.face_areas();
+        let mut pick = f64::rnd() * areas.iter().sum::<f64>();
+        let axis = (0..2)
+            .find(|&axis| {
+                pick -= areas[axis];
+                pick < 0.
+            })
+            .unwrap_or(2);
+
+        let across = |i: usize| {
+            let slab = self.bbox.axis_interval(i);
+            if i != axis {
+                f64::rnd_rng(slab.min, slab.max)
+            } else if f64::rnd() < 0.5 {
+                slab.min
+            } else {
+                slab.max
+            }
+        };
+        Vec3(across(0), across(1), across(2))
+    }
+
+    /// Surface (u, v) of `p` on the face perpendicular to `axis`, spanning [0, 1] across the face.
+    /// A flat box has faces with no width along some axis, and those get 0 along it
+    fn face_uv(&self, p: Vec3, axis: usize) -> (f64, f64) {
+        let across = |i: usize| {
+            let slab = self.bbox.axis_interval(i);
+            if slab.size() > 0. {
+                (p[i] - slab.min) / slab.size()
+            } else {
+                0.
+            }
+        };
+        (across((axis + 1) % 3), across((axis + 2) % 3))
+    }
+}
+
+impl Hittable for BoxShape {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        let mut t_near = f64::NEG_INFINITY;
+        let mut t_far = f64::INFINITY;
+        let (mut near_axis, mut far_axis) = (0, 0);
+
+        for axis in 0..3 {
+            let slab = self.bbox.axis_interval(axis);
+            let (o, d) = (r.origin[axis], r.direction[axis]);
+
+            // Parallel to this slab: either always between its planes or never
+            if d == 0. {
+                if !slab.contains(o) {
+                    return (false, None);
+                }
+                continue;
+            }
+
+            let t0 = (slab.min - o) / d;
+            let t1 = (slab.max - o) / d;
+            let (enter, exit) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            if enter > t_near {
+                t_near = enter;
+                near_axis = axis;
+            }
+            if exit < t_far {
+                t_far = exit;
+                far_axis = axis;
+            }
+            if t_near > t_far {
+                return (false, None);
+            }
+        }
+
+        // Entering from outside, or leaving from inside
+        let (t, axis, sign) = if ray_t.surrounds(t_near) {
+            (t_near, near_axis, -r.direction[near_axis].signum())
+        } else if ray_t.surrounds(t_far) {
+            (t_far, far_axis, r.direction[far_axis].signum())
+        } else {
+            return (false, None);
+        };
+
+        let mut p = r.at(t);
+        let mut outward_normal = Vec3(0., 0., 0.);
+        let slab = self.bbox.axis_interval(axis);
+        let face = if sign > 0. { slab.max } else { slab.min };
+        match axis {
+            0 => (outward_normal.0, p.0) = (sign, face),
+            1 => (outward_normal.1, p.1) = (sign, face),
+            _ => (outward_normal.2, p.2) = (sign, face),
+        }
+        let (u, v) = self.face_uv(p, axis);
+
+        // Snapped onto the face, so there's no error across it to step over
+        let mut rec = HitRecord::new(r, t, p, outward_normal, self.mat.clone(), u, v);
+        match axis {
+            0 => rec.p_error.0 = 0.,
+            1 => rec.p_error.1 = 0.,
+            _ => rec.p_error.2 = 0.,
+        }
+        (true, Some(rec))
+    }
+
+    fn samples_as_light(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        let area: f64 = self.face_areas().iter().sum();
+        if area <= 0. {
+            return 0.;
+        }
+
+        // A line from outside crosses two faces, and a point on either picks the same direction
+        r.direction.length_squared()
+            * self
+                .hit_all(r, Interval::new(0., f64::INFINITY))
+                .iter()
+                .map(|rec| {
+                    let cosine = dot(r.direction, rec.normal).abs() / r.direction.length();
+                    rec.t * rec.t / (cosine * area)
+                })
+                .sum::<f64>()
+    }
+
+    fn random_toward(&self, origin: Vec3, _time: f64) -> Vec3 {
+        self.random_point() - origin
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod box_shape_tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{instance::Instance, transform::Transform};
+
+    fn unit_box() -> BoxShape {
+        let mat = Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        };
+        BoxShape::new(Vec3(1., 1., 1.), Vec3(-1., -1., -1.), mat)
+    }
+
+    #[test]
+    fn hit_from_outside() {
+        let b = unit_box();
+        let r = Ray::new(Vec3(0.5, 0., -5.), Vec3(0., 0., 1.));
+        let (hit, rec) = b.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+
+        assert!(hit);
+        assert_eq!(rec.t, 4.);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3(0., 0., -1.));
+        // Face z = -1 spans x and y from -1 to 1
+        assert!((rec.u - 0.75).abs() < 1e-12);
+        assert!((rec.v - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn flat_box_has_finite_uv() {
+        let mat = Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        };
+        let sheet = BoxShape::new(Vec3(-1., 0., -1.), Vec3(1., 0., 1.), mat);
+        let r = Ray::new(Vec3(0.5, 5., 0.), Vec3(0., -1., 0.));
+        let rec = sheet
+            .hit(&r, Interval::new(0.001, f64::INFINITY))
+            .1
+            .unwrap();
+
+        assert_eq!(rec.normal, Vec3(0., 1., 0.));
+        // Face y = 0 spans z, then x
+        assert!((rec.u - 0.5).abs() < 1e-12);
+        assert!((rec.v - 0.75).abs() < 1e-12);
+
+        // The sides have no height, but still give numbers
+        let edge_on = Ray::new(Vec3(5., 0., 0.5), Vec3(-1., 0., 0.));
+        let rec = sheet
+            .hit(&edge_on, Interval::new(0.001, f64::INFINITY))
+            .1
+            .unwrap();
+        assert_eq!((rec.u, rec.v), (0., 0.75));
+    }
+
+    #[test]
+    fn hit_from_inside() {
+        let b = unit_box();
+        let r = Ray::new(Vec3(0., 0., 0.), Vec3(0., -2., 0.));
+        let (_, rec) = b.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+
+        assert_eq!(rec.t, 0.5);
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3(0., 1., 0.));
+    }
+
+    #[test]
+    fn misses_and_parallel_rays() {
+        let b = unit_box();
+        let past = Ray::new(Vec3(0., 1.5, -5.), Vec3(0., 0., 1.));
+        assert!(!b.hit(&past, Interval::new(0.001, f64::INFINITY)).0);
+
+        let diagonal_miss = Ray::new(Vec3(-5., 4.5, 0.), Vec3(1., -0.4, 0.));
+        assert!(!b.hit(&diagonal_miss, Interval::new(0.001, f64::INFINITY)).0);
+
+        let behind = Ray::new(Vec3(0., 0., 5.), Vec3(0., 0., 1.));
+        assert!(!b.hit(&behind, Interval::new(0.001, f64::INFINITY)).0);
+    }
+
+    #[test]
+    fn rotated_box() {
+        let b: Arc<dyn Hittable> = Arc::new(unit_box());
+        let turned = Instance::new(b, Transform::rotate_y(45.));
+
+        // Turned 45 degrees, the corner now pokes out to x = sqrt(2)
+        let r = Ray::new(Vec3(5., 0., 0.), Vec3(-1., 0., 0.));
+        let (_, rec) = turned.hit(&r, Interval::new(0.001, f64::INFINITY));
+        assert!((rec.unwrap().t - (5. - 2f64.sqrt())).abs() < 1e-9);
+    }
+}
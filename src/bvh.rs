@@ -0,0 +1,269 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    hittable_list::HittableList,
+    interval::Interval,
+    packet::{Mask, PacketHits, RayPacket, lane_intervals},
+    ray::Ray,
+};
+
+/// Number of buckets centroids are binned into when evaluating split planes
+const SAH_BUCKETS: usize = 12;
+/// Cost of visiting a node relative to intersecting one primitive
+const TRAVERSAL_COST: f64 = 0.125;
+/// Above this many objects a leaf is always split, even if SAH says otherwise
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Bounding volume hierarchy over a set of hittables, split with the surface area heuristic
+pub enum Bvh {
+    Leaf(HittableList),
+    Node {
+        left: Box<Bvh>,
+        right: Box<Bvh>,
+        bbox: Aabb,
+    },
+}
+
+impl Bvh {
+    pub fn new(list: HittableList) -> Bvh {
+        Bvh::build(list.into_objects())
+    }
+
+    fn build(objects: Vec<Box<dyn Hittable>>) -> Bvh {
+        let bboxes: Vec<Aabb> = objects.iter().map(|o| o.bounding_box()).collect();
+        let bbox = bboxes
+            .iter()
+            .fold(Aabb::default(), |acc, b| Aabb::surrounding(&acc, b));
+
+        if objects.len() <= 1 {
+            return Bvh::leaf(objects);
+        }
+
+        let centroid_bounds = bboxes.iter().fold(Aabb::default(), |acc, b| {
+            let c = b.centroid();
+            Aabb::surrounding(&acc, &Aabb::from_points(c, c))
+        });
+        let axis = centroid_bounds.longest_axis();
+        let extent = centroid_bounds.axis_interval(axis);
+
+        // Every centroid sits on the same spot, so no plane can separate them
+        if extent.size() <= 0. {
+            return if objects.len() <= MAX_LEAF_SIZE {
+                Bvh::leaf(objects)
+            } else {
+                Bvh::split_in_half(objects, bboxes, axis, bbox)
+            };
+        }
+
+        let bucket_of = |b: &Aabb| {
+            let offset = (b.centroid()[axis] - extent.min) / extent.size();
+            ((offset * SAH_BUCKETS as f64) as usize).min(SAH_BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; SAH_BUCKETS];
+        let mut bucket_bounds = [Aabb::default(); SAH_BUCKETS];
+        for b in &bboxes {
+            let i = bucket_of(b);
+            counts[i] += 1;
+            bucket_bounds[i] = Aabb::surrounding(&bucket_bounds[i], b);
+        }
+
+        // Cost of splitting after each bucket, relative to the parent's area
+        let mut best_split = 0;
+        let mut best_cost = f64::INFINITY;
+        for split in 0..SAH_BUCKETS - 1 {
+            let (mut left_box, mut right_box) = (Aabb::default(), Aabb::default());
+            let (mut left_count, mut right_count) = (0, 0);
+
+            for i in 0..=split {
+                left_box = Aabb::surrounding(&left_box, &bucket_bounds[i]);
+                left_count += counts[i];
+            }
+            for i in split + 1..SAH_BUCKETS {
+                right_box = Aabb::surrounding(&right_box, &bucket_bounds[i]);
+                right_count += counts[i];
+            }
+
+            let cost = TRAVERSAL_COST
+                + (left_count as f64 * left_box.surface_area()
+                    + right_count as f64 * right_box.surface_area())
+                    / bbox.surface_area();
+
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = split;
+            }
+        }
+
+        let leaf_cost = objects.len() as f64;
+        if objects.len() <= MAX_LEAF_SIZE && leaf_cost <= best_cost {
+            return Bvh::leaf(objects);
+        }
+
+        let (left, right): (Vec<_>, Vec<_>) = objects
+            .into_iter()
+            .zip(bboxes)
+            .partition(|(_, b)| bucket_of(b) <= best_split);
+
+        if left.is_empty() || right.is_empty() {
+            let (objects, bboxes) = left.into_iter().chain(right).unzip();
+            return Bvh::split_in_half(objects, bboxes, axis, bbox);
+        }
+
+        Bvh::Node {
+            left: Box::new(Bvh::build(left.into_iter().map(|(o, _)| o).collect())),
+            right: Box::new(Bvh::build(right.into_iter().map(|(o, _)| o).collect())),
+            bbox,
+        }
+    }
+
+    /// Fallback when binning can't separate the objects: sort along `axis` and cut the list in two
+    fn split_in_half(
+        objects: Vec<Box<dyn Hittable>>,
+        bboxes: Vec<Aabb>,
+        axis: usize,
+        bbox: Aabb,
+    ) -> Bvh {
+        let mut pairs: Vec<_> = objects.into_iter().zip(bboxes).collect();
+        pairs.sort_by(|(_, a), (_, b)| a.centroid()[axis].total_cmp(&b.centroid()[axis]));
+
+        let right = pairs.split_off(pairs.len() / 2);
+        let left = pairs;
+
+        Bvh::Node {
+            left: Box::new(Bvh::build(left.into_iter().map(|(o, _)| o).collect())),
+            right: Box::new(Bvh::build(right.into_iter().map(|(o, _)| o).collect())),
+            bbox,
+        }
+    }
+
+    fn leaf(objects: Vec<Box<dyn Hittable>>) -> Bvh {
+        let mut list = HittableList::default();
+        for obj in objects {
+            list.add(obj);
+        }
+        Bvh::Leaf(list)
+    }
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        if !self.bounding_box().hit(r, ray_t) {
+            return (false, None);
+        }
+
+        match self {
+            Bvh::Leaf(list) => list.hit(r, ray_t),
+            Bvh::Node { left, right, .. } => {
+                let (hit_left, left_rec) = left.hit(r, ray_t);
+                let closest = match &left_rec {
+                    Some(rec) if hit_left => rec.t,
+                    _ => ray_t.max,
+                };
+
+                let (hit_right, right_rec) = right.hit(r, Interval::new(ray_t.min, closest));
+                if hit_right {
+                    (true, right_rec)
+                } else {
+                    (hit_left, left_rec)
+                }
+            }
+        }
+    }
+
+    fn hit_packet(&self, packet: &RayPacket, mask: Mask, ray_t: Interval, hits: &mut PacketHits) {
+        // Only lanes that reach the box go further down, as they would tracing alone
+        let mask = self
+            .bounding_box()
+            .hit_packet(packet, &lane_intervals(mask, ray_t, hits));
+        if !mask.contains(&true) {
+            return;
+        }
+
+        match self {
+            Bvh::Leaf(list) => list.hit_packet(packet, mask, ray_t, hits),
+            Bvh::Node { left, right, .. } => {
+                left.hit_packet(packet, mask, ray_t, hits);
+                right.hit_packet(packet, mask, ray_t, hits);
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            Bvh::Leaf(list) => list.bounding_box(),
+            Bvh::Node { bbox, .. } => *bbox,
+        }
+    }
+}
+
+#[cfg(test)]
+mod bvh_tests {
+    use super::*;
+    use crate::{material::Material, random::Random, sphere::Sphere, vec3::Vec3};
+
+    fn random_spheres(n: usize) -> Vec<(Vec3, f64)> {
+        (0..n)
+            .map(|_| (Vec3::rnd_rng(-20., 20.), f64::rnd_rng(0.1, 1.5)))
+            .collect()
+    }
+
+    fn world_from(spheres: &[(Vec3, f64)]) -> HittableList {
+        let mut world = HittableList::default();
+        for &(center, radius) in spheres {
+            let mat = Material::Lambertian {
+                albedo: center.into(),
+            };
+            world.add(Sphere::new(center, radius, mat).into_box());
+        }
+        world
+    }
+
+    #[test]
+    fn same_closest_hit_as_list() {
+        let spheres = random_spheres(300);
+        let list = world_from(&spheres);
+        let bvh = Bvh::new(world_from(&spheres));
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let r = Ray::new(Vec3::rnd_rng(-25., 25.), Vec3::rnd_rng(-1., 1.));
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+
+            let (list_hit, list_rec) = list.hit(&r, ray_t);
+            let (bvh_hit, bvh_rec) = bvh.hit(&r, ray_t);
+
+            assert_eq!(list_hit, bvh_hit);
+            if let (Some(a), Some(b)) = (list_rec, bvh_rec) {
+                hits += 1;
+                assert_eq!(a.t, b.t);
+                assert_eq!(a.p, b.p);
+                assert_eq!(a.normal, b.normal);
+            }
+        }
+
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn bounds_match_list() {
+        let spheres = random_spheres(50);
+        let list = world_from(&spheres);
+        let bvh = Bvh::new(world_from(&spheres));
+
+        assert_eq!(list.bounding_box().min(), bvh.bounding_box().min());
+        assert_eq!(list.bounding_box().max(), bvh.bounding_box().max());
+    }
+
+    #[test]
+    fn coincident_objects() {
+        // Identical centroids can't be binned apart - building must still terminate
+        let spheres = vec![(Vec3(1., 2., 3.), 0.5); 20];
+        let bvh = Bvh::new(world_from(&spheres));
+
+        let r = Ray::new(Vec3(1., 2., -10.), Vec3(0., 0., 1.));
+        let (hit, rec) = bvh.hit(&r, Interval::new(0.001, f64::INFINITY));
+        assert!(hit);
+        assert!((rec.unwrap().t - 12.5).abs() < 1e-9);
+    }
+}
@@ -1,9 +1,11 @@
 use crate::{
+    aabb::Aabb,
     color::write_color,
     global_stuff::degrees_to_radians,
-    hittable::Hittable,
+    hittable::{HitRecord, Hittable},
     hittable_list::HittableList,
     interval::Interval,
+    packet::{LANES, PacketHits, RayPacket},
     random::Random,
     ray::Ray,
     vec3::{Vec3, cross, random_in_unit_disk, unit},
@@ -22,6 +24,37 @@ pub struct CameraConfig {
     pub max_depth: i32,
     pub defocus_angle: f64,
     pub focus_dist: f64,
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+#[allow(dead_code)]
+impl CameraConfig {
+    /// Aims at the middle of `bbox` and backs off along the current viewing direction until all
+    /// of it is in shot, keeping the field of view. Focuses on the middle too
+    pub fn framing(mut self, bbox: &Aabb) -> Self {
+        if bbox.is_empty() {
+            return self;
+        }
+
+        let back = self.look_from - self.look_at;
+        let back = if back.near_zero() {
+            Vec3(0., 0., 1.)
+        } else {
+            unit(back)
+        };
+
+        // Fit the sphere around the box inside whichever of the width or height is narrower
+        let radius = 0.5 * (bbox.max() - bbox.min()).length();
+        let half_height = degrees_to_radians(self.vfov) / 2.;
+        let half_width = (half_height.tan() * self.aspect_ratio).atan();
+        let distance = radius / half_height.min(half_width).sin();
+
+        self.look_at = bbox.centroid();
+        self.look_from = self.look_at + distance * back;
+        self.focus_dist = distance;
+        self
+    }
 }
 
 #[allow(dead_code)]
@@ -36,6 +69,8 @@ pub struct Camera {
     pub max_depth: i32,
     pub defocus_angle: f64,
     pub focus_dist: f64,
+    pub shutter_open: f64,
+    pub shutter_close: f64,
     image_height: i64,
     camera_center: Vec3,
     pixel_samples_scale: f64,
@@ -47,6 +82,9 @@ pub struct Camera {
     w: Vec3,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    packets: bool,
+    background: Background,
+    lights: HittableList,
 }
 
 impl Camera {
@@ -62,6 +100,8 @@ impl Camera {
             max_depth,
             defocus_angle,
             focus_dist,
+            shutter_open,
+            shutter_close,
         } = cfg;
         let image_height = (image_width / aspect_ratio) as i64;
 
@@ -120,10 +160,47 @@ impl Camera {
             focus_dist,
             defocus_disk_u,
             defocus_disk_v,
+            shutter_open,
+            shutter_close,
+            packets: false,
+            background: Background::sky(),
+            lights: HittableList::default(),
         }
     }
 
-    pub fn render(&mut self, world: &HittableList) {
+    /// Emitters to aim shadow rays at from diffuse surfaces, as copies of ones in the world.
+    /// Paths still find lights by bouncing into them too, and the two are blended with the power
+    /// heuristic.
+    ///
+    /// Panics unless every light can be sampled: quads, spheres, boxes and instances of them
+    #[allow(dead_code)]
+    pub fn with_lights(mut self, lights: HittableList) -> Self {
+        assert!(
+            lights.samples_as_light(),
+            "Lights must be quads, spheres, boxes or instances of them"
+        );
+        self.lights = lights;
+        self
+    }
+
+    /// What rays see when they leave the scene. Black leaves the scene's own lights as the only
+    /// ones, for interiors like a Cornell box
+    #[allow(dead_code)]
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Traces each pixel's camera rays `LANES` at a time through the world's packet path. Only
+    /// the first hit is shared - bounces after it go their own way. Off by default, leaving the
+    /// scalar path as the reference
+    #[allow(dead_code)]
+    pub fn with_packets(mut self, packets: bool) -> Self {
+        self.packets = packets;
+        self
+    }
+
+    pub fn render(&mut self, world: &dyn Hittable) {
         let &mut Camera {
             image_width,
             image_height,
@@ -138,9 +215,8 @@ impl Camera {
             let row: Vec<Vec3> = (0..(image_width as i64))
                 .collect::<Vec<_>>()
                 .par_iter()
-                .map(|i| {
-                    self.render_pixel(j, *i, world)
-                }).collect();
+                .map(|i| self.render_pixel(j, *i, world))
+                .collect();
 
             for pixel in row {
                 write_color(pixel);
@@ -149,7 +225,7 @@ impl Camera {
         eprint!("\rDone! ");
     }
 
-    fn render_pixel(&self, j: i64, i: i64, world: &HittableList) -> Vec3 {
+    fn render_pixel(&self, j: i64, i: i64, world: &dyn Hittable) -> Vec3 {
         let &Camera {
             samples_per_pixel,
             pixel_samples_scale,
@@ -158,10 +234,33 @@ impl Camera {
         } = self;
 
         let mut pixel_color = Vec3(0., 0., 0.);
-        for _sample in 0..samples_per_pixel {
-            let r = self.get_ray(i, j);
+        if self.packets {
+            let mut remaining = samples_per_pixel as usize;
+            while remaining > 0 {
+                let rays: Vec<Ray> = (0..remaining.min(LANES))
+                    .map(|_| self.get_ray(i, j))
+                    .collect();
+                remaining -= rays.len();
+
+                let packet = RayPacket::new(&rays);
+                let mut hits = PacketHits::default();
+                world.hit_packet(
+                    &packet,
+                    packet.filled,
+                    Interval::new(0., f64::INFINITY),
+                    &mut hits,
+                );
+
+                for (r, hit) in rays.iter().zip(hits) {
+                    pixel_color += self.shade(r, hit, max_depth, world, None);
+                }
+            }
+        } else {
+            for _sample in 0..samples_per_pixel {
+                let r = self.get_ray(i, j);
 
-            pixel_color += ray_color(&r, max_depth, world);
+                pixel_color += self.ray_color(&r, max_depth, world, None);
+            }
         }
 
         let samples_scale_vec = Vec3::splat(pixel_samples_scale);
@@ -176,6 +275,8 @@ impl Camera {
             pixel_delta_u,
             pixel_delta_v,
             defocus_angle,
+            shutter_open,
+            shutter_close,
             ..
         } = self;
 
@@ -191,8 +292,29 @@ impl Camera {
         };
 
         let ray_direction = pixel_sample - ray_origin;
+        let ray_time = f64::rnd_rng(shutter_open, shutter_close);
 
-        Ray::new(ray_origin, ray_direction)
+        Ray::with_time(ray_origin, ray_direction, ray_time)
+    }
+
+    /// Where `p` lands on the image, in pixels from the top left corner. `None` when it's behind
+    /// the camera
+    pub fn project(&self, p: Vec3) -> Option<(f64, f64)> {
+        let d = p - self.camera_center;
+        let depth = -d.dot(self.w);
+        if depth <= 0. {
+            return None;
+        }
+
+        // Scale out to the focus plane, where the pixel grid lives
+        let on_plane = d * (self.focus_dist / depth);
+        let x = on_plane.dot(self.pixel_delta_u) / self.pixel_delta_u.length_squared();
+        let y = on_plane.dot(self.pixel_delta_v) / self.pixel_delta_v.length_squared();
+
+        Some((
+            x + 0.5 * self.image_width,
+            y + 0.5 * self.image_height as f64,
+        ))
     }
 
     fn defocus_disk_sample(&self) -> Vec3 {
@@ -200,6 +322,98 @@ impl Camera {
 
         self.camera_center + (p.0 * self.defocus_disk_u) + (p.1 * self.defocus_disk_v)
     }
+
+    /// `bsdf_pdf` is the density the last bounce picked `r` with, when it also sampled the lights
+    fn ray_color(&self, r: &Ray, depth: i32, world: &dyn Hittable, bsdf_pdf: Option<f64>) -> Vec3 {
+        if depth <= 0 {
+            return Vec3::splat(0.);
+        }
+
+        // Scattered rays start just off the surface they left, by its error bounds, so anything
+        // ahead of the origin is a genuine hit
+        let hit = match world.hit(r, Interval::new(0., f64::INFINITY)) {
+            (true, rec) => rec,
+            _ => None,
+        };
+
+        self.shade(r, hit, depth, world, bsdf_pdf)
+    }
+
+    /// Color carried back along `r`, given what it hit first
+    fn shade(
+        &self,
+        r: &Ray,
+        hit: Option<HitRecord>,
+        depth: i32,
+        world: &dyn Hittable,
+        bsdf_pdf: Option<f64>,
+    ) -> Vec3 {
+        if depth <= 0 {
+            return Vec3::splat(0.);
+        }
+
+        let Some(rec) = hit else {
+            return self.background.color(r.direction);
+        };
+
+        // Lights shine at every bounce, whether or not the path carries on from them. When the
+        // last bounce also aimed a shadow ray at the lights, this is only its share
+        let emitted = rec.mat.emitted(&rec);
+        let mut color = match bsdf_pdf {
+            Some(pdf) if emitted != Vec3(0., 0., 0.) => {
+                emitted * power_heuristic(pdf, self.lights.pdf_value(r))
+            }
+            _ => emitted,
+        };
+
+        let Some(sample) = rec.mat.scatter(r, &rec) else {
+            return color;
+        };
+
+        // Aim at the lights whichever lobe the bounce took, since `eval` already scales the smooth
+        // ones by their share. Only a bounce off a smooth lobe could have found the light by
+        // itself, so only those weigh what they hit against light sampling
+        if !self.lights.is_empty() {
+            color += self.sample_lights(r, &rec, world);
+        }
+        let pdf = (!sample.delta && !self.lights.is_empty()).then_some(sample.pdf);
+
+        color + sample.attenuation * self.ray_color(&sample.ray, depth - 1, world, pdf)
+    }
+
+    /// Light reaching `rec` straight from a random point on one of the lights, weighted against
+    /// the chance of the material scattering that way by itself
+    fn sample_lights(&self, r_in: &Ray, rec: &HitRecord, world: &dyn Hittable) -> Vec3 {
+        let direction = self.lights.random_toward(rec.p, r_in.time);
+        let shadow = rec.spawn_ray(direction, r_in.time);
+
+        // Mirrors and glass have nothing to evaluate, so don't bother with the light's density
+        let f = rec.mat.eval(r_in, rec, direction);
+        if f == Vec3(0., 0., 0.) {
+            return Vec3(0., 0., 0.);
+        }
+        let light_pdf = self.lights.pdf_value(&shadow);
+        if light_pdf <= 0. {
+            return Vec3(0., 0., 0.);
+        }
+
+        // Whatever the shadow ray meets first is what lights the point from there
+        let (true, Some(blocker)) = world.hit(&shadow, Interval::new(0., f64::INFINITY)) else {
+            return Vec3(0., 0., 0.);
+        };
+        let bsdf_pdf = rec.mat.pdf(rec, direction);
+
+        f * blocker.mat.emitted(&blocker) * (power_heuristic(light_pdf, bsdf_pdf) / light_pdf)
+    }
+}
+
+/// Multiple importance sampling weight for a sample drawn with density `a`, when it could also
+/// have come from a strategy with density `b`
+fn power_heuristic(a: f64, b: f64) -> f64 {
+    if a <= 0. {
+        return 0.;
+    }
+    a * a / (a * a + b * b)
 }
 
 fn sample_square() -> Vec3 {
@@ -208,25 +422,305 @@ fn sample_square() -> Vec3 {
     v
 }
 
-fn ray_color(r: &Ray, depth: i32, world: &dyn Hittable) -> Vec3 {
-    if depth <= 0 {
-        return Vec3::splat(0.);
+/// Light arriving from beyond the scene
+#[derive(Debug, Clone, Copy)]
+pub enum Background {
+    /// Same color from every direction. Black for no light at all
+    #[allow(dead_code)]
+    Solid(Vec3),
+    /// Blends from `bottom` straight down to `top` straight up
+    Gradient { bottom: Vec3, top: Vec3 },
+}
+
+#[allow(dead_code)]
+impl Background {
+    /// Fades from white straight down to pale blue straight up
+    pub fn sky() -> Self {
+        Background::Gradient {
+            bottom: Vec3(1., 1., 1.),
+            top: Vec3(0.5, 0.7, 1.0),
+        }
     }
 
-    let (is_hit, hit_record) = world.hit(r, Interval::new(0.001, f64::INFINITY));
-    if is_hit {
-        if let Some(rec) = hit_record {
-            let (_b, attenuation, scattered) = rec.mat.scatter(r, &rec);
-            if _b {
-                return attenuation * ray_color(&scattered, depth - 1, world);
+    pub fn color(&self, direction: Vec3) -> Vec3 {
+        match *self {
+            Background::Solid(color) => color,
+            Background::Gradient { bottom, top } => {
+                let a = 0.5 * (direction.unit().1 + 1.0);
+                (1.0 - a) * bottom + a * top
             }
+        }
+    }
+}
 
-            return Vec3(0., 0., 0.);
+#[cfg(test)]
+mod camera_tests {
+    use super::*;
+    use crate::{box_shape::BoxShape, material::Material, quad::Quad, torus::Torus};
+
+    fn config() -> CameraConfig {
+        CameraConfig {
+            vfov: 90.,
+            look_from: Vec3(0., 0., 0.),
+            look_at: Vec3(0., 0., -1.),
+            v_up: Vec3(0., 1., 0.),
+            aspect_ratio: 2.,
+            image_width: 200.,
+            samples_per_pixel: 1,
+            max_depth: 1,
+            defocus_angle: 0.,
+            focus_dist: 3.,
+            shutter_open: 0.,
+            shutter_close: 0.,
+        }
+    }
+
+    #[test]
+    fn projects_onto_pixels() {
+        let cam = Camera::new(config());
+
+        let (x, y) = cam.project(Vec3(0., 0., -5.)).unwrap();
+        assert!((x - 100.).abs() < 1e-9 && (y - 50.).abs() < 1e-9);
+
+        // A 90 degree field of view reaches the top edge at 45 degrees up
+        let (x, y) = cam.project(Vec3(0., 2., -2.)).unwrap();
+        assert!((x - 100.).abs() < 1e-9 && y.abs() < 1e-9);
+
+        let (x, _) = cam.project(Vec3(1., 0., -1.)).unwrap();
+        assert!((x - 150.).abs() < 1e-9);
+
+        assert!(cam.project(Vec3(0., 0., 1.)).is_none());
+    }
+
+    #[test]
+    fn framing_fits_the_whole_box() {
+        let bbox = Aabb::from_points(Vec3(10., -2., 3.), Vec3(16., 6., 5.));
+        let cam = Camera::new(config().framing(&bbox));
+
+        assert_eq!(cam.look_at, bbox.centroid());
+        // Still looking down -z
+        assert!((unit(cam.look_from - cam.look_at) - Vec3(0., 0., 1.)).length() < 1e-12);
+
+        // Tall box in a wide image, so the height is what it has to fit
+        let mut tallest: f64 = 0.;
+        for i in 0..8 {
+            let corner = |bit: usize, lo: f64, hi: f64| if i & bit != 0 { hi } else { lo };
+            let p = Vec3(corner(1, 10., 16.), corner(2, -2., 6.), corner(4, 3., 5.));
+            let (x, y) = cam.project(p).unwrap();
+
+            assert!((0. ..=200.).contains(&x) && (0. ..=100.).contains(&y));
+            tallest = tallest.max((y - 50.).abs());
+        }
+        assert!(tallest > 30.);
+    }
+
+    /// Closed white room with the camera inside, lit only by a panel in the ceiling if `lit`
+    fn room(lit: bool) -> HittableList {
+        let white = Material::Lambertian {
+            albedo: Vec3(0.73, 0.73, 0.73).into(),
+        };
+        let mut world = HittableList::default();
+        world.add(BoxShape::new(Vec3(-3., -3., -3.), Vec3(3., 3., 3.), white).into_box());
+
+        if lit {
+            let light = Material::DiffuseLight {
+                emit: Vec3(1., 0.9, 0.8).into(),
+                intensity: 15.,
+            };
+            world.add(
+                Quad::new(
+                    Vec3(-1., 2.9, -1.),
+                    Vec3(2., 0., 0.),
+                    Vec3(0., 0., 2.),
+                    light,
+                )
+                .into_box(),
+            );
         }
+        world
     }
 
-    let unit_direction = r.direction.unit();
-    let a = 0.5 * (unit_direction.1 + 1.0);
+    #[test]
+    fn lights_shine_in_a_black_room() {
+        let mut cfg = config();
+        cfg.samples_per_pixel = 64;
+        cfg.max_depth = 8;
+        let cam = Camera::new(cfg).with_background(Background::Solid(Vec3(0., 0., 0.)));
+
+        // The far wall is only lit by what bounces off it from the panel
+        let wall = cam.render_pixel(50, 100, &room(true));
+        assert!(wall.0 > 0.05 && wall.1 > 0.05 && wall.2 > 0.05, "{wall:?}");
+
+        // Straight at the panel, its own glow comes through on top of anything bouncing
+        let up = Camera::new(CameraConfig {
+            look_at: Vec3(0., 1., 0.),
+            v_up: Vec3(0., 0., -1.),
+            ..config()
+        })
+        .with_background(Background::Solid(Vec3(0., 0., 0.)));
+        let panel = up.render_pixel(50, 100, &room(true));
+        assert!(panel.0 >= 15., "{panel:?}");
+
+        assert_eq!(cam.render_pixel(50, 100, &room(false)), Vec3(0., 0., 0.));
+    }
+
+    #[test]
+    fn background_on_a_miss() {
+        let empty = HittableList::default();
+        let glow = Vec3(0.2, 0.4, 0.6);
+        let cam = Camera::new(config()).with_background(Background::Solid(glow));
+        assert_eq!(cam.render_pixel(10, 10, &empty), glow);
+
+        let sky = Background::sky();
+        assert_eq!(sky.color(Vec3(0., 5., 0.)), Vec3(0.5, 0.7, 1.0));
+        assert_eq!(sky.color(Vec3(0., -5., 0.)), Vec3(1., 1., 1.));
+    }
+
+    fn matte() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.8, 0.8, 0.8).into(),
+        }
+    }
 
-    (1.0 - a) * Vec3(1., 1., 1.) + a * Vec3(0.5, 0.7, 1.0)
+    /// Floor under a small square light, and the camera looking down at the patch it lights
+    fn lamp(floor: Material) -> (HittableList, HittableList) {
+        let light = || {
+            let mat = Material::DiffuseLight {
+                emit: Vec3(1., 1., 1.).into(),
+                intensity: 20.,
+            };
+            Quad::new(
+                Vec3(-0.2, 2., -0.2),
+                Vec3(0.4, 0., 0.),
+                Vec3(0., 0., 0.4),
+                mat,
+            )
+            .into_box()
+        };
+
+        let mut world = HittableList::default();
+        world.add(
+            Quad::new(
+                Vec3(-10., 0., -10.),
+                Vec3(20., 0., 0.),
+                Vec3(0., 0., 20.),
+                floor,
+            )
+            .into_box(),
+        );
+        world.add(light());
+
+        let mut lights = HittableList::default();
+        lights.add(light());
+        (world, lights)
+    }
+
+    /// Mean and spread of one pixel's value over repeated renders
+    fn pixel_stats(cam: &Camera, world: &dyn Hittable, runs: usize) -> (f64, f64) {
+        let values: Vec<f64> = (0..runs)
+            .map(|_| cam.render_pixel(50, 100, world).1)
+            .collect();
+        let mean = values.iter().sum::<f64>() / runs as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (runs - 1) as f64;
+        (mean, variance.sqrt())
+    }
+
+    #[test]
+    fn light_sampling_agrees_with_plain_paths() {
+        let (world, lights) = lamp(matte());
+        let view = || {
+            Camera::new(CameraConfig {
+                look_from: Vec3(0., 1., 3.),
+                look_at: Vec3(0., 0., 0.),
+                samples_per_pixel: 16,
+                max_depth: 4,
+                ..config()
+            })
+            .with_background(Background::Solid(Vec3(0., 0., 0.)))
+        };
+
+        // Both converge on the same brightness, but plain paths only find the light by luck
+        let (plain_mean, plain_spread) = pixel_stats(&view(), &world, 2000);
+        let (nee_mean, nee_spread) = pixel_stats(&view().with_lights(lights), &world, 200);
+        assert!(
+            (plain_mean - nee_mean).abs() < 0.05,
+            "{plain_mean} {nee_mean}"
+        );
+        assert!(
+            nee_spread < 0.1 * plain_spread,
+            "{plain_spread} {nee_spread}"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Lights must be")]
+    fn lights_that_cant_be_sampled_are_rejected() {
+        let mut lights = HittableList::default();
+        lights.add(
+            Quad::new(
+                Vec3(0., 2., 0.),
+                Vec3(1., 0., 0.),
+                Vec3(0., 0., 1.),
+                matte(),
+            )
+            .into_box(),
+        );
+        lights.add(Box::new(Torus::new(Vec3(0., 2., 0.), 1., 0.2, matte())));
+        let _ = Camera::new(config()).with_lights(lights);
+    }
+
+    #[test]
+    fn hair_gets_all_its_direct_light() {
+        // Half the bounces take the mirror-like lobe, but the light still reaches the other half
+        let hair = Material::Hair {
+            albedo: Vec3(0.8, 0.8, 0.8),
+            specular: 0.5,
+            exponent: 20.,
+        };
+        let (world, lights) = lamp(hair);
+        let view = || {
+            Camera::new(CameraConfig {
+                look_from: Vec3(0., 1., 3.),
+                look_at: Vec3(0., 0., 0.),
+                samples_per_pixel: 16,
+                max_depth: 4,
+                ..config()
+            })
+            .with_background(Background::Solid(Vec3(0., 0., 0.)))
+        };
+
+        let (plain_runs, nee_runs) = (20000, 500);
+        let (plain_mean, plain_spread) = pixel_stats(&view(), &world, plain_runs);
+        let (nee_mean, nee_spread) = pixel_stats(&view().with_lights(lights), &world, nee_runs);
+        let error = (plain_spread.powi(2) / plain_runs as f64
+            + nee_spread.powi(2) / nee_runs as f64)
+            .sqrt();
+        assert!(
+            (plain_mean - nee_mean).abs() < 5. * error,
+            "{plain_mean} {nee_mean} {error}"
+        );
+    }
+
+    #[test]
+    fn packets_render_like_scalar_rays() {
+        let (world, _) = lamp(matte());
+        let view = |packets| {
+            Camera::new(CameraConfig {
+                look_from: Vec3(0., 1., 3.),
+                look_at: Vec3(0., 0., 0.),
+                samples_per_pixel: 16,
+                max_depth: 4,
+                ..config()
+            })
+            .with_packets(packets)
+        };
+
+        let runs = 300;
+        let (scalar, spread) = pixel_stats(&view(false), &world, runs);
+        let (packed, _) = pixel_stats(&view(true), &world, runs);
+        assert!(
+            (scalar - packed).abs() < 6. * spread / (runs as f64).sqrt() + 1e-3,
+            "{scalar} {packed}"
+        );
+    }
 }
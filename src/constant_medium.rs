@@ -0,0 +1,173 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    interval::{Interval, UNIVERSE},
+    material::Material,
+    random::Random,
+    ray::Ray,
+    vec3::{Vec3, unit},
+};
+
+/// Fog or smoke of uniform density filling a convex boundary.
+///
+/// A ray passing through scatters after an exponentially distributed distance. The boundary is
+/// intersected along the whole line rather than just `ray_t`, so rays that start inside the
+/// volume - like those scattered from within it - still find how much of it is ahead of them.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Material,
+}
+
+#[allow(dead_code)]
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hittable>, density: f64, albedo: Vec3) -> Self {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1. / density,
+            phase_function: Material::Isotropic { albedo },
+        }
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        // Where the line enters the boundary, which may be behind the ray's origin
+        let (true, Some(entry)) = self.boundary.hit(r, UNIVERSE) else {
+            return (false, None);
+        };
+
+        // And where it leaves, carrying on from just clear of the entry point's rounding error.
+        // Same direction, so t along it just adds on
+        let onward = entry.spawn_ray(r.direction, r.time);
+        let (true, Some(leaving)) = self.boundary.hit(&onward, Interval::new(0., f64::INFINITY))
+        else {
+            return (false, None);
+        };
+        let (enter, exit) = (entry.t, entry.t + leaving.t);
+
+        let t_enter = enter.max(ray_t.min).max(0.);
+        let t_exit = exit.min(ray_t.max);
+        if t_enter >= t_exit {
+            return (false, None);
+        }
+
+        let ray_length = r.direction.length();
+        let distance_inside_boundary = (t_exit - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * f64::rnd().ln();
+
+        if hit_distance > distance_inside_boundary {
+            return (false, None);
+        }
+
+        let t = t_enter + hit_distance / ray_length;
+        let p = r.at(t);
+
+        // There's no surface in a volume, so the normal is arbitrary - face it back at the ray so
+        // the record still reads as a front face
+        let outward_normal = -unit(r.direction);
+
+        (
+            true,
+            Some(HitRecord::new(
+                r,
+                t,
+                p,
+                outward_normal,
+                self.phase_function.clone(),
+                0.,
+                0.,
+            )),
+        )
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod constant_medium_tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    fn fog(radius: f64, density: f64) -> ConstantMedium {
+        let mat = Material::Lambertian {
+            albedo: Vec3(0., 0., 0.).into(),
+        };
+        let boundary = Sphere::new(Vec3(0., 0., 0.), radius, mat).into_box();
+        ConstantMedium::new(boundary, density, Vec3(0.8, 0.8, 0.8))
+    }
+
+    #[test]
+    fn mean_free_path() {
+        let medium = fog(1000., 2.);
+        let r = Ray::new(Vec3(0., 0., 0.), Vec3(1., 0., 0.));
+
+        let n = 20000;
+        let total: f64 = (0..n)
+            .map(|_| {
+                let (hit, rec) = medium.hit(&r, Interval::new(0., f64::INFINITY));
+                assert!(hit);
+                rec.unwrap().t
+            })
+            .sum();
+
+        // Exponential with rate = density has mean 1 / density
+        let mean = total / n as f64;
+        assert!((mean - 0.5).abs() < 0.02, "mean free path was {mean}");
+    }
+
+    #[test]
+    fn ray_starting_inside_scatters_before_exit() {
+        let medium = fog(1., 1e6);
+
+        for _ in 0..100 {
+            let r = Ray::new(Vec3(0.5, 0., 0.), Vec3(0., 1., 0.));
+            let (hit, rec) = medium.hit(&r, Interval::new(0.001, f64::INFINITY));
+            let rec = rec.unwrap();
+
+            assert!(hit);
+            assert!(rec.t >= 0.001 && rec.t < 0.01);
+            assert!(matches!(rec.mat, Material::Isotropic { .. }));
+        }
+    }
+
+    #[test]
+    fn thin_medium_and_misses() {
+        let medium = fog(1., 1e-9);
+        let through = Ray::new(Vec3(0., 0., -5.), Vec3(0., 0., 1.));
+        assert!(!medium.hit(&through, Interval::new(0.001, f64::INFINITY)).0);
+
+        let dense = fog(1., 1e6);
+        let past = Ray::new(Vec3(0., 2., -5.), Vec3(0., 0., 1.));
+        assert!(!dense.hit(&past, Interval::new(0.001, f64::INFINITY)).0);
+
+        // Volume lies entirely behind the ray
+        let away = Ray::new(Vec3(0., 0., 5.), Vec3(0., 0., 1.));
+        assert!(!dense.hit(&away, Interval::new(0.001, f64::INFINITY)).0);
+    }
+
+    #[test]
+    fn isotropic_scatters_everywhere() {
+        let medium = fog(1., 1e6);
+        let r = Ray::new(Vec3(0., 0., -5.), Vec3(0., 0., 1.));
+        let (_, rec) = medium.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+
+        let mut backwards = 0;
+        for _ in 0..1000 {
+            let sample = rec.mat.scatter(&r, &rec).unwrap();
+            assert_eq!(sample.attenuation, Vec3(0.8, 0.8, 0.8));
+            assert!((sample.ray.direction.length() - 1.).abs() < 1e-9);
+            if sample.ray.direction.2 < 0. {
+                backwards += 1;
+            }
+        }
+        assert!(backwards > 400 && backwards < 600);
+    }
+}
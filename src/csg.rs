@@ -0,0 +1,241 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    ray::Ray,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// Everything in the first object that isn't in the second
+    Difference,
+}
+
+impl CsgOp {
+    fn contains(&self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            CsgOp::Union => in_a || in_b,
+            CsgOp::Intersection => in_a && in_b,
+            CsgOp::Difference => in_a && !in_b,
+        }
+    }
+}
+
+/// Solid made by combining two closed objects.
+///
+/// Walks the crossings of both objects along the ray, tracking whether the ray is inside each,
+/// and keeps the crossings where it passes in or out of the combined solid. `front_face` on
+/// those says whether the ray is entering the result. Where the second object's surface bounds
+/// a difference, its outward normal points into the solid, so those crossings flip.
+pub struct Csg {
+    a: Box<dyn Hittable>,
+    b: Box<dyn Hittable>,
+    op: CsgOp,
+    bbox: Aabb,
+}
+
+#[allow(dead_code)]
+impl Csg {
+    pub fn new(a: Box<dyn Hittable>, b: Box<dyn Hittable>, op: CsgOp) -> Self {
+        let (box_a, box_b) = (a.bounding_box(), b.bounding_box());
+        let bbox = match op {
+            CsgOp::Union => Aabb::surrounding(&box_a, &box_b),
+            CsgOp::Intersection => Aabb::new(
+                Interval::overlap(box_a.x, box_b.x),
+                Interval::overlap(box_a.y, box_b.y),
+                Interval::overlap(box_a.z, box_b.z),
+            ),
+            CsgOp::Difference => box_a,
+        };
+
+        Csg { a, b, op, bbox }
+    }
+
+    pub fn union(a: Box<dyn Hittable>, b: Box<dyn Hittable>) -> Self {
+        Csg::new(a, b, CsgOp::Union)
+    }
+
+    pub fn intersection(a: Box<dyn Hittable>, b: Box<dyn Hittable>) -> Self {
+        Csg::new(a, b, CsgOp::Intersection)
+    }
+
+    pub fn difference(a: Box<dyn Hittable>, b: Box<dyn Hittable>) -> Self {
+        Csg::new(a, b, CsgOp::Difference)
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        match self.hit_all(r, ray_t).into_iter().next() {
+            Some(rec) => (true, Some(rec)),
+            None => (false, None),
+        }
+    }
+
+    fn hit_all(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        if !self.bbox.hit(r, ray_t) {
+            return Vec::new();
+        }
+
+        // Look past `ray_t.max` too: whether the ray starts inside an operand is only known
+        // from its first crossing, which can be beyond the end of the interval
+        let ahead = Interval::new(ray_t.min, f64::INFINITY);
+        let crossings_a = self.a.hit_all(r, ahead);
+        let crossings_b = self.b.hit_all(r, ahead);
+
+        let mut in_a = crossings_a.first().is_some_and(|rec| !rec.front_face);
+        let mut in_b = crossings_b.first().is_some_and(|rec| !rec.front_face);
+        let mut inside = self.op.contains(in_a, in_b);
+
+        let mut crossings: Vec<(HitRecord, bool)> = crossings_a
+            .into_iter()
+            .map(|rec| (rec, false))
+            .chain(crossings_b.into_iter().map(|rec| (rec, true)))
+            .collect();
+        crossings.sort_by(|x, y| x.0.t.total_cmp(&y.0.t));
+
+        let mut out = Vec::new();
+        for (mut rec, from_b) in crossings {
+            if rec.t >= ray_t.max {
+                break;
+            }
+
+            if from_b {
+                in_b = !in_b;
+            } else {
+                in_a = !in_a;
+            }
+
+            let now_inside = self.op.contains(in_a, in_b);
+            if now_inside != inside {
+                inside = now_inside;
+                // The normal already faces the ray, so flipping the outward direction is just a
+                // matter of which side the ray is on
+                rec.front_face = now_inside;
+                out.push(rec);
+            }
+        }
+
+        out
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod csg_tests {
+    use super::*;
+    use crate::{
+        box_shape::BoxShape, material::Material, quadric::Quadric, sphere::Sphere, vec3::Vec3,
+    };
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn ball(x: f64) -> Box<Sphere> {
+        Sphere::new(Vec3(x, 0., 0.), 1., grey()).into_box()
+    }
+
+    fn along_x(obj: &dyn Hittable, from: f64) -> Vec<HitRecord> {
+        let r = Ray::new(Vec3(from, 0., 0.), Vec3(1., 0., 0.));
+        obj.hit_all(&r, Interval::new(0.001, f64::INFINITY))
+    }
+
+    fn ts(crossings: &[HitRecord]) -> Vec<f64> {
+        crossings.iter().map(|rec| rec.t).collect()
+    }
+
+    #[test]
+    fn default_hit_all_finds_both_sides() {
+        let crossings = along_x(ball(0.).as_ref(), -5.);
+        assert_eq!(ts(&crossings), vec![4., 6.]);
+        assert!(crossings[0].front_face);
+        assert!(!crossings[1].front_face);
+    }
+
+    #[test]
+    fn union_drops_internal_surfaces() {
+        let pair = Csg::union(ball(-0.5), ball(0.5));
+        let crossings = along_x(&pair, -5.);
+
+        assert_eq!(ts(&crossings), vec![3.5, 6.5]);
+        assert!(crossings[0].front_face && !crossings[1].front_face);
+    }
+
+    #[test]
+    fn lens_from_two_spheres() {
+        let lens = Csg::intersection(ball(-0.5), ball(0.5));
+        let crossings = along_x(&lens, -5.);
+
+        assert_eq!(ts(&crossings), vec![4.5, 5.5]);
+        assert!(crossings[0].front_face);
+        assert_eq!(crossings[0].normal, Vec3(-1., 0., 0.));
+        assert!(!crossings[1].front_face);
+
+        let bbox = lens.bounding_box();
+        assert_eq!((bbox.x.min, bbox.x.max), (-0.5, 0.5));
+
+        // Inside one sphere but not the other
+        let r = Ray::new(Vec3(-1.2, 0., -5.), Vec3(0., 0., 1.));
+        assert!(!lens.hit(&r, Interval::new(0.001, f64::INFINITY)).0);
+
+        // Starting inside the lens, the first crossing leaves it
+        let (_, rec) = lens.hit(
+            &Ray::new(Vec3(0., 0., 0.), Vec3(1., 0., 0.)),
+            Interval::new(0.001, f64::INFINITY),
+        );
+        let rec = rec.unwrap();
+        assert_eq!(rec.t, 0.5);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn hole_drilled_through_box() {
+        let block = BoxShape::new(Vec3(-1., -1., -1.), Vec3(1., 1., 1.), grey()).into_box();
+        let drill = Quadric::cylinder(Vec3(0., -2., 0.), 0.5, 4., grey())
+            .with_caps()
+            .into_box();
+        let drilled = Csg::difference(block, drill);
+
+        // Down the hole there's nothing to hit
+        let r = Ray::new(Vec3(0., 5., 0.), Vec3(0., -1., 0.));
+        assert!(!drilled.hit(&r, Interval::new(0.001, f64::INFINITY)).0);
+
+        // Across it: into the block, out into the hole, back into the block, out the far side
+        let crossings = along_x(&drilled, -5.);
+        assert_eq!(ts(&crossings), vec![4., 4.5, 5.5, 6.]);
+        let faces: Vec<bool> = crossings.iter().map(|rec| rec.front_face).collect();
+        assert_eq!(faces, vec![true, false, true, false]);
+
+        // The hole's far wall faces back into the hole, towards the ray
+        assert_eq!(crossings[2].normal, Vec3(-1., 0., 0.));
+
+        // Only the part within the interval is reported
+        let r = Ray::new(Vec3(-5., 0., 0.), Vec3(1., 0., 0.));
+        assert_eq!(
+            ts(&drilled.hit_all(&r, Interval::new(4.2, 5.8))),
+            vec![4.5, 5.5]
+        );
+    }
+
+    #[test]
+    fn nested_operations() {
+        // A lens with a sphere bitten out of its middle leaves two thin shells along x
+        let lens = Csg::intersection(ball(-0.5), ball(0.5)).into_box();
+        let core = Sphere::new(Vec3(0., 0., 0.), 0.25, grey()).into_box();
+        let ring = Csg::difference(lens, core);
+
+        assert_eq!(ts(&along_x(&ring, -5.)), vec![4.5, 4.75, 5.25, 5.5]);
+    }
+}
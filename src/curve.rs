@@ -0,0 +1,448 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    aabb::Aabb,
+    bvh::Bvh,
+    hittable::{HitRecord, Hittable},
+    hittable_list::HittableList,
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    vec3::{Vec3, cross, dot, perpendicular, unit},
+};
+
+/// Most times a curve gets halved while looking for a hit
+const MAX_DEPTH: u32 = 10;
+
+/// How the width of a curve is filled in
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CurveKind {
+    /// Flat strip that always turns to face the ray. Cheap, and fine for strands too thin to
+    /// see the side of
+    Ribbon,
+    /// Round tube, shaded and bulging towards the ray like a cylinder
+    Tube,
+}
+
+/// Cubic Bézier curve swept out to a width, for hair, fur and grass.
+///
+/// The width goes linearly from `root_width` at the first control point to `tip_width` at the
+/// last. Surface u runs along the curve and v across it, and hits carry the direction along the
+/// curve as their tangent, for `Material::Hair`.
+pub struct Curve {
+    cp: [Vec3; 4],
+    root_width: f64,
+    tip_width: f64,
+    kind: CurveKind,
+    /// Times to halve the curve before it's flat enough to treat as a line
+    depth: u32,
+    mat: Material,
+    bbox: Aabb,
+}
+
+/// Nearest hit found so far, in the frame where the ray runs down +z from the origin
+struct LeafHit {
+    z: f64,
+    u: f64,
+    v: f64,
+}
+
+#[allow(dead_code)]
+impl Curve {
+    pub fn new(
+        cp: [Vec3; 4],
+        root_width: f64,
+        tip_width: f64,
+        kind: CurveKind,
+        mat: Material,
+    ) -> Self {
+        let half = 0.5 * root_width.max(tip_width);
+        let bbox = cp[1..]
+            .iter()
+            .fold(Aabb::from_points(cp[0], cp[0]), |acc, &p| {
+                Aabb::surrounding(&acc, &Aabb::from_points(p, p))
+            });
+        let bbox = Aabb::new(
+            bbox.x.expand(2. * half),
+            bbox.y.expand(2. * half),
+            bbox.z.expand(2. * half),
+        );
+
+        // Enough halvings for the control polygon to sit within a twentieth of the width of
+        // the curve itself
+        let bend = (0..2)
+            .map(|i| (cp[i] - 2. * cp[i + 1] + cp[i + 2]).length())
+            .fold(0., f64::max);
+        let eps = 0.05 * root_width.max(tip_width);
+        let depth = if bend > 0. && eps > 0. {
+            ((6. * bend / (8. * eps)).log2() / 2.)
+                .ceil()
+                .clamp(0., MAX_DEPTH as f64) as u32
+        } else {
+            0
+        };
+
+        Curve {
+            cp,
+            root_width,
+            tip_width,
+            kind,
+            depth,
+            mat,
+            bbox: bbox.pad_to_minimums(),
+        }
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    fn width_at(&self, u: f64) -> f64 {
+        (1. - u) * self.root_width + u * self.tip_width
+    }
+
+    /// Looks for the nearest hit of the ray running down +z from the origin on the part of the
+    /// curve from `u0` to `u1`, whose control points are `seg`
+    fn intersect(
+        &self,
+        local: &[Vec3; 4],
+        seg: [Vec3; 4],
+        (u0, u1): (f64, f64),
+        depth: u32,
+        z_range: Interval,
+        best: &mut Option<LeafHit>,
+    ) {
+        let z_max = best.as_ref().map_or(z_range.max, |b| b.z);
+        let half = 0.5 * self.width_at(u0).max(self.width_at(u1));
+
+        // A Bézier curve stays inside the box around its control points
+        let bounds = seg[1..]
+            .iter()
+            .fold(Aabb::from_points(seg[0], seg[0]), |acc, &p| {
+                Aabb::surrounding(&acc, &Aabb::from_points(p, p))
+            });
+        if !bounds.x.expand(2. * half).contains(0.)
+            || !bounds.y.expand(2. * half).contains(0.)
+            || bounds.z.max + half < z_range.min
+            || bounds.z.min - half > z_max
+        {
+            return;
+        }
+
+        if depth > 0 {
+            let (a, b) = split(seg);
+            let mid = 0.5 * (u0 + u1);
+            self.intersect(local, a, (u0, mid), depth - 1, z_range, best);
+            self.intersect(local, b, (mid, u1), depth - 1, z_range, best);
+            return;
+        }
+
+        // Flat enough to treat as the line between its end points. Only count the ray if it
+        // passes between the lines across the curve at each end, so the pieces don't overlap
+        let (c0, c3) = (seg[0], seg[3]);
+        if dot2(-c0, seg[1] - c0) < 0. || dot2(-c3, seg[2] - c3) < 0. {
+            return;
+        }
+        let along = c3 - c0;
+        let len2 = dot2(along, along);
+        if len2 == 0. {
+            return;
+        }
+
+        let w = (dot2(-c0, along) / len2).clamp(0., 1.);
+        let u = u0 + w * (u1 - u0);
+        let pc = bezier(local, u);
+        let half = 0.5 * self.width_at(u);
+        let dist2 = pc.0 * pc.0 + pc.1 * pc.1;
+        if dist2 > half * half {
+            return;
+        }
+
+        let z = match self.kind {
+            CurveKind::Ribbon => pc.2,
+            CurveKind::Tube => pc.2 - (half * half - dist2).sqrt(),
+        };
+        if !(z_range.min < z && z < z_max) {
+            return;
+        }
+
+        // Which side of the center line the ray passed
+        let side = if along.0 * -c0.1 - along.1 * -c0.0 > 0. {
+            1.
+        } else {
+            -1.
+        };
+        *best = Some(LeafHit {
+            z,
+            u,
+            v: 0.5 + side * dist2.sqrt() / (2. * half),
+        });
+    }
+}
+
+impl Hittable for Curve {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        let Some(clipped) = self.bbox.clip(r, ray_t) else {
+            return (false, None);
+        };
+
+        // Work in a frame where the ray runs down +z from the origin, so the question is just
+        // whether the curve passes close to the z axis
+        let speed = r.direction.length();
+        let dz = r.direction / speed;
+        let dx = perpendicular(dz);
+        let dy = cross(dz, dx);
+        let local = self.cp.map(|p| {
+            let rel = p - r.origin;
+            Vec3(dot(rel, dx), dot(rel, dy), dot(rel, dz))
+        });
+
+        let mut best = None;
+        let z_range = Interval::new(ray_t.min * speed, clipped.max * speed);
+        self.intersect(&local, local, (0., 1.), self.depth, z_range, &mut best);
+
+        let Some(LeafHit { z, u, v }) = best else {
+            return (false, None);
+        };
+        let t = z / speed;
+        let p = r.at(t);
+
+        let mut tangent = bezier_derivative(&self.cp, u);
+        if tangent.near_zero() {
+            tangent = self.cp[3] - self.cp[0];
+        }
+        let tangent = unit(tangent);
+
+        // Facing back up the ray, square to the curve
+        let facing = -(dz - dot(dz, tangent) * tangent);
+        let facing = if facing.near_zero() {
+            perpendicular(tangent)
+        } else {
+            unit(facing)
+        };
+
+        let normal = match self.kind {
+            CurveKind::Ribbon => facing,
+            CurveKind::Tube => {
+                // Tilt towards the side of the tube the ray hit, as a cylinder would
+                let side = cross(tangent, facing);
+                let s =
+                    (dot(p - bezier(&self.cp, u), side) / (0.5 * self.width_at(u))).clamp(-1., 1.);
+                (1. - s * s).sqrt() * facing + s * side
+            }
+        };
+
+        let mut rec = HitRecord::new(r, t, p, normal, self.mat.clone(), u, v);
+        rec.tangent = tangent;
+        // The flattened pieces can be out by a fair part of the width, so step off by all of it
+        rec.p_error = Vec3::splat(self.width_at(u));
+        (true, Some(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+fn dot2(a: Vec3, b: Vec3) -> f64 {
+    a.0 * b.0 + a.1 * b.1
+}
+
+pub fn bezier(cp: &[Vec3; 4], u: f64) -> Vec3 {
+    let s = 1. - u;
+    s * s * s * cp[0] + 3. * s * s * u * cp[1] + 3. * s * u * u * cp[2] + u * u * u * cp[3]
+}
+
+fn bezier_derivative(cp: &[Vec3; 4], u: f64) -> Vec3 {
+    let s = 1. - u;
+    3. * (s * s * (cp[1] - cp[0]) + 2. * s * u * (cp[2] - cp[1]) + u * u * (cp[3] - cp[2]))
+}
+
+/// De Casteljau split into the halves either side of u = 0.5
+fn split(cp: [Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+    let mid = |a: Vec3, b: Vec3| 0.5 * (a + b);
+    let (m01, m12, m23) = (mid(cp[0], cp[1]), mid(cp[1], cp[2]), mid(cp[2], cp[3]));
+    let (m012, m123) = (mid(m01, m12), mid(m12, m23));
+    let m = mid(m012, m123);
+
+    ([cp[0], m01, m012, m], [m, m123, m23, cp[3]])
+}
+
+/// Loads curves from a text file with one curve per line: the four control points as twelve
+/// numbers, then the root width and optionally a different tip width. Blank lines and anything
+/// after a `#` are skipped.
+#[allow(dead_code)]
+pub fn load_curves(path: impl AsRef<Path>, kind: CurveKind, mat: Material) -> io::Result<Bvh> {
+    let src = fs::read_to_string(path)?;
+
+    let mut list = HittableList::default();
+    for curve in parse_curves(&src, kind, mat)? {
+        list.add(curve.into_box());
+    }
+    Ok(Bvh::new(list))
+}
+
+pub fn parse_curves(src: &str, kind: CurveKind, mat: Material) -> io::Result<Vec<Curve>> {
+    let mut curves = Vec::new();
+
+    for (line_no, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg: &str| invalid_data(format!("curve line {}: {msg}", line_no + 1));
+
+        let numbers = line
+            .split_whitespace()
+            .map(|s| s.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| err("bad number"))?;
+        if !(13..=14).contains(&numbers.len()) {
+            return Err(err("expected four control points and one or two widths"));
+        }
+
+        let cp = [0, 1, 2, 3].map(|i| Vec3(numbers[3 * i], numbers[3 * i + 1], numbers[3 * i + 2]));
+        let root = numbers[12];
+        let tip = numbers.get(13).copied().unwrap_or(root);
+        curves.push(Curve::new(cp, root, tip, kind, mat.clone()));
+    }
+
+    Ok(curves)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod curve_tests {
+    use super::*;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    /// Arch from x = -1 to 1, peaking at y = 0.75
+    fn arch(kind: CurveKind) -> Curve {
+        Curve::new(
+            [
+                Vec3(-1., 0., 0.),
+                Vec3(-1., 1., 0.),
+                Vec3(1., 1., 0.),
+                Vec3(1., 0., 0.),
+            ],
+            0.2,
+            0.1,
+            kind,
+            grey(),
+        )
+    }
+
+    fn shoot(c: &Curve, origin: Vec3, direction: Vec3) -> Option<HitRecord> {
+        match c.hit(
+            &Ray::new(origin, direction),
+            Interval::new(0.001, f64::INFINITY),
+        ) {
+            (true, rec) => rec,
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn ribbon_faces_the_ray() {
+        let c = arch(CurveKind::Ribbon);
+
+        let rec = shoot(&c, Vec3(0., 0.75, 5.), Vec3(0., 0., -1.)).unwrap();
+        assert!((rec.t - 5.).abs() < 1e-6);
+        assert!((rec.u - 0.5).abs() < 1e-3);
+        assert!((rec.normal.2 - 1.).abs() < 1e-6);
+        assert!(rec.tangent.0 > 0.999);
+
+        // Width shrinks from 0.2 at the root to 0.1 at the tip
+        let root = bezier(&c.cp, 0.1);
+        assert!(shoot(&c, root + Vec3(0.08, 0., 5.), Vec3(0., 0., -1.)).is_some());
+        let tip = bezier(&c.cp, 0.9);
+        assert!(shoot(&c, tip + Vec3(-0.08, 0., 5.), Vec3(0., 0., -1.)).is_none());
+
+        // Inside the arch, and off the ends
+        assert!(shoot(&c, Vec3(0., 0.4, 5.), Vec3(0., 0., -1.)).is_none());
+        assert!(shoot(&c, Vec3(1.2, -0.05, 5.), Vec3(0., 0., -1.)).is_none());
+
+        // Same from behind, and from far away
+        let rec = shoot(&c, Vec3(0., 0.75, -5.), Vec3(0., 0., 1.)).unwrap();
+        assert!((rec.normal.2 + 1.).abs() < 1e-6);
+        let rec = shoot(&c, Vec3(0., 0.75, 1e5), Vec3(0., 0., -1.)).unwrap();
+        assert!((rec.t - 1e5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tube_is_round() {
+        let c = arch(CurveKind::Tube);
+        let half = 0.5 * c.width_at(0.5);
+
+        // Dead center hits the front of the tube
+        let rec = shoot(&c, Vec3(0., 0.75, 5.), Vec3(0., 0., -1.)).unwrap();
+        assert!((rec.t - (5. - half)).abs() < 1e-3);
+        assert!(rec.normal.2 > 0.999);
+
+        // Off to one side, the normal leans out that way like a cylinder's
+        let offset = 0.6 * half;
+        let rec = shoot(&c, Vec3(0., 0.75 + offset, 5.), Vec3(0., 0., -1.)).unwrap();
+        assert!((rec.normal.1 - 0.6).abs() < 0.02);
+        assert!((rec.normal.2 - 0.8).abs() < 0.02);
+        assert!(rec.v > 0.7 || rec.v < 0.3);
+    }
+
+    #[test]
+    fn hair_highlight_follows_the_strand() {
+        let shiny = Material::Hair {
+            albedo: Vec3(0.3, 0.2, 0.1),
+            specular: 1.,
+            exponent: 1e6,
+        };
+        let c = Curve::new(
+            [
+                Vec3(0., 0., 0.),
+                Vec3(0., 1., 0.),
+                Vec3(0., 2., 0.),
+                Vec3(0., 3., 0.),
+            ],
+            0.1,
+            0.1,
+            CurveKind::Tube,
+            shiny,
+        );
+
+        // Coming down at the strand, the highlight leaves on the cone going down at the same angle
+        let incoming = Vec3(0., -1., -1.);
+        let r = Ray::new(Vec3(0., 2.5, 1.), incoming);
+        let (_, rec) = c.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert!(rec.tangent.1 > 0.999);
+
+        for _ in 0..20 {
+            let sample = rec.mat.scatter(&r, &rec).unwrap();
+            assert!(sample.delta);
+            assert_eq!(sample.attenuation, Vec3(1., 1., 1.));
+            assert!((unit(sample.ray.direction).1 - unit(incoming).1).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn parses_curve_files() {
+        let src = "
+            # two strands
+            0 0 0  0 1 0  0 2 0  0 3 0  0.1
+            1 0 0  1 1 0  1 2 0  1 3 0  0.1 0.02
+        ";
+        let curves = parse_curves(src, CurveKind::Tube, grey()).unwrap();
+        assert_eq!(curves.len(), 2);
+        assert_eq!(curves[1].tip_width, 0.02);
+
+        assert!(parse_curves("0 0 0  1 1 1", CurveKind::Tube, grey()).is_err());
+        assert!(parse_curves("0 0 0 0 1 0 0 2 0 0 3 x 0.1", CurveKind::Tube, grey()).is_err());
+    }
+}
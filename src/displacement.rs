@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+
+use crate::{
+    camera::Camera,
+    mesh::{MeshData, MeshFace, TriangleMesh},
+    vec3::{Vec3, cross, unit},
+};
+
+/// Backstop on recursion for degenerate slivers that never get short enough to stop splitting
+const MAX_DEPTH: u32 = 32;
+
+/// Edge between two vertices, smaller index first so both directions map to the same key
+type EdgeKey = (usize, usize);
+
+fn edge_key(a: usize, b: usize) -> EdgeKey {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+/// Scalar height at a surface point, given its texture coordinates and position
+type HeightFn = dyn Fn(f64, f64, Vec3) -> f64 + Send + Sync;
+
+/// Moves the surface of a triangle mesh in or out along its normals, for detail like brickwork and
+/// rock that has to show up in silhouette.
+///
+/// The mesh is first tessellated until every edge covers no more than a few pixels as seen from
+/// the camera, so close-up geometry gets fine triangles and distant geometry stays coarse.
+#[allow(dead_code)]
+pub struct Displacement {
+    height: Box<HeightFn>,
+    scale: f64,
+    max_edge_pixels: f64,
+    max_level: u32,
+}
+
+#[allow(dead_code)]
+impl Displacement {
+    /// `height` is sampled at each vertex, and the vertex pushed out by `scale` times that
+    pub fn new(height: impl Fn(f64, f64, Vec3) -> f64 + Send + Sync + 'static, scale: f64) -> Self {
+        Displacement {
+            height: Box::new(height),
+            scale,
+            max_edge_pixels: 2.,
+            max_level: 8,
+        }
+    }
+
+    /// Longest an edge may be on screen before it gets split
+    pub fn with_edge_length(mut self, pixels: f64) -> Self {
+        self.max_edge_pixels = pixels;
+        self
+    }
+
+    /// How many times the longest edge of the mesh may be halved, however close the camera is
+    pub fn with_max_level(mut self, levels: u32) -> Self {
+        self.max_level = levels;
+        self
+    }
+
+    /// Tessellate `data` for the view from `cam`, then displace it
+    pub fn apply(&self, data: &MeshData, cam: &Camera) -> TriangleMesh {
+        TriangleMesh::new(self.displace(data, cam))
+    }
+
+    /// Same as `apply`, stopping short of building the triangle BVH
+    pub fn displace(&self, data: &MeshData, cam: &Camera) -> MeshData {
+        let mut t = Tessellator::new(self, data, cam);
+        for face in &data.faces {
+            t.split(face.v, face.material, 0);
+        }
+
+        let Tessellator {
+            mut positions,
+            normals,
+            uvs,
+            colors,
+            faces,
+            ..
+        } = t;
+
+        // Every copy of a vertex moves the same way, which keeps the surface closed
+        for (i, p) in positions.iter_mut().enumerate() {
+            let (u, v) = uvs.get(i).copied().unwrap_or((0., 0.));
+            *p += normals[i] * (self.scale * (self.height)(u, v, *p));
+        }
+
+        let normals = vertex_normals(&positions, &faces);
+        let has_uvs = !uvs.is_empty();
+
+        MeshData {
+            faces: faces
+                .into_iter()
+                .map(|(v, material)| MeshFace {
+                    v,
+                    n: Some(v),
+                    uv: has_uvs.then_some(v),
+                    material,
+                })
+                .collect(),
+            positions,
+            normals,
+            uvs,
+            colors,
+            materials: data.materials.clone(),
+        }
+    }
+}
+
+/// Working state while splitting triangles, with one normal and uv per position
+struct Tessellator<'a> {
+    cam: &'a Camera,
+    max_edge_pixels: f64,
+    /// Edges shorter than this are never split, which is what `max_level` comes down to
+    min_edge: f64,
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    colors: Vec<Vec3>,
+    /// The vertex already made in the middle of each split edge, so the triangles on either side
+    /// share it rather than leave a crack
+    midpoints: HashMap<EdgeKey, usize>,
+    faces: Vec<([usize; 3], usize)>,
+}
+
+impl<'a> Tessellator<'a> {
+    fn new(disp: &Displacement, data: &MeshData, cam: &'a Camera) -> Self {
+        let count = data.positions.len();
+
+        // Corners of a vertex can carry different normals and uvs, but it can only move one way
+        // without tearing, so they're merged per position
+        let mut normals = vec![Vec3(0., 0., 0.); count];
+        let mut uvs = vec![None; count];
+        for face in &data.faces {
+            let [p0, p1, p2] = face.v.map(|i| data.positions[i]);
+            let face_normal = cross(p1 - p0, p2 - p0);
+
+            for k in 0..3 {
+                let i = face.v[k];
+                normals[i] += match face.n {
+                    Some(n) if !data.normals.is_empty() => unit(data.normals[n[k]]),
+                    _ => face_normal,
+                };
+                if let Some(uv) = face.uv {
+                    uvs[i].get_or_insert(data.uvs[uv[k]]);
+                }
+            }
+        }
+
+        let has_uvs = data.faces.iter().any(|f| f.uv.is_some());
+        let longest = data
+            .faces
+            .iter()
+            .flat_map(|f| (0..3).map(move |k| (f.v[k], f.v[(k + 1) % 3])))
+            .map(|(a, b)| (data.positions[a] - data.positions[b]).length())
+            .fold(0., f64::max);
+
+        Tessellator {
+            cam,
+            max_edge_pixels: disp.max_edge_pixels,
+            min_edge: longest / 2f64.powi(disp.max_level as i32),
+            positions: data.positions.clone(),
+            normals: normals.into_iter().map(unit).collect(),
+            uvs: if has_uvs {
+                uvs.into_iter().map(|uv| uv.unwrap_or((0., 0.))).collect()
+            } else {
+                Vec::new()
+            },
+            colors: data.colors.clone(),
+            midpoints: HashMap::new(),
+            faces: Vec::new(),
+        }
+    }
+
+    /// Whether an edge is still too long on screen. Only looks at the edge itself, so the two
+    /// triangles sharing it always agree
+    fn too_long(&self, a: usize, b: usize) -> bool {
+        let (pa, pb) = (self.positions[a], self.positions[b]);
+        if (pa - pb).length() <= self.min_edge {
+            return false;
+        }
+
+        match (self.cam.project(pa), self.cam.project(pb)) {
+            (Some((xa, ya)), Some((xb, yb))) => (xa - xb).hypot(ya - yb) > self.max_edge_pixels,
+            // Crossing behind the camera, so nothing to measure it by
+            _ => false,
+        }
+    }
+
+    fn midpoint(&mut self, a: usize, b: usize) -> usize {
+        if let Some(&m) = self.midpoints.get(&edge_key(a, b)) {
+            return m;
+        }
+
+        let m = self.positions.len();
+        self.positions
+            .push(0.5 * (self.positions[a] + self.positions[b]));
+
+        let n = self.normals[a] + self.normals[b];
+        self.normals.push(if n.length_squared() > 0. {
+            unit(n)
+        } else {
+            self.normals[a]
+        });
+
+        if !self.uvs.is_empty() {
+            let ((u0, v0), (u1, v1)) = (self.uvs[a], self.uvs[b]);
+            self.uvs.push((0.5 * (u0 + u1), 0.5 * (v0 + v1)));
+        }
+        if !self.colors.is_empty() {
+            self.colors.push(0.5 * (self.colors[a] + self.colors[b]));
+        }
+
+        self.midpoints.insert(edge_key(a, b), m);
+        m
+    }
+
+    fn split(&mut self, v: [usize; 3], material: usize, depth: u32) {
+        let long = [0, 1, 2].map(|k| depth < MAX_DEPTH && self.too_long(v[k], v[(k + 1) % 3]));
+
+        match long.iter().filter(|&&l| l).count() {
+            0 => self.faces.push((v, material)),
+            1 => {
+                // Rotate so the long edge runs a -> b
+                let k = long.iter().position(|&l| l).unwrap();
+                let [a, b, c] = [v[k], v[(k + 1) % 3], v[(k + 2) % 3]];
+                let m = self.midpoint(a, b);
+
+                self.split([a, m, c], material, depth + 1);
+                self.split([m, b, c], material, depth + 1);
+            }
+            2 => {
+                // Rotate so the short edge runs c -> a
+                let k = (long.iter().position(|&l| !l).unwrap() + 1) % 3;
+                let [a, b, c] = [v[k], v[(k + 1) % 3], v[(k + 2) % 3]];
+                let (ab, bc) = (self.midpoint(a, b), self.midpoint(b, c));
+
+                self.split([ab, b, bc], material, depth + 1);
+                self.split([a, ab, bc], material, depth + 1);
+                self.split([a, bc, c], material, depth + 1);
+            }
+            _ => {
+                let [a, b, c] = v;
+                let (ab, bc, ca) = (
+                    self.midpoint(a, b),
+                    self.midpoint(b, c),
+                    self.midpoint(c, a),
+                );
+
+                self.split([a, ab, ca], material, depth + 1);
+                self.split([ab, b, bc], material, depth + 1);
+                self.split([ca, bc, c], material, depth + 1);
+                self.split([ab, bc, ca], material, depth + 1);
+            }
+        }
+    }
+}
+
+/// Smooth normals for the displaced surface, weighted by triangle area
+fn vertex_normals(positions: &[Vec3], faces: &[([usize; 3], usize)]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3(0., 0., 0.); positions.len()];
+    for (v, _) in faces {
+        let [p0, p1, p2] = v.map(|i| positions[i]);
+        let n = cross(p1 - p0, p2 - p0);
+        for &i in v {
+            normals[i] += n;
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.length_squared() > 0. {
+                unit(n)
+            } else {
+                Vec3(0., 1., 0.)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod displacement_tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        camera::CameraConfig, hittable::Hittable, interval::Interval, material::Material, ray::Ray,
+    };
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn camera(look_from: Vec3, look_at: Vec3) -> Camera {
+        Camera::new(CameraConfig {
+            vfov: 40.,
+            look_from,
+            look_at,
+            v_up: Vec3(0., 1., 0.),
+            aspect_ratio: 1.,
+            image_width: 100.,
+            samples_per_pixel: 1,
+            max_depth: 1,
+            defocus_angle: 0.,
+            focus_dist: 10.,
+            shutter_open: 0.,
+            shutter_close: 0.,
+        })
+    }
+
+    fn mesh(positions: Vec<Vec3>, tris: &[[usize; 3]]) -> MeshData {
+        MeshData {
+            positions,
+            faces: tris
+                .iter()
+                .map(|&v| MeshFace {
+                    v,
+                    n: None,
+                    uv: None,
+                    material: 0,
+                })
+                .collect(),
+            materials: vec![grey()],
+            ..Default::default()
+        }
+    }
+
+    /// Unit square in the xz plane, facing up
+    fn floor() -> MeshData {
+        mesh(
+            vec![
+                Vec3(0., 0., 0.),
+                Vec3(0., 0., 1.),
+                Vec3(1., 0., 1.),
+                Vec3(1., 0., 0.),
+            ],
+            &[[0, 1, 2], [0, 2, 3]],
+        )
+    }
+
+    fn octahedron() -> MeshData {
+        mesh(
+            vec![
+                Vec3(1., 0., 0.),
+                Vec3(-1., 0., 0.),
+                Vec3(0., 1., 0.),
+                Vec3(0., -1., 0.),
+                Vec3(0., 0., 1.),
+                Vec3(0., 0., -1.),
+            ],
+            &[
+                [0, 2, 4],
+                [4, 2, 1],
+                [1, 2, 5],
+                [5, 2, 0],
+                [4, 3, 0],
+                [1, 3, 4],
+                [5, 3, 1],
+                [0, 3, 5],
+            ],
+        )
+    }
+
+    #[test]
+    fn finer_up_close() {
+        let flat = Displacement::new(|_, _, _| 0., 1.).with_edge_length(4.);
+
+        let near = flat.displace(&floor(), &camera(Vec3(0.5, 2., 0.5), Vec3(0.5, 0., 0.4)));
+        let far = flat.displace(&floor(), &camera(Vec3(0.5, 200., 0.5), Vec3(0.5, 0., 0.4)));
+
+        assert_eq!(far.faces.len(), 2);
+        assert!(near.faces.len() > 100);
+
+        // Nothing moved, and the normals still point up
+        assert!(near.positions.iter().all(|p| p.1 == 0.));
+        assert!(near.normals.iter().all(|n| (n.1 - 1.).abs() < 1e-9));
+    }
+
+    #[test]
+    fn max_level_caps_splitting() {
+        let cam = camera(Vec3(0.5, 0.1, 0.5), Vec3(0.5, 0., 0.4));
+        let capped = Displacement::new(|_, _, _| 0., 1.)
+            .with_max_level(2)
+            .displace(&floor(), &cam);
+
+        // Two levels of halving the diagonal is at most 4 x 4 cells of 2 triangles each
+        assert!(capped.faces.len() > 2 && capped.faces.len() <= 2 * 4 * 4 * 2);
+    }
+
+    #[test]
+    fn uneven_tessellation_stays_closed() {
+        // Close to one corner, so the triangles around it split far more than the far side
+        let cam = camera(Vec3(3., 0.5, 0.), Vec3(0., 0., 0.));
+        let bumpy = Displacement::new(|_, _, p| (7. * p.0).sin() * (5. * p.2).cos(), 0.1);
+        let data = bumpy.displace(&octahedron(), &cam);
+
+        assert!(data.faces.len() > 100);
+
+        let mut edges: HashMap<EdgeKey, usize> = HashMap::new();
+        for face in &data.faces {
+            for k in 0..3 {
+                *edges
+                    .entry(edge_key(face.v[k], face.v[(k + 1) % 3]))
+                    .or_default() += 1;
+            }
+        }
+        assert!(edges.values().all(|&n| n == 2));
+    }
+
+    #[test]
+    fn displaced_silhouette() {
+        let cam = camera(Vec3(0.5, 3., 3.), Vec3(0.5, 0., 0.5));
+        let raised = Displacement::new(|_, _, p| if p.0 > 0.5 { 1. } else { 0. }, 0.3)
+            .with_edge_length(1.)
+            .apply(&floor(), &cam);
+
+        // Skims over the flat half, then runs into the side of the raised half
+        let r = Ray::new(Vec3(-1., 0.15, 0.5), Vec3(1., 0., 0.));
+        let (hit, rec) = raised.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.filter(|_| hit).unwrap();
+
+        assert!(rec.p.0 > 0.45 && rec.p.0 < 0.55);
+        assert!(rec.normal.0 < 0.);
+
+        // Straight down onto the raised half
+        let r = Ray::new(Vec3(0.8, 2., 0.5), Vec3(0., -1., 0.));
+        let (_, rec) = raised.hit(&r, Interval::new(0.001, f64::INFINITY));
+        assert!((rec.unwrap().p.1 - 0.3).abs() < 1e-9);
+    }
+}
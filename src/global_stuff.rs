@@ -1,5 +1,9 @@
-pub fn degrees_to_radians(degrees: f64) -> f64 {
-    return degrees * std::f64::consts::PI / 180.0;
+/// Bound on the relative rounding error built up over `n` floating-point operations, as in PBRT
+pub fn gamma(n: u32) -> f64 {
+    let eps = f64::EPSILON * 0.5;
+    n as f64 * eps / (1. - n as f64 * eps)
 }
 
-const INFINITY: f64 = std::f64::INFINITY;
+pub fn degrees_to_radians(degrees: f64) -> f64 {
+    degrees * std::f64::consts::PI / 180.0
+}
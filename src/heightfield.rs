@@ -0,0 +1,507 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    aabb::Aabb,
+    global_stuff::gamma,
+    hittable::{HitRecord, Hittable, set_face_normal},
+    interval::Interval,
+    material::Material,
+    mesh::intersect_triangle,
+    ray::Ray,
+    vec3::{Vec3, cross, unit},
+};
+
+/// Grid of heights, `width` samples along x by `depth` along z, stored row by row along x
+#[derive(Debug, Clone)]
+pub struct HeightMap {
+    width: usize,
+    depth: usize,
+    heights: Vec<f64>,
+}
+
+#[allow(dead_code)]
+impl HeightMap {
+    /// Panics unless there are at least 2 x 2 samples and exactly `width * depth` of them
+    pub fn new(width: usize, depth: usize, heights: Vec<f64>) -> Self {
+        assert!(
+            width >= 2 && depth >= 2,
+            "Height map needs at least 2 x 2 samples"
+        );
+        assert_eq!(heights.len(), width * depth, "Height map size mismatch");
+        HeightMap {
+            width,
+            depth,
+            heights,
+        }
+    }
+
+    /// Samples `f(x, z)` with both coordinates running from 0 to 1 across the map
+    pub fn from_fn(width: usize, depth: usize, f: impl Fn(f64, f64) -> f64) -> Self {
+        let mut heights = Vec::with_capacity(width * depth);
+        for j in 0..depth {
+            for i in 0..width {
+                let x = i as f64 / (width - 1) as f64;
+                let z = j as f64 / (depth - 1) as f64;
+                heights.push(f(x, z));
+            }
+        }
+        HeightMap::new(width, depth, heights)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn at(&self, i: usize, j: usize) -> f64 {
+        self.heights[j * self.width + i]
+    }
+}
+
+/// Reads a grayscale PGM (binary or ASCII) or PNG as heights from 0 to 1. 16-bit images keep
+/// their full precision; other images are converted to grayscale first.
+#[allow(dead_code)]
+pub fn load_heightmap(path: impl AsRef<Path>) -> io::Result<HeightMap> {
+    parse_heightmap(&fs::read(path)?)
+}
+
+pub fn parse_heightmap(bytes: &[u8]) -> io::Result<HeightMap> {
+    let img = image::load_from_memory(bytes)
+        .map_err(|e| invalid_data(format!("Couldn't decode height map: {e}")))?
+        .into_luma16();
+
+    let (width, depth) = (img.width() as usize, img.height() as usize);
+    if width < 2 || depth < 2 {
+        return Err(invalid_data(format!(
+            "Height map is {width} x {depth}, needs at least 2 x 2"
+        )));
+    }
+
+    let heights = img.pixels().map(|p| p.0[0] as f64 / 65535.).collect();
+    Ok(HeightMap::new(width, depth, heights))
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Material for the parts of the terrain no higher than `max_height` (in world units) and no
+/// steeper than `max_slope` (in degrees from flat)
+#[derive(Clone)]
+pub struct TerrainRule {
+    pub max_height: f64,
+    pub max_slope: f64,
+    pub mat: Material,
+}
+
+/// Terrain surface over a height map, split into two triangles per grid cell.
+///
+/// Rays walk the grid cell by cell with a 2D DDA, and only test a cell's triangles when the ray
+/// passes within that cell's height range. Normals are blended from per-sample normals taken
+/// from the slope of the map.
+pub struct Heightfield {
+    map: HeightMap,
+    corner: Vec3,
+    size: Vec3,
+    /// Lowest and highest world y of each cell's corners
+    cell_range: Vec<(f64, f64)>,
+    normals: Vec<Vec3>,
+    mat: Material,
+    rules: Vec<TerrainRule>,
+    bbox: Aabb,
+}
+
+#[allow(dead_code)]
+impl Heightfield {
+    /// Terrain covering `size.x` by `size.z` from `corner`, with map heights scaled by `size.y`
+    /// and raised by `corner.y`
+    pub fn new(map: HeightMap, corner: Vec3, size: Vec3, mat: Material) -> Self {
+        let (w, d) = (map.width, map.depth);
+        let y = |i: usize, j: usize| corner.1 + size.1 * map.at(i, j);
+
+        let mut cell_range = Vec::with_capacity((w - 1) * (d - 1));
+        for j in 0..d - 1 {
+            for i in 0..w - 1 {
+                let ys = [y(i, j), y(i + 1, j), y(i, j + 1), y(i + 1, j + 1)];
+                let lo = ys.iter().copied().fold(f64::INFINITY, f64::min);
+                let hi = ys.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                cell_range.push((lo, hi));
+            }
+        }
+
+        // Central differences, one-sided along the edges
+        let (dx, dz) = (size.0 / (w - 1) as f64, size.2 / (d - 1) as f64);
+        let mut normals = Vec::with_capacity(w * d);
+        for j in 0..d {
+            for i in 0..w {
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(w - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(d - 1));
+                let slope_x = (y(i1, j) - y(i0, j)) / ((i1 - i0) as f64 * dx);
+                let slope_z = (y(i, j1) - y(i, j0)) / ((j1 - j0) as f64 * dz);
+                normals.push(unit(Vec3(-slope_x, 1., -slope_z)));
+            }
+        }
+
+        let lo = cell_range.iter().map(|c| c.0).fold(f64::INFINITY, f64::min);
+        let hi = cell_range
+            .iter()
+            .map(|c| c.1)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let bbox = Aabb::from_points(
+            Vec3(corner.0, lo, corner.2),
+            Vec3(corner.0 + size.0, hi, corner.2 + size.2),
+        )
+        .pad_to_minimums();
+
+        Heightfield {
+            map,
+            corner,
+            size,
+            cell_range,
+            normals,
+            mat,
+            rules: Vec::new(),
+            bbox,
+        }
+    }
+
+    /// Vary the material with height and slope. The first rule that matches wins, and places
+    /// no rule covers fall back to the material passed to `new`
+    pub fn with_rules(mut self, rules: Vec<TerrainRule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (
+            self.size.0 / (self.map.width - 1) as f64,
+            self.size.2 / (self.map.depth - 1) as f64,
+        )
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        let (dx, dz) = self.cell_size();
+        Vec3(
+            self.corner.0 + i as f64 * dx,
+            self.corner.1 + self.size.1 * self.map.at(i, j),
+            self.corner.2 + j as f64 * dz,
+        )
+    }
+
+    fn normal(&self, i: usize, j: usize) -> Vec3 {
+        self.normals[j * self.map.width + i]
+    }
+
+    /// Nearest hit on the two triangles of cell (i, j)
+    fn hit_cell(&self, r: &Ray, i: usize, j: usize, ray_t: Interval) -> Option<CellHit> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut best: Option<CellHit> = None;
+
+        for tri in [[0, 1, 2], [0, 2, 3]] {
+            let idx = tri.map(|k| corners[k]);
+            let [p0, p1, p2] = idx.map(|(a, b)| self.vertex(a, b));
+
+            let limit = best
+                .as_ref()
+                .map_or(ray_t, |b| Interval::new(ray_t.min, b.t));
+            if let Some((w, t)) = intersect_triangle(r, p0, p1, p2, limit) {
+                let [n0, n1, n2] = idx.map(|(a, b)| self.normal(a, b));
+                let shading = unit(w[0] * n0 + w[1] * n1 + w[2] * n2);
+                // Winding is clockwise seen from above, so this points up
+                let geometric = unit(cross(p2 - p0, p1 - p0));
+
+                let along = [w[0] * p0, w[1] * p1, w[2] * p2];
+                best = Some(CellHit {
+                    t,
+                    p: along[0] + along[1] + along[2],
+                    p_error: gamma(7) * (along[0].abs() + along[1].abs() + along[2].abs()),
+                    shading,
+                    geometric,
+                });
+            }
+        }
+
+        best
+    }
+
+    fn material_at(&self, height: f64, normal: Vec3) -> Material {
+        let slope = normal.1.clamp(-1., 1.).acos().to_degrees();
+        self.rules
+            .iter()
+            .find(|rule| height <= rule.max_height && slope <= rule.max_slope)
+            .map_or(&self.mat, |rule| &rule.mat)
+            .clone()
+    }
+}
+
+/// Where a ray met one of a cell's triangles, with the point rebuilt from its barycentrics
+struct CellHit {
+    t: f64,
+    p: Vec3,
+    p_error: Vec3,
+    shading: Vec3,
+    geometric: Vec3,
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        let Some(span) = self.bbox.clip(r, ray_t) else {
+            return (false, None);
+        };
+
+        let (dx, dz) = self.cell_size();
+        let (cells_x, cells_z) = (self.map.width - 1, self.map.depth - 1);
+
+        // Grid coordinates where the ray enters the box, and how fast they change with t
+        let start = r.at(span.min);
+        let gx = (start.0 - self.corner.0) / dx;
+        let gz = (start.2 - self.corner.2) / dz;
+        let (vx, vz) = (r.direction.0 / dx, r.direction.2 / dz);
+
+        let mut i = (gx.floor().max(0.) as usize).min(cells_x - 1);
+        let mut j = (gz.floor().max(0.) as usize).min(cells_z - 1);
+
+        // t at which the ray crosses the next cell boundary along each axis
+        let next_boundary = |g: f64, cell: usize, v: f64| {
+            if v > 0. {
+                span.min + ((cell + 1) as f64 - g) / v
+            } else if v < 0. {
+                span.min + (cell as f64 - g) / v
+            } else {
+                f64::INFINITY
+            }
+        };
+        let mut t_next_x = next_boundary(gx, i, vx);
+        let mut t_next_z = next_boundary(gz, j, vz);
+        let (t_step_x, t_step_z) = ((1. / vx).abs(), (1. / vz).abs());
+
+        let mut t_enter = span.min;
+        let found = loop {
+            let t_exit = t_next_x.min(t_next_z).min(span.max);
+
+            // Skip the triangles unless the ray's height overlaps the cell's on the way through
+            let (y0, y1) = (r.at(t_enter).1, r.at(t_exit).1);
+            let (lo, hi) = self.cell_range[j * cells_x + i];
+            let tolerance = 1e-9 * (1. + lo.abs().max(hi.abs()));
+            if y0.min(y1) <= hi + tolerance
+                && y0.max(y1) >= lo - tolerance
+                && let Some(hit) = self.hit_cell(r, i, j, ray_t)
+            {
+                break Some(hit);
+            }
+
+            if t_exit >= span.max {
+                break None;
+            }
+
+            if t_next_x < t_next_z {
+                t_enter = t_next_x;
+                t_next_x += t_step_x;
+                match i.checked_add_signed(vx.signum() as isize) {
+                    Some(next) if next < cells_x => i = next,
+                    _ => break None,
+                }
+            } else {
+                t_enter = t_next_z;
+                t_next_z += t_step_z;
+                match j.checked_add_signed(vz.signum() as isize) {
+                    Some(next) if next < cells_z => j = next,
+                    _ => break None,
+                }
+            }
+        };
+
+        let Some(CellHit {
+            t,
+            p,
+            p_error,
+            shading,
+            geometric,
+        }) = found
+        else {
+            return (false, None);
+        };
+
+        // Keep the blended normal on the same side as the actual surface, so front_face agrees
+        // with the triangle the ray really hit
+        let outward_normal = if shading.dot(geometric) > 0. {
+            shading
+        } else {
+            geometric
+        };
+        let u = (p.0 - self.corner.0) / self.size.0;
+        let v = (p.2 - self.corner.2) / self.size.2;
+
+        let mat = self.material_at(p.1, outward_normal);
+        let mut rec = HitRecord::new(r, t, p, outward_normal, mat, u, v);
+        rec.p_error = p_error;
+        rec.geometric_normal = set_face_normal(r, geometric).0;
+        (true, Some(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod heightfield_tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{random::Random, texture::Texture};
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn hills() -> Heightfield {
+        let map = HeightMap::from_fn(33, 17, |x, z| {
+            0.5 + 0.25 * (8. * x).sin() * (5. * z).cos() + 0.1 * (23. * x * z).sin()
+        });
+        Heightfield::new(map, Vec3(-4., -1., -2.), Vec3(8., 2., 4.), grey())
+    }
+
+    #[test]
+    fn reads_16_bit_pgm_and_png() {
+        let mut pgm = b"P5\n3 2\n65535\n".to_vec();
+        for v in [0u16, 1, 256, 32768, 65534, 65535] {
+            pgm.extend(v.to_be_bytes());
+        }
+        let map = parse_heightmap(&pgm).unwrap();
+        assert_eq!((map.width(), map.depth()), (3, 2));
+        assert_eq!(map.at(1, 0), 1. / 65535.);
+        assert_eq!(map.at(2, 1), 1.);
+
+        let ascii = parse_heightmap(b"P2\n2 2\n65535\n0 100\n200 65535\n").unwrap();
+        assert_eq!(ascii.at(0, 1), 200. / 65535.);
+
+        let img = image::ImageBuffer::<image::Luma<u16>, _>::from_fn(4, 3, |x, y| {
+            image::Luma([(x * 1000 + y) as u16])
+        });
+        let mut png = Cursor::new(Vec::new());
+        img.write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let map = parse_heightmap(png.get_ref()).unwrap();
+        assert_eq!(map.at(3, 2), 3002. / 65535.);
+
+        assert!(parse_heightmap(b"not an image").is_err());
+        let tiny = parse_heightmap(b"P2\n1 1\n255\n7\n");
+        assert_eq!(tiny.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn sloped_plane() {
+        // Rises one unit for every two along x
+        let map = HeightMap::from_fn(9, 9, |x, _| x);
+        let field = Heightfield::new(map, Vec3(0., 0., 0.), Vec3(2., 1., 2.), grey());
+
+        let r = Ray::new(Vec3(1.5, 10., 0.5), Vec3(0., -1., 0.));
+        let (_, rec) = field.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert!((rec.t - 9.25).abs() < 1e-12);
+        assert!((rec.normal - unit(Vec3(-0.5, 1., 0.))).length() < 1e-12);
+        assert!((rec.u - 0.75).abs() < 1e-12 && (rec.v - 0.25).abs() < 1e-12);
+
+        // A shallow ray along the slope's rise, starting off the side of the map
+        let r = Ray::new(Vec3(-1., 0.2, 1.), Vec3(1., 0., 0.));
+        let (_, rec) = field.hit(&r, Interval::new(0.001, f64::INFINITY));
+        assert!((rec.unwrap().t - 1.4).abs() < 1e-12);
+
+        // From underneath
+        let r = Ray::new(Vec3(1., -5., 1.), Vec3(0., 1., 0.));
+        let (_, rec) = field.hit(&r, Interval::new(0.001, f64::INFINITY));
+        assert!(!rec.unwrap().front_face);
+    }
+
+    #[test]
+    fn dda_agrees_with_testing_every_triangle() {
+        let field = hills();
+        let (w, d) = (field.map.width(), field.map.depth());
+
+        for _ in 0..500 {
+            let origin = Vec3::rnd_rng(-6., 6.);
+            let r = Ray::new(origin, Vec3::rnd_rng(-1., 1.) * 3. - origin);
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+
+            let mut brute: Option<f64> = None;
+            for j in 0..d - 1 {
+                for i in 0..w - 1 {
+                    let limit = brute.map_or(ray_t, |t| Interval::new(ray_t.min, t));
+                    if let Some(CellHit { t, .. }) = field.hit_cell(&r, i, j, limit) {
+                        brute = Some(t);
+                    }
+                }
+            }
+
+            let (hit, rec) = field.hit(&r, ray_t);
+            assert_eq!(hit, brute.is_some());
+            if let (Some(rec), Some(t)) = (rec, brute) {
+                assert!((rec.t - t).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_normals() {
+        let field = hills();
+
+        // Across one cell the normal changes gradually rather than snapping between facets
+        let r = |x: f64| Ray::new(Vec3(x, 5., 0.1), Vec3(0., -1., 0.));
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let mut previous = field.hit(&r(0.), ray_t).1.unwrap().normal;
+        for k in 1..=50 {
+            let n = field.hit(&r(k as f64 * 0.005), ray_t).1.unwrap().normal;
+            assert!((n - previous).length() < 0.02);
+            previous = n;
+        }
+    }
+
+    #[test]
+    fn material_rules() {
+        let colored = |c: f64| Material::Lambertian {
+            albedo: Vec3(c, c, c).into(),
+        };
+        let map = HeightMap::from_fn(17, 17, |x, _| if x < 0.5 { 0. } else { 4. * (x - 0.5) });
+        let field = Heightfield::new(map, Vec3(0., 0., 0.), Vec3(4., 1., 4.), colored(0.))
+            .with_rules(vec![
+                TerrainRule {
+                    max_height: 0.1,
+                    max_slope: 10.,
+                    mat: colored(0.1),
+                },
+                TerrainRule {
+                    max_height: f64::INFINITY,
+                    max_slope: 30.,
+                    mat: colored(0.2),
+                },
+            ]);
+
+        let albedo = |x: f64| {
+            let r = Ray::new(Vec3(x, 5., 2.), Vec3(0., -1., 0.));
+            match field
+                .hit(&r, Interval::new(0.001, f64::INFINITY))
+                .1
+                .unwrap()
+                .mat
+            {
+                Material::Lambertian {
+                    albedo: Texture::Solid(albedo),
+                } => albedo.0,
+                _ => unreachable!(),
+            }
+        };
+
+        // Flat valley floor, then a 45 degree slope too steep for either rule
+        assert_eq!(albedo(0.5), 0.1);
+        assert_eq!(albedo(3.5), 0.);
+    }
+}
@@ -1,20 +1,156 @@
 use crate::{
+    aabb::Aabb,
+    global_stuff::gamma,
     interval::Interval,
     material::Material,
+    packet::{Mask, PacketHits, RayPacket, active},
     ray::Ray,
     vec3::{Vec3, dot},
 };
 
-pub trait Hittable {
+/// Most crossings `hit_all` collects along one ray, in case an object keeps reporting hits
+const MAX_CROSSINGS: usize = 64;
+
+pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>);
+
+    /// Every place the ray crosses the surface within `ray_t`, nearest first.
+    ///
+    /// By default this asks `hit` for the next crossing after the previous one until there are
+    /// no more.
+    fn hit_all(&self, r: &Ray, ray_t: Interval) -> Vec<HitRecord> {
+        let mut crossings: Vec<HitRecord> = Vec::new();
+        let mut from = ray_t.min;
+
+        while crossings.len() < MAX_CROSSINGS {
+            match self.hit(r, Interval::new(from, ray_t.max)) {
+                (true, Some(rec)) => {
+                    from = rec.t;
+                    crossings.push(rec);
+                }
+                _ => break,
+            }
+        }
+
+        crossings
+    }
+
+    /// Traces the lanes of `packet` set in `mask`, replacing a lane's entry in `hits` when it
+    /// finds something closer. Gives the same records as calling `hit` on each ray.
+    ///
+    /// By default this does exactly that, one lane at a time. Shapes and aggregates that can work
+    /// on every lane at once override it.
+    fn hit_packet(&self, packet: &RayPacket, mask: Mask, ray_t: Interval, hits: &mut PacketHits) {
+        for lane in active(mask) {
+            let closest = hits[lane].as_ref().map_or(ray_t.max, |rec| rec.t);
+            if let (true, Some(rec)) =
+                self.hit(&packet.ray(lane), Interval::new(ray_t.min, closest))
+            {
+                hits[lane] = Some(rec);
+            }
+        }
+    }
+
+    /// Whether `pdf_value` and `random_toward` are implemented, so that this can be aimed at as
+    /// a light
+    fn samples_as_light(&self) -> bool {
+        false
+    }
+
+    /// Density, per unit solid angle, with which `random_toward` picks the direction of `r` from
+    /// its origin. Shapes that can't be sampled as lights leave this at zero
+    fn pdf_value(&self, _r: &Ray) -> f64 {
+        0.
+    }
+
+    /// Direction from `origin` to a random point on the surface, for sampling it as a light
+    fn random_toward(&self, _origin: Vec3, _time: f64) -> Vec3 {
+        Vec3(1., 0., 0.)
+    }
+
+    /// Axis-aligned box enclosing everything this object can be hit on
+    fn bounding_box(&self) -> Aabb;
 }
 
+#[allow(dead_code)]
 pub struct HitRecord {
     pub p: Vec3,
     pub normal: Vec3,
     pub mat: Material,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
+    /// Direction along the surface that anisotropic materials like `Hair` line up with. Zero when
+    /// the surface doesn't have one
+    pub tangent: Vec3,
+    /// Bound on how far `p` may be from the true surface along each axis, from rounding
+    pub p_error: Vec3,
+    /// Normal of the underlying geometry, on the same side as `normal`. Only differs from it
+    /// where shading normals are interpolated, as on meshes
+    pub geometric_normal: Vec3,
+}
+
+impl HitRecord {
+    /// Builds a record at `p`, orienting `outward_normal` against the incoming ray
+    pub fn new(
+        r: &Ray,
+        t: f64,
+        p: Vec3,
+        outward_normal: Vec3,
+        mat: Material,
+        u: f64,
+        v: f64,
+    ) -> Self {
+        let (normal, front_face) = set_face_normal(r, outward_normal);
+
+        // Rounding in r.at(t) itself. Shapes that can do better, or know their t is less exact,
+        // set their own
+        let p_error = gamma(7) * (r.origin.abs() + (t * r.direction).abs());
+
+        HitRecord {
+            p,
+            normal,
+            mat,
+            t,
+            u,
+            v,
+            front_face,
+            tangent: Vec3::default(),
+            p_error,
+            geometric_normal: normal,
+        }
+    }
+
+    /// Where to start a ray leaving the surface in `direction`. Pushed out along the normal just
+    /// past `p_error`, to whichever side the ray is headed, so it can't hit the surface it starts
+    /// on whatever the scale of the scene
+    pub fn spawn_origin(&self, direction: Vec3) -> Vec3 {
+        let n = if dot(direction, self.geometric_normal) < 0. {
+            -self.geometric_normal
+        } else {
+            self.geometric_normal
+        };
+        let moved = self.p + dot(n.abs(), self.p_error) * n;
+
+        // Rounding the sum could land it back inside the error bounds, so step one more float
+        // away along each axis the normal leans. That also lifts points with no error at all off
+        // the surface
+        let away = |x: f64, lean: f64| {
+            if lean > 0. {
+                x.next_up()
+            } else if lean < 0. {
+                x.next_down()
+            } else {
+                x
+            }
+        };
+        Vec3(away(moved.0, n.0), away(moved.1, n.1), away(moved.2, n.2))
+    }
+
+    pub fn spawn_ray(&self, direction: Vec3, time: f64) -> Ray {
+        Ray::with_time(self.spawn_origin(direction), direction, time)
+    }
 }
 
 pub fn set_face_normal(r: &Ray, outward_normal: Vec3) -> (Vec3, bool) {
@@ -27,3 +163,320 @@ pub fn set_face_normal(r: &Ray, outward_normal: Vec3) -> (Vec3, bool) {
 
     (normal, front_face)
 }
+
+#[cfg(test)]
+mod hittable_tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        box_shape::BoxShape,
+        constant_medium::ConstantMedium,
+        hittable_list::HittableList,
+        instance::Instance,
+        mesh::TriangleMesh,
+        quad::Quad,
+        sdf::{Sdf, SdfObject},
+        sphere::Sphere,
+        subdivision::PolyMesh,
+        transform::Transform,
+        vec3::{cross, perpendicular, unit},
+    };
+
+    const SCALES: [f64; 6] = [1e-4, 1e-2, 1., 1e2, 1e4, 1e6];
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn shoot(obj: &dyn Hittable, r: &Ray) -> Option<HitRecord> {
+        match obj.hit(r, Interval::new(0., f64::INFINITY)) {
+            (true, rec) => rec,
+            _ => None,
+        }
+    }
+
+    /// `n` directions spread evenly over the sphere along a Fibonacci spiral, so the scale tests
+    /// shoot the same rays every run
+    fn spread_directions(n: usize) -> Vec<Vec3> {
+        let golden = std::f64::consts::PI * (3. - 5f64.sqrt());
+        (0..n)
+            .map(|i| {
+                let z = 1. - (2 * i + 1) as f64 / n as f64;
+                let r = (1. - z * z).sqrt();
+                let phi = golden * i as f64;
+                Vec3(r * phi.cos(), r * phi.sin(), z)
+            })
+            .collect()
+    }
+
+    /// Slopes off the surface for rays leaving it, down to ones that barely lift off. Grazing
+    /// rays are where a poorly offset origin catches its own surface
+    const LEAVING: [f64; 5] = [0.5, 1e-2, 1e-4, 1e-6, 1e-8];
+
+    /// Slopes into the surface for rays heading back in
+    const ENTERING: [f64; 3] = [0.5, 1e-2, 1e-3];
+
+    /// Four directions along the surface with normal `n`, a quarter turn apart
+    fn along_surface(n: Vec3) -> [Vec3; 4] {
+        let a = perpendicular(n);
+        let b = cross(n, a);
+        [a, b, -a, -b]
+    }
+
+    /// Rays leaving the surface of a convex object never hit it again on the way out, however
+    /// shallow the angle, and always hit it on the way in
+    fn check_convex(obj: &dyn Hittable, center: Vec3, size: f64) {
+        check_convex_entering(obj, center, size, &ENTERING);
+    }
+
+    /// `check_convex` with only the given slopes for rays heading back in
+    fn check_convex_entering(obj: &dyn Hittable, center: Vec3, size: f64, entering: &[f64]) {
+        let directions = spread_directions(24);
+        for (i, &from) in directions.iter().enumerate() {
+            let origin = center + 4. * size * from;
+            let target = center + 0.2 * size * directions[(7 * i + 3) % directions.len()];
+            let rec = shoot(obj, &Ray::new(origin, target - origin)).unwrap();
+            let n = rec.geometric_normal;
+            assert!(rec.p_error.length() < 1e-5 * (size + center.length()));
+
+            for across in along_surface(n) {
+                for slope in LEAVING {
+                    let leaving = rec.spawn_ray(unit(across + slope * n), 0.);
+                    assert!(
+                        shoot(obj, &leaving).is_none(),
+                        "hit itself at size {size}, slope {slope}"
+                    );
+                    // Moved no further than the error bound, give or take a float step
+                    let moved = (leaving.origin - rec.p).length();
+                    assert!(moved <= rec.p_error.length() + 4. * f64::EPSILON * rec.p.length());
+                }
+
+                for &slope in entering {
+                    let entering = rec.spawn_ray(unit(across - slope * n), 0.);
+                    assert!(
+                        shoot(obj, &entering).is_some(),
+                        "leaked out at size {size}, slope {slope}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spheres_at_every_scale() {
+        for s in SCALES {
+            let center = s * Vec3(30., -20., 10.);
+            check_convex(&Sphere::new(center, s, grey()), center, s);
+        }
+    }
+
+    #[test]
+    fn sdfs_at_every_scale() {
+        for s in SCALES {
+            let center = s * Vec3(-15., 8., 22.);
+            let bounds = Aabb::from_points(
+                center - Vec3::splat(1.01 * s),
+                center + Vec3::splat(1.01 * s),
+            );
+            let ball = SdfObject::new(Sdf::sphere(s).translate(center), bounds, grey());
+            // Marching from just under the surface takes steps no longer than the depth, so a ray
+            // that dips in at a shallow angle runs out of steps before it reaches the far side.
+            // Refracted rays, the only ones that head inside, are never that shallow
+            check_convex_entering(&ball, center, s, &[0.5, 0.1]);
+        }
+    }
+
+    #[test]
+    fn boxes_at_every_scale() {
+        for s in SCALES {
+            let center = s * Vec3(-5., 40., 20.);
+            let cube = BoxShape::new(center - Vec3::splat(s), center + Vec3::splat(s), grey());
+            check_convex(&cube, center, s);
+        }
+    }
+
+    #[test]
+    fn meshes_at_every_scale() {
+        let faces = vec![
+            vec![0, 2, 4],
+            vec![4, 2, 1],
+            vec![1, 2, 5],
+            vec![5, 2, 0],
+            vec![4, 3, 0],
+            vec![1, 3, 4],
+            vec![5, 3, 1],
+            vec![0, 3, 5],
+        ];
+        let corners = [
+            Vec3(1., 0., 0.),
+            Vec3(-1., 0., 0.),
+            Vec3(0., 1., 0.),
+            Vec3(0., -1., 0.),
+            Vec3(0., 0., 1.),
+            Vec3(0., 0., -1.),
+        ];
+
+        for s in SCALES {
+            let center = s * Vec3(12., 7., -30.);
+            let positions = corners.iter().map(|&c| center + s * c).collect();
+
+            // Smooth normals, so shading and geometric normals disagree everywhere but the middle
+            // of each face
+            let data = PolyMesh::new(positions, faces.clone(), grey()).to_mesh_data();
+            check_convex(&TriangleMesh::new(data), center, s);
+        }
+    }
+
+    #[test]
+    fn instances_at_every_scale() {
+        let unit_sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(Vec3(0., 0., 0.), 1., grey()));
+
+        for s in SCALES {
+            let center = s * Vec3(3., 25., -8.);
+            let transform = Transform::scale(Vec3(s, 0.5 * s, s))
+                .then(&Transform::rotate(30., Vec3(1., 1., 0.)))
+                .then(&Transform::translate(center));
+            let ellipsoid = Instance::new(Arc::clone(&unit_sphere), transform);
+            check_convex(&ellipsoid, center, 0.5 * s);
+        }
+    }
+
+    #[test]
+    fn media_at_every_scale() {
+        for s in SCALES {
+            let center = s * Vec3(-7., 3., 15.);
+            let boundary = Sphere::new(center, s, grey()).into_box();
+            // Dense enough that even the shortest chords below are hundreds of mean free paths
+            // long, so no ray gets through
+            let fog = ConstantMedium::new(boundary, 1e3 / s, Vec3(0.8, 0.8, 0.8));
+
+            for from in spread_directions(12) {
+                let inside = Ray::new(center, from);
+                let rec = shoot(&fog, &inside).unwrap_or_else(|| panic!("got out at {s}"));
+                assert!((rec.p - center).length() <= s);
+
+                // Passing the center at a distance of `miss` times the radius, out to the rim,
+                // where the chord through it is very short
+                let side = perpendicular(from);
+                for miss in [0., 0.5, 0.9, 0.99, 0.999] {
+                    let r = Ray::new(center + s * (4. * from + miss * side), -from);
+                    let rec = shoot(&fog, &r).unwrap_or_else(|| panic!("passed through at {s}"));
+                    assert!(
+                        (rec.p - center).length() <= s * (1. + 1e-9),
+                        "{miss} at {s}"
+                    );
+                }
+
+                let wide = Ray::new(center + s * (4. * from + 1.001 * side), -from);
+                assert!(shoot(&fog, &wide).is_none(), "scattered outside at {s}");
+            }
+        }
+    }
+
+    #[test]
+    fn huge_ground_doesnt_catch_its_own_rays() {
+        for s in SCALES {
+            let ground = Quad::new(
+                Vec3(-1000. * s, 0., -1000. * s),
+                Vec3(0., 0., 2000. * s),
+                Vec3(2000. * s, 0., 0.),
+                grey(),
+            );
+            let up = Vec3(0., 1., 0.);
+
+            // From high above down to just off the ground far away, so some hits come in at a
+            // glancing angle too
+            let origins = [
+                Vec3(3., 600., -7.),
+                Vec3(-450., 20., 310.),
+                Vec3(-990., 1e-3, 0.4),
+            ];
+            let targets = [
+                Vec3(0.3, 0., -0.2),
+                Vec3(480.1, 0., -310.7),
+                Vec3(899., 0., 899.),
+            ];
+            for origin in origins {
+                for target in targets {
+                    let r = Ray::new(s * origin, s * (target - origin));
+                    let rec = shoot(&ground, &r).unwrap();
+
+                    for across in along_surface(up) {
+                        for slope in LEAVING {
+                            let out = unit(across + slope * up);
+                            let leaving = rec.spawn_ray(out, 0.);
+                            assert!(shoot(&ground, &leaving).is_none(), "{slope} at {s}");
+                            assert!(rec.spawn_origin(out).1 > 0.);
+                            assert!(rec.spawn_origin(-out).1 < 0.);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The density over all directions from `origin` should add up to one - and every direction
+    /// the shape picks must have some density.
+    ///
+    /// The integral takes the midpoint of each cell in a grid of equal-area patches of the sphere,
+    /// so it comes out the same every run and is only off by the patches the edge of the light
+    /// cuts through. The grid's pole is tilted off the axes so that the edges of axis-aligned
+    /// shapes don't run along its rows or columns
+    fn check_light_pdf(light: &dyn Hittable, origin: Vec3) {
+        let pole = unit(Vec3(0.3, 0.8, 0.5));
+        let (a, b) = (perpendicular(pole), cross(pole, perpendicular(pole)));
+        let (rows, columns) = (400, 800);
+        let mut total = 0.;
+        for i in 0..rows {
+            let z = 1. - 2. * (i as f64 + 0.5) / rows as f64;
+            let r = (1. - z * z).sqrt();
+            for j in 0..columns {
+                let phi = 2. * std::f64::consts::PI * (j as f64 + 0.5) / columns as f64;
+                let direction = r * phi.cos() * a + r * phi.sin() * b + z * pole;
+                total += light.pdf_value(&Ray::new(origin, direction));
+            }
+        }
+        let integral = total * 4. * std::f64::consts::PI / (rows * columns) as f64;
+        assert!((integral - 1.).abs() < 0.005, "{integral}");
+
+        for _ in 0..1000 {
+            let toward = light.random_toward(origin, 0.);
+            assert!(light.pdf_value(&Ray::new(origin, toward)) > 0.);
+        }
+    }
+
+    #[test]
+    fn light_densities_integrate_to_one() {
+        let (q, u, v) = (Vec3(-1., 1., -1.), Vec3(2., 0., 0.), Vec3(0., 0., 2.));
+        let origin = Vec3(0.3, 0., 0.2);
+
+        check_light_pdf(&Quad::new(q, u, v, grey()), origin);
+        check_light_pdf(&Quad::triangle(q, u, v, grey()), origin);
+        check_light_pdf(&Quad::disk(Vec3(0., 1., 0.), u, v, grey()), origin);
+
+        let ball = Sphere::new(Vec3(0., 2., 0.), 1., grey());
+        check_light_pdf(&ball, origin);
+        check_light_pdf(&ball, Vec3(0.2, 2.3, 0.));
+
+        let cube = BoxShape::new(Vec3(-0.5, 1., -0.5), Vec3(0.5, 1.5, 0.), grey());
+        check_light_pdf(&cube, origin);
+        check_light_pdf(&cube, Vec3(0., 1.2, -0.2));
+
+        // Turned, stretched and moved, so directions are warped on the way in and out
+        let transform = Transform::scale(Vec3(1., 0.3, 2.))
+            .then(&Transform::rotate(40., Vec3(1., 0., 1.)))
+            .then(&Transform::translate(Vec3(0.5, 2., 0.)));
+        let quad: Arc<dyn Hittable> = Arc::new(Quad::new(q, u, v, grey()));
+        check_light_pdf(&Instance::new(quad, transform), origin);
+        let unit_ball: Arc<dyn Hittable> = Arc::new(Sphere::new(Vec3(0., 0., 0.), 1., grey()));
+        check_light_pdf(&Instance::new(unit_ball, transform), origin);
+
+        let mut both = HittableList::default();
+        both.add(Quad::new(q, u, v, grey()).into_box());
+        both.add(Sphere::new(Vec3(0., -2., 0.), 1., grey()).into_box());
+        check_light_pdf(&both, origin);
+    }
+}
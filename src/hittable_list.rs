@@ -1,27 +1,60 @@
 use crate::{
+    aabb::Aabb,
     hittable::{HitRecord, Hittable},
     interval::Interval,
+    packet::{Mask, PacketHits, RayPacket},
+    random::Random,
+    ray::Ray,
+    vec3::Vec3,
 };
 
 #[derive(Default)]
 pub struct HittableList {
     objects: Vec<Box<dyn Hittable>>,
+    bbox: Aabb,
 }
 
-unsafe impl Sync for HittableList {}
-
+#[allow(dead_code)]
 impl HittableList {
     pub fn add(&mut self, obj: Box<dyn Hittable>) {
+        self.bbox = Aabb::surrounding(&self.bbox, &obj.bounding_box());
         self.objects.push(obj);
     }
 
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.bbox = Aabb::default();
+    }
+
+    pub fn into_objects(self) -> Vec<Box<dyn Hittable>> {
+        self.objects
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Box around everything in the list, for framing a camera on it. Empty if the list is
+    pub fn bounds(&self) -> Aabb {
+        self.bbox
+    }
+
+    /// Objects whose bounding boxes reach into `region`
+    pub fn touching(&self, region: &Aabb) -> Vec<&dyn Hittable> {
+        self.objects
+            .iter()
+            .filter(|obj| obj.bounding_box().overlaps(region))
+            .map(|obj| obj.as_ref())
+            .collect()
     }
 }
 
 impl Hittable for HittableList {
-    fn hit(&self, r: &crate::ray::Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
         let mut rec: Option<HitRecord> = None;
         let mut hit_anything = false;
         let mut closest_so_far = ray_t.max;
@@ -41,4 +74,67 @@ impl Hittable for HittableList {
 
         (hit_anything, rec)
     }
+
+    fn hit_packet(&self, packet: &RayPacket, mask: Mask, ray_t: Interval, hits: &mut PacketHits) {
+        for obj in &self.objects {
+            obj.hit_packet(packet, mask, ray_t, hits);
+        }
+    }
+
+    fn samples_as_light(&self) -> bool {
+        self.objects.iter().all(|obj| obj.samples_as_light())
+    }
+
+    /// Mixture of the members' densities, each picked equally often by `random_toward`
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        if self.objects.is_empty() {
+            return 0.;
+        }
+        let sum: f64 = self.objects.iter().map(|obj| obj.pdf_value(r)).sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3(1., 0., 0.);
+        }
+        let n = self.objects.len();
+        let pick = ((f64::rnd() * n as f64) as usize).min(n - 1);
+        self.objects[pick].random_toward(origin, time)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod hittable_list_tests {
+    use super::*;
+    use crate::{material::Material, sphere::Sphere, vec3::Vec3};
+
+    #[test]
+    fn scene_queries() {
+        let mat = Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        };
+        let mut world = HittableList::default();
+        assert!(world.is_empty() && world.bounds().is_empty());
+
+        for x in [-4., 0., 4.] {
+            world.add(Sphere::new(Vec3(x, 1., 0.), 1., mat.clone()).into_box());
+        }
+        assert_eq!(world.len(), 3);
+        assert_eq!(world.bounds().min(), Vec3(-5., 0., -1.));
+        assert_eq!(world.bounds().max(), Vec3(5., 2., 1.));
+
+        let middle = Aabb::from_points(Vec3(-1.5, 0., -0.5), Vec3(1.5, 0.5, 0.5));
+        assert_eq!(world.touching(&middle).len(), 1);
+
+        let right = Aabb::from_points(Vec3(1., 0., 0.), Vec3(10., 10., 10.));
+        assert_eq!(world.touching(&right).len(), 2);
+
+        let above = Aabb::from_points(Vec3(-10., 3., -10.), Vec3(10., 4., 10.));
+        assert!(world.touching(&above).is_empty());
+    }
 }
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    motion::AnimatedTransform,
+    ray::Ray,
+    transform::Transform,
+    vec3::{Vec3, cross, dot, unit},
+};
+
+/// A shared hittable placed in the world by an affine transform.
+///
+/// Many instances can point at the same object, so a mesh can be placed hundreds of times without
+/// copying it.
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    transform: Transform,
+    /// Overrides `transform` with one that depends on the ray's time
+    motion: Option<AnimatedTransform>,
+    bbox: Aabb,
+}
+
+#[allow(dead_code)]
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Self {
+        let bbox = transform.bbox(&object.bounding_box());
+        Instance {
+            object,
+            transform,
+            motion: None,
+            bbox,
+        }
+    }
+
+    /// Instance whose transform follows `motion` over the shutter interval
+    pub fn animated(object: Arc<dyn Hittable>, motion: AnimatedTransform) -> Self {
+        let bbox = motion.bbox(&object.bounding_box());
+        Instance {
+            object,
+            transform: Transform::identity(),
+            motion: Some(motion),
+            bbox,
+        }
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    fn transform_at(&self, time: f64) -> Transform {
+        match &self.motion {
+            Some(motion) => motion.at(time),
+            None => self.transform,
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        let transform = self.transform_at(r.time);
+
+        // The direction is left unnormalized so t means the same thing in both spaces
+        let to_object = transform.inverse();
+        let object_ray = Ray::with_time(
+            to_object.point(r.origin),
+            to_object.vector(r.direction),
+            r.time,
+        );
+
+        let (hit, rec) = self.object.hit(&object_ray, ray_t);
+        match rec {
+            Some(mut rec) if hit => {
+                // Inverse transpose keeps the normal on the same side of the surface, so
+                // front_face carries over unchanged
+                (rec.p, rec.p_error) = transform.point_with_error(rec.p, rec.p_error);
+                rec.normal = unit(transform.normal(rec.normal));
+                rec.geometric_normal = unit(transform.normal(rec.geometric_normal));
+                if !rec.tangent.near_zero() {
+                    rec.tangent = unit(transform.vector(rec.tangent));
+                }
+                (true, Some(rec))
+            }
+            _ => (false, None),
+        }
+    }
+
+    fn samples_as_light(&self) -> bool {
+        self.object.samples_as_light()
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        let to_object = self.transform_at(r.time).inverse();
+        let object_direction = to_object.vector(unit(r.direction));
+        let object_ray = Ray::with_time(to_object.point(r.origin), object_direction, r.time);
+
+        // The transform stretches directions as well as space. Normalizing A w, for the linear
+        // part A of the inverse, changes solid angle by |det A| / |A w|^3
+        let column = |axis: Vec3| to_object.vector(axis);
+        let det = dot(
+            column(Vec3(1., 0., 0.)),
+            cross(column(Vec3(0., 1., 0.)), column(Vec3(0., 0., 1.))),
+        );
+        self.object.pdf_value(&object_ray) * det.abs() / object_direction.length().powi(3)
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64) -> Vec3 {
+        let transform = self.transform_at(time);
+        let toward = self
+            .object
+            .random_toward(transform.inverse().point(origin), time);
+        transform.vector(toward)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod instance_tests {
+    use super::*;
+    use crate::{material::Material, motion::Pose, quad::Quad, sphere::Sphere, vec3::Vec3};
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn translated_sphere() {
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(Vec3(0., 0., 0.), 1., grey()));
+        let moved = Instance::new(sphere, Transform::translate(Vec3(5., 0., 0.)));
+
+        let r = Ray::new(Vec3(5., 0., -10.), Vec3(0., 0., 1.));
+        let (hit, rec) = moved.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert!(hit);
+        assert_eq!(rec.t, 9.);
+        assert!(close(rec.p, Vec3(5., 0., -1.)));
+        assert!(rec.front_face);
+
+        let bbox = moved.bounding_box();
+        assert_eq!(bbox.min(), Vec3(4., -1., -1.));
+        assert_eq!(bbox.max(), Vec3(6., 1., 1.));
+    }
+
+    #[test]
+    fn shared_object_placed_twice() {
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(Vec3(0., 0., 0.), 1., grey()));
+        let a = Instance::new(Arc::clone(&sphere), Transform::translate(Vec3(-3., 0., 0.)));
+        let b = Instance::new(Arc::clone(&sphere), Transform::translate(Vec3(3., 0., 0.)));
+        assert_eq!(Arc::strong_count(&sphere), 3);
+
+        let r = Ray::new(Vec3(-10., 0., 0.), Vec3(1., 0., 0.));
+        let (_, ra) = a.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let (_, rb) = b.hit(&r, Interval::new(0.001, f64::INFINITY));
+        assert_eq!(ra.unwrap().t, 6.);
+        assert_eq!(rb.unwrap().t, 12.);
+    }
+
+    #[test]
+    fn rotated_quad_normal() {
+        // Unit quad in the xy plane facing +z, turned to face +x
+        let quad: Arc<dyn Hittable> = Arc::new(Quad::new(
+            Vec3(-0.5, -0.5, 0.),
+            Vec3(1., 0., 0.),
+            Vec3(0., 1., 0.),
+            grey(),
+        ));
+        let turned = Instance::new(quad, Transform::rotate_y(90.));
+
+        let r = Ray::new(Vec3(5., 0.2, 0.2), Vec3(-1., 0., 0.));
+        let (hit, rec) = turned.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert!(hit);
+        assert!(rec.front_face);
+        assert!(close(rec.normal, Vec3(1., 0., 0.)));
+        assert!(close(rec.p, Vec3(0., 0.2, 0.2)));
+
+        // Edge-on in the original orientation, so the untransformed quad would have been missed
+        let r = Ray::new(Vec3(0.2, 0.2, 5.), Vec3(0., 0., -1.));
+        assert!(!turned.hit(&r, Interval::new(0.001, f64::INFINITY)).0);
+    }
+
+    #[test]
+    fn scaled_sphere_normals_are_unit() {
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(Vec3(0., 0., 0.), 1., grey()));
+        let squashed = Instance::new(sphere, Transform::scale(Vec3(3., 1., 1.)));
+
+        let r = Ray::new(Vec3(-10., 0., 0.), Vec3(1., 0., 0.));
+        let (_, rec) = squashed.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert!(close(rec.p, Vec3(-3., 0., 0.)));
+
+        let r = Ray::new(Vec3(1.5, 5., 0.), Vec3(0., -1., 0.));
+        let (_, rec) = squashed.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert!((rec.normal.length() - 1.).abs() < 1e-12);
+        // Ellipse x^2/9 + y^2 = 1 has gradient (2x/9, 2y) at the hit point
+        let p = rec.p;
+        assert!(close(rec.normal, unit(Vec3(p.0 / 9., p.1, 0.))));
+    }
+
+    #[test]
+    fn animated_instance_moves_with_ray_time() {
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(Vec3(0., 0., 0.), 1., grey()));
+        let slide = AnimatedTransform::new(vec![
+            (0., Pose::default()),
+            (
+                1.,
+                Pose {
+                    translate: Vec3(10., 0., 0.),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        let moving = Instance::animated(sphere, slide);
+
+        let r = |time| Ray::with_time(Vec3(5., 0., -10.), Vec3(0., 0., 1.), time);
+        assert!(!moving.hit(&r(0.), Interval::new(0.001, f64::INFINITY)).0);
+        assert!(moving.hit(&r(0.5), Interval::new(0.001, f64::INFINITY)).0);
+        assert!(!moving.hit(&r(1.), Interval::new(0.001, f64::INFINITY)).0);
+
+        assert!(moving.bounding_box().x.max >= 11.);
+    }
+}
@@ -1,15 +1,31 @@
-use std::f64::{INFINITY, NEG_INFINITY};
-
+#[derive(Debug, Clone, Copy)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
 }
 
+#[allow(dead_code)]
 impl Interval {
     pub fn new(min: f64, max: f64) -> Self {
         Interval { min, max }
     }
 
+    /// Tightest interval containing both `a` and `b`
+    pub fn enclosing(a: Interval, b: Interval) -> Self {
+        Interval {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
+
+    /// Part of the line covered by both `a` and `b`, which may be empty
+    pub fn overlap(a: Interval, b: Interval) -> Self {
+        Interval {
+            min: a.min.max(b.min),
+            max: a.max.min(b.max),
+        }
+    }
+
     pub fn size(&self) -> f64 {
         self.max - self.min
     }
@@ -31,15 +47,21 @@ impl Interval {
             _ => x,
         }
     }
+
+    pub fn expand(&self, delta: f64) -> Interval {
+        let padding = delta / 2.;
+        Interval::new(self.min - padding, self.max + padding)
+    }
 }
 
 pub const EMPTY: Interval = Interval {
-    min: INFINITY,
-    max: NEG_INFINITY,
+    min: f64::INFINITY,
+    max: f64::NEG_INFINITY,
 };
+#[allow(dead_code)]
 pub const UNIVERSE: Interval = Interval {
-    min: NEG_INFINITY,
-    max: INFINITY,
+    min: f64::NEG_INFINITY,
+    max: f64::INFINITY,
 };
 
 #[cfg(test)]
@@ -52,7 +74,6 @@ mod interval_tests {
     fn clamp() {
         let interval = Interval::new(5.0, 10.0);
 
-
         assert_eq!(interval.clamp(2.0), 5.0);
         assert_eq!(interval.clamp(100.0), 10.0);
         assert_eq!(interval.clamp(6.5), 6.5);
@@ -1,21 +1,46 @@
+use bvh::Bvh;
 use camera::{Camera, CameraConfig};
 use hittable_list::HittableList;
 use material::Material::{Dialectric, Lambertian, Metal};
-use rand::random_range;
 use random::Random;
 use sphere::Sphere;
 use vec3::Vec3;
 
+mod aabb;
+mod box_shape;
+mod bvh;
 mod camera;
 mod color;
+mod constant_medium;
+mod csg;
+mod curve;
+mod displacement;
 mod global_stuff;
+mod heightfield;
 mod hittable;
 mod hittable_list;
+mod instance;
 mod interval;
 mod material;
+mod mesh;
+mod metaballs;
+mod motion;
+mod noise;
+mod obj;
+mod packet;
+mod ply;
+mod poly;
+mod polyhedron;
+mod quad;
+mod quadric;
 mod random;
 mod ray;
+mod sdf;
 mod sphere;
+mod subdivision;
+mod texture;
+mod torus;
+mod transform;
 mod vec3;
 
 fn main() {
@@ -23,7 +48,7 @@ fn main() {
     let mut world: HittableList = Default::default();
 
     let ground_material = Lambertian {
-        albedo: Vec3(0.5, 0.5, 0.5),
+        albedo: Vec3(0.5, 0.5, 0.5).into(),
     };
     world.add(Sphere::new(Vec3(0., -1000., 0.), 1000., ground_material).into_box());
 
@@ -41,13 +66,18 @@ fn main() {
                 match choose_mat {
                     i if i < 0.8 => {
                         let albedo = Vec3::rnd() * Vec3::rnd();
-                        let mat = Lambertian { albedo };
+                        let mat = Lambertian {
+                            albedo: albedo.into(),
+                        };
                         world.add(Sphere::new(center, 0.2, mat).into_box());
                     }
                     i if i < 0.95 => {
                         let albedo = Vec3::rnd_rng(0.5, 1.);
                         let fuzz = f64::rnd_rng(0., 0.5);
-                        let mat = Metal { albedo, fuzz };
+                        let mat = Metal {
+                            albedo: albedo.into(),
+                            fuzz,
+                        };
                         world.add(Sphere::new(center, 0.2, mat).into_box());
                     }
                     _ => {
@@ -67,12 +97,12 @@ fn main() {
     world.add(Sphere::new(Vec3(0., 1., 0.), 1., material1).into_box());
 
     let material2 = Lambertian {
-        albedo: Vec3(0.4, 0.2, 0.1),
+        albedo: Vec3(0.4, 0.2, 0.1).into(),
     };
     world.add(Sphere::new(Vec3(-4., 1., 0.), 1., material2).into_box());
 
     let material3 = Metal {
-        albedo: Vec3(0.7, 0.6, 0.5),
+        albedo: Vec3(0.7, 0.6, 0.5).into(),
         fuzz: 0.0,
     };
     world.add(Sphere::new(Vec3(4., 1., 0.), 1., material3).into_box());
@@ -88,7 +118,11 @@ fn main() {
         max_depth: 50,
         defocus_angle: 0.6,
         focus_dist: 10.0,
+        shutter_open: 0.0,
+        shutter_close: 1.0,
     });
 
+    let world = Bvh::new(world);
+
     cam.render(&world);
 }
@@ -1,25 +1,73 @@
+use std::f64::consts::PI;
+
 use rand::random;
 
 use crate::{
     hittable::HitRecord,
     ray::Ray,
-    vec3::{Vec3, dot, random_unit_vector, reflect, refract, unit},
+    texture::Texture,
+    vec3::{Vec3, cross, dot, perpendicular, random_unit_vector, reflect, refract, unit},
 };
 
 #[derive(Clone)]
 pub enum Material {
-    Lambertian { albedo: Vec3 },
-    Metal { albedo: Vec3, fuzz: f64 },
-    Dialectric { refraction_index: f64 },
+    Lambertian {
+        albedo: Texture,
+    },
+    Metal {
+        albedo: Texture,
+        fuzz: f64,
+    },
+    Dialectric {
+        refraction_index: f64,
+    },
+    /// Phase function for participating media - scatters uniformly in every direction
+    Isotropic {
+        albedo: Vec3,
+    },
+    /// Kajiya-Kay hair and fur. Light leaves in a diffuse lobe that follows the sine of the angle
+    /// to the strand, or with probability `specular` as an uncolored highlight on the cone of
+    /// mirror directions around it, which `exponent` tightens. Lines up with `HitRecord::tangent`
+    #[allow(dead_code)]
+    Hair {
+        albedo: Vec3,
+        specular: f64,
+        exponent: f64,
+    },
+    /// Area light. Gives off `emit` scaled by `intensity` from both sides, and scatters nothing
+    #[allow(dead_code)]
+    DiffuseLight {
+        emit: Texture,
+        intensity: f64,
+    },
+}
+
+/// One direction picked by `Material::scatter`
+pub struct ScatterSample {
+    pub ray: Ray,
+    /// BSDF times cosine over `pdf` - what light coming back along `ray` is multiplied by
+    pub attenuation: Vec3,
+    /// Density, per unit solid angle, the direction was picked with. Zero for delta lobes
+    pub pdf: f64,
+    /// Picked from a lobe with no density to speak of, like a mirror or glass, so `eval` and
+    /// `pdf` know nothing of it and light sampling can't help
+    pub delta: bool,
 }
 
-// pub trait Material {
-//     /// Scatters vector- returns (true, attenuation, scattered ray)
-//     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray);
-// }
+impl ScatterSample {
+    fn delta(ray: Ray, attenuation: Vec3) -> Self {
+        ScatterSample {
+            ray,
+            attenuation,
+            pdf: 0.,
+            delta: true,
+        }
+    }
+}
 
 impl Material {
-    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
+    /// Picks a direction for light arriving along `r_in` to leave in, or `None` if it's absorbed
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterSample> {
         match self {
             Material::Lambertian { albedo } => {
                 let mut scatter_direction = rec.normal + random_unit_vector();
@@ -28,23 +76,28 @@ impl Material {
                     scatter_direction = rec.normal;
                 }
 
-                let scattered = Ray::new(rec.p, scatter_direction);
-                let attenuation = albedo;
-
-                (true, *attenuation, scattered)
+                Some(ScatterSample {
+                    ray: rec.spawn_ray(scatter_direction, r_in.time),
+                    attenuation: albedo.value(rec.u, rec.v, rec.p),
+                    pdf: self.pdf(rec, scatter_direction),
+                    delta: false,
+                })
             }
             Material::Metal { albedo, fuzz } => {
+                // Fuzzed reflections have no closed form density, so they go as a delta lobe too
                 let mut reflected = reflect(r_in.direction, rec.normal);
                 reflected = unit(reflected) + (*fuzz * random_unit_vector());
-                let scattered = Ray::new(rec.p, reflected);
-                let attenuation = albedo;
-
-                let b = dot(scattered.direction, rec.normal) > 0.;
+                if dot(reflected, rec.normal) <= 0. {
+                    return None;
+                }
 
-                (b, *attenuation, scattered)
+                let scattered = rec.spawn_ray(reflected, r_in.time);
+                Some(ScatterSample::delta(
+                    scattered,
+                    albedo.value(rec.u, rec.v, rec.p),
+                ))
             }
             Material::Dialectric { refraction_index } => {
-                let attenuation = Vec3(1., 1., 1.);
                 let ri = if rec.front_face {
                     1.0 / *refraction_index
                 } else {
@@ -62,17 +115,362 @@ impl Material {
                     refract(unit_direction, rec.normal, ri)
                 };
 
-                let scattered = Ray::new(rec.p, direction);
+                let scattered = rec.spawn_ray(direction, r_in.time);
+                Some(ScatterSample::delta(scattered, Vec3(1., 1., 1.)))
+            }
+            Material::Isotropic { albedo } => {
+                let direction = random_unit_vector();
+
+                Some(ScatterSample {
+                    ray: rec.spawn_ray(direction, r_in.time),
+                    attenuation: *albedo,
+                    pdf: self.pdf(rec, direction),
+                    delta: false,
+                })
+            }
+            Material::Hair {
+                albedo,
+                specular,
+                exponent,
+            } => {
+                let t = hair_tangent(rec);
+                let b1 = perpendicular(t);
+                let b2 = cross(t, b1);
+
+                if random::<f64>() < *specular {
+                    // Mirroring off a cylinder keeps the angle to its axis, and the exponent
+                    // spreads the highlight either side of that cone
+                    let incoming = dot(unit(r_in.direction), t).clamp(-1., 1.).asin();
+                    let spread = random::<f64>().powf(1. / (exponent + 1.)).acos();
+                    let theta = if random::<bool>() {
+                        incoming + spread
+                    } else {
+                        incoming - spread
+                    };
+                    let phi = 2. * PI * random::<f64>();
+
+                    let direction =
+                        theta.sin() * t + theta.cos() * (phi.cos() * b1 + phi.sin() * b2);
+                    let scattered = rec.spawn_ray(direction, r_in.time);
+
+                    Some(ScatterSample::delta(scattered, Vec3(1., 1., 1.)))
+                } else {
+                    // Thin strands scatter all the way round, so sample the whole sphere and
+                    // weight by the lobe. Sine averages pi / 4 over it
+                    let direction = random_unit_vector();
+                    let sine = (1. - dot(direction, t).powi(2)).max(0.).sqrt();
+
+                    Some(ScatterSample {
+                        ray: rec.spawn_ray(direction, r_in.time),
+                        attenuation: *albedo * (sine * 4. / PI),
+                        pdf: self.pdf(rec, direction),
+                        delta: false,
+                    })
+                }
+            }
+            Material::DiffuseLight { .. } => None,
+        }
+    }
+
+    /// Density, per unit solid angle, with which `scatter` picks `direction` from its smooth
+    /// lobes. Delta lobes count for nothing here
+    pub fn pdf(&self, rec: &HitRecord, direction: Vec3) -> f64 {
+        match self {
+            Material::Lambertian { .. } => {
+                let cosine = dot(rec.normal, unit(direction));
+                cosine.max(0.) / PI
+            }
+            Material::Isotropic { .. } => 1. / (4. * PI),
+            Material::Hair { specular, .. } => (1. - specular) / (4. * PI),
+            _ => 0.,
+        }
+    }
+
+    /// How much of the light arriving along `direction` is scattered back down `r_in` - the BSDF
+    /// times the cosine at the surface, over the smooth lobes only
+    pub fn eval(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        match self {
+            Material::Lambertian { albedo } => {
+                let cosine = dot(rec.normal, unit(direction));
+                albedo.value(rec.u, rec.v, rec.p) * (cosine.max(0.) / PI)
+            }
+            Material::Isotropic { albedo } => *albedo / (4. * PI),
+            Material::Hair {
+                albedo, specular, ..
+            } => {
+                let sine = (1. - dot(unit(direction), hair_tangent(rec)).powi(2))
+                    .max(0.)
+                    .sqrt();
+                (1. - specular) * sine / (PI * PI) * *albedo
+            }
+            _ => Vec3(0., 0., 0.),
+        }
+    }
 
-                (true, attenuation, scattered)
+    /// Light given off at the hit, before anything scattered is added
+    pub fn emitted(&self, rec: &HitRecord) -> Vec3 {
+        match self {
+            Material::DiffuseLight { emit, intensity } => {
+                *intensity * emit.value(rec.u, rec.v, rec.p)
             }
+            _ => Vec3(0., 0., 0.),
         }
     }
 }
 
+/// Direction of the strand at a hair hit, or some direction across the surface if it has none
+fn hair_tangent(rec: &HitRecord) -> Vec3 {
+    if rec.tangent.near_zero() {
+        perpendicular(rec.normal)
+    } else {
+        unit(rec.tangent)
+    }
+}
+
 fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
     let mut r0 = (1. - refraction_index) / (1. + refraction_index);
     r0 = r0 * r0;
 
     r0 + (1. - r0) * ((1. - cosine).powf(5.))
 }
+
+#[cfg(test)]
+mod material_tests {
+    use super::*;
+    use crate::{
+        camera::Background, hittable::Hittable, hittable_list::HittableList, interval::Interval,
+        random::Random, sphere::Sphere,
+    };
+
+    /// `scatter` as it was before it handed back a `ScatterSample`, kept to check the port
+    /// against
+    fn legacy_scatter(mat: &Material, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
+        match mat {
+            Material::Lambertian { albedo } => {
+                let mut scatter_direction = rec.normal + random_unit_vector();
+                if scatter_direction.near_zero() {
+                    scatter_direction = rec.normal;
+                }
+                let scattered = rec.spawn_ray(scatter_direction, r_in.time);
+                (true, albedo.value(rec.u, rec.v, rec.p), scattered)
+            }
+            Material::Metal { albedo, fuzz } => {
+                let mut reflected = reflect(r_in.direction, rec.normal);
+                reflected = unit(reflected) + (*fuzz * random_unit_vector());
+                let scattered = rec.spawn_ray(reflected, r_in.time);
+                let b = dot(scattered.direction, rec.normal) > 0.;
+                (b, albedo.value(rec.u, rec.v, rec.p), scattered)
+            }
+            Material::Dialectric { refraction_index } => {
+                let ri = if rec.front_face {
+                    1.0 / *refraction_index
+                } else {
+                    *refraction_index
+                };
+                let unit_direction = unit(r_in.direction);
+                let cos_theta = dot(-unit_direction, rec.normal).min(1.);
+                let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+
+                let cannot_refract = ri * sin_theta > 1.;
+                let direction = if cannot_refract || reflectance(cos_theta, ri) > random::<f64>() {
+                    reflect(unit_direction, rec.normal)
+                } else {
+                    refract(unit_direction, rec.normal, ri)
+                };
+                (true, Vec3(1., 1., 1.), rec.spawn_ray(direction, r_in.time))
+            }
+            _ => unreachable!("only the ported materials are kept"),
+        }
+    }
+
+    type Scatter = fn(&Material, &Ray, &HitRecord) -> Option<(Vec3, Ray)>;
+
+    fn new_api(mat: &Material, r: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)> {
+        mat.scatter(r, rec).map(|s| (s.attenuation, s.ray))
+    }
+
+    fn old_api(mat: &Material, r: &Ray, rec: &HitRecord) -> Option<(Vec3, Ray)> {
+        let (b, attenuation, scattered) = legacy_scatter(mat, r, rec);
+        b.then_some((attenuation, scattered))
+    }
+
+    fn ported() -> [Material; 4] {
+        [
+            Material::Lambertian {
+                albedo: Vec3(0.7, 0.3, 0.2).into(),
+            },
+            Material::Metal {
+                albedo: Vec3(0.8, 0.8, 0.9).into(),
+                fuzz: 0.,
+            },
+            Material::Metal {
+                albedo: Vec3(0.9, 0.6, 0.2).into(),
+                fuzz: 0.5,
+            },
+            Material::Dialectric {
+                refraction_index: 1.5,
+            },
+        ]
+    }
+
+    /// Hit on a floor facing up, from a ray coming in at a slant
+    fn floor_hit(mat: Material, front: bool) -> (Ray, HitRecord) {
+        let incoming = if front {
+            Vec3(0.6, -1., 0.2)
+        } else {
+            Vec3(0.3, 1., -0.1)
+        };
+        let r = Ray::new(Vec3(0., 0., 0.) - incoming, incoming);
+        let rec = HitRecord::new(&r, 1., Vec3(0., 0., 0.), Vec3(0., 1., 0.), mat, 0., 0.);
+        (r, rec)
+    }
+
+    #[test]
+    fn smooth_lobes_report_their_density() {
+        let lambertian = &ported()[0];
+        let (r, rec) = floor_hit(lambertian.clone(), true);
+
+        for _ in 0..1000 {
+            let sample = lambertian.scatter(&r, &rec).unwrap();
+            assert!(!sample.delta);
+            assert!(sample.pdf > 0.);
+            assert_eq!(sample.pdf, lambertian.pdf(&rec, sample.ray.direction));
+
+            // The weight handed back is the BSDF over the density it was picked with
+            let ratio = lambertian.eval(&r, &rec, sample.ray.direction) / sample.pdf;
+            assert!((ratio - sample.attenuation).length() < 1e-9);
+        }
+
+        // Hair's diffuse lobe too
+        let hair = Material::Hair {
+            albedo: Vec3(0.5, 0.3, 0.1),
+            specular: 0.3,
+            exponent: 20.,
+        };
+        let (r, rec) = floor_hit(hair.clone(), true);
+        let mut smooth = 0;
+        for _ in 0..1000 {
+            let sample = hair.scatter(&r, &rec).unwrap();
+            if !sample.delta {
+                smooth += 1;
+                let ratio = hair.eval(&r, &rec, sample.ray.direction) / sample.pdf;
+                assert!((ratio - sample.attenuation).length() < 1e-9);
+            }
+        }
+        assert!((600..800).contains(&smooth), "{smooth}");
+    }
+
+    #[test]
+    fn mirrors_and_glass_are_delta() {
+        for mat in &ported()[1..] {
+            for front in [true, false] {
+                let (r, rec) = floor_hit(mat.clone(), front);
+                for _ in 0..100 {
+                    let Some(sample) = mat.scatter(&r, &rec) else {
+                        continue;
+                    };
+                    assert!(sample.delta);
+                    assert_eq!(sample.pdf, 0.);
+                    assert_eq!(mat.pdf(&rec, sample.ray.direction), 0.);
+                    assert_eq!(mat.eval(&r, &rec, sample.ray.direction), Vec3(0., 0., 0.));
+                }
+            }
+        }
+
+        let light = Material::DiffuseLight {
+            emit: Vec3(1., 1., 1.).into(),
+            intensity: 1.,
+        };
+        let (r, rec) = floor_hit(light.clone(), true);
+        assert!(light.scatter(&r, &rec).is_none());
+    }
+
+    /// Average of the attenuation, the attenuation-weighted direction, and how often the path
+    /// ends, over many scatters
+    fn scatter_stats(scatter: Scatter, mat: &Material, front: bool) -> (Vec3, Vec3, f64) {
+        let n = 20_000;
+        let (r, rec) = floor_hit(mat.clone(), front);
+        let (mut attenuation, mut weighted, mut absorbed) = (Vec3::default(), Vec3::default(), 0);
+
+        for _ in 0..n {
+            match scatter(mat, &r, &rec) {
+                Some((a, out)) => {
+                    attenuation += a;
+                    weighted += a * unit(out.direction);
+                }
+                None => absorbed += 1,
+            }
+        }
+
+        let n_f = n as f64;
+        (attenuation / n_f, weighted / n_f, absorbed as f64 / n_f)
+    }
+
+    #[test]
+    fn scatters_like_before() {
+        for mat in ported() {
+            for front in [true, false] {
+                let (a_old, w_old, k_old) = scatter_stats(old_api, &mat, front);
+                let (a_new, w_new, k_new) = scatter_stats(new_api, &mat, front);
+
+                assert!((a_old - a_new).length() < 0.03, "{a_old:?} {a_new:?}");
+                assert!((w_old - w_new).length() < 0.03, "{w_old:?} {w_new:?}");
+                assert!((k_old - k_new).abs() < 0.03, "{k_old} {k_new}");
+            }
+        }
+    }
+
+    /// Plain path tracing under the sky, scattering with `scatter`
+    fn trace(scatter: Scatter, r: &Ray, depth: i32, world: &dyn Hittable) -> Vec3 {
+        if depth <= 0 {
+            return Vec3(0., 0., 0.);
+        }
+        match world.hit(r, Interval::new(0., f64::INFINITY)) {
+            (true, Some(rec)) => match scatter(&rec.mat, r, &rec) {
+                Some((attenuation, out)) => attenuation * trace(scatter, &out, depth - 1, world),
+                None => Vec3(0., 0., 0.),
+            },
+            _ => Background::sky().color(r.direction),
+        }
+    }
+
+    #[test]
+    fn images_are_statistically_unchanged() {
+        let mut world = HittableList::default();
+        let [diffuse, mirror, brushed, glass] = ported();
+        world.add(Sphere::new(Vec3(0., -100.5, -1.), 100., diffuse).into_box());
+        world.add(Sphere::new(Vec3(-1., 0., -1.), 0.5, mirror).into_box());
+        world.add(Sphere::new(Vec3(0., 0., -1.2), 0.5, brushed).into_box());
+        world.add(Sphere::new(Vec3(1., 0., -1.), 0.5, glass).into_box());
+
+        // A coarse 6 x 3 image, each pixel's mean and standard error from many samples
+        let samples = 600;
+        let pixel = |scatter: Scatter, i: usize, j: usize| {
+            let values: Vec<f64> = (0..samples)
+                .map(|_| {
+                    let x = (i as f64 + f64::rnd()) / 6. * 4. - 2.;
+                    let y = 1. - (j as f64 + f64::rnd()) / 3. * 2.;
+                    let r = Ray::new(Vec3(0., 0., 1.), Vec3(x, y, -2.));
+                    let c = trace(scatter, &r, 8, &world);
+                    (c.0 + c.1 + c.2) / 3.
+                })
+                .collect();
+            let mean = values.iter().sum::<f64>() / samples as f64;
+            let variance =
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (samples - 1) as f64;
+            (mean, variance / samples as f64)
+        };
+
+        for j in 0..3 {
+            for i in 0..6 {
+                let (old, old_var) = pixel(old_api, i, j);
+                let (new, new_var) = pixel(new_api, i, j);
+                let error = (old_var + new_var).sqrt();
+                assert!(
+                    (old - new).abs() < 5. * error + 1e-3,
+                    "pixel ({i}, {j}): {old} vs {new}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    bvh::Bvh,
+    global_stuff::gamma,
+    hittable::{HitRecord, Hittable, set_face_normal},
+    hittable_list::HittableList,
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    vec3::{Vec3, cross, dot, unit},
+};
+
+/// One triangle of a mesh, as indices into the shared buffers of `MeshData`
+#[derive(Debug, Clone, Copy)]
+pub struct MeshFace {
+    pub v: [usize; 3],
+    pub n: Option<[usize; 3]>,
+    pub uv: Option<[usize; 3]>,
+    pub material: usize,
+}
+
+/// Vertex, normal and uv buffers shared by every triangle in a mesh
+#[derive(Default)]
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    /// Optional per-vertex colors, indexed like `positions`. Used as the albedo of `Lambertian`
+    /// faces
+    pub colors: Vec<Vec3>,
+    pub faces: Vec<MeshFace>,
+    pub materials: Vec<Material>,
+}
+
+/// Triangle mesh with its own BVH, so it takes a single slot in a `HittableList`
+pub struct TriangleMesh {
+    data: Arc<MeshData>,
+    bvh: Bvh,
+}
+
+#[allow(dead_code)]
+impl TriangleMesh {
+    pub fn new(data: MeshData) -> Self {
+        let data = Arc::new(data);
+
+        let mut triangles = HittableList::default();
+        for index in 0..data.faces.len() {
+            triangles.add(Box::new(MeshTriangle {
+                mesh: Arc::clone(&data),
+                index,
+            }));
+        }
+
+        TriangleMesh {
+            bvh: Bvh::new(triangles),
+            data,
+        }
+    }
+
+    pub fn data(&self) -> &MeshData {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.faces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.faces.is_empty()
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        self.bvh.hit(r, ray_t)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
+    }
+}
+
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    index: usize,
+}
+
+impl MeshTriangle {
+    fn vertices(&self) -> [Vec3; 3] {
+        let face = &self.mesh.faces[self.index];
+        face.v.map(|i| self.mesh.positions[i])
+    }
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        let [p0, p1, p2] = self.vertices();
+
+        let (b, t) = match intersect_triangle(r, p0, p1, p2, ray_t) {
+            Some(hit) => hit,
+            None => return (false, None),
+        };
+
+        let face = &self.mesh.faces[self.index];
+        let p = b[0] * p0 + b[1] * p1 + b[2] * p2;
+
+        // Shading normal is interpolated, but kept in the geometric normal's hemisphere
+        let geometric_normal = unit(cross(p1 - p0, p2 - p0));
+        let outward_normal = match face.n {
+            Some(n) => {
+                let ns = b[0] * self.mesh.normals[n[0]]
+                    + b[1] * self.mesh.normals[n[1]]
+                    + b[2] * self.mesh.normals[n[2]];
+                if ns.length_squared() < 1e-16 {
+                    geometric_normal
+                } else if dot(ns, geometric_normal) < 0. {
+                    -unit(ns)
+                } else {
+                    unit(ns)
+                }
+            }
+            None => geometric_normal,
+        };
+
+        let (u, v) = match face.uv {
+            Some(uv) => {
+                let [a, bb, c] = uv.map(|i| self.mesh.uvs[i]);
+                (
+                    b[0] * a.0 + b[1] * bb.0 + b[2] * c.0,
+                    b[0] * a.1 + b[1] * bb.1 + b[2] * c.1,
+                )
+            }
+            None => (b[1], b[2]),
+        };
+
+        let mat = match &self.mesh.materials[face.material] {
+            Material::Lambertian { .. } if !self.mesh.colors.is_empty() => {
+                let [c0, c1, c2] = face.v.map(|i| self.mesh.colors[i]);
+                Material::Lambertian {
+                    albedo: (b[0] * c0 + b[1] * c1 + b[2] * c2).into(),
+                }
+            }
+            mat => mat.clone(),
+        };
+
+        let mut rec = HitRecord::new(r, t, p, outward_normal, mat, u, v);
+        rec.p_error = gamma(7) * ((b[0] * p0).abs() + (b[1] * p1).abs() + (b[2] * p2).abs());
+        rec.geometric_normal = set_face_normal(r, geometric_normal).0;
+        (true, Some(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [p0, p1, p2] = self.vertices();
+        Aabb::surrounding(&Aabb::from_points(p0, p1), &Aabb::from_points(p0, p2)).pad_to_minimums()
+    }
+}
+
+/// Watertight ray/triangle test (Woop, Benthin & Wald 2013).
+///
+/// Works in a ray-aligned space where edges shared by two triangles are evaluated identically from
+/// both sides, so rays can't slip through the cracks between them. Returns the barycentric weights
+/// of (p0, p1, p2) and the ray parameter.
+pub fn intersect_triangle(
+    r: &Ray,
+    p0: Vec3,
+    p1: Vec3,
+    p2: Vec3,
+    ray_t: Interval,
+) -> Option<([f64; 3], f64)> {
+    let d = r.direction;
+
+    // Permute so the ray's dominant axis becomes z
+    let kz = max_dimension(Vec3(d.0.abs(), d.1.abs(), d.2.abs()));
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if d[kz] < 0. {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    let sx = -d[kx] / d[kz];
+    let sy = -d[ky] / d[kz];
+    let sz = 1. / d[kz];
+
+    // Translate to the ray origin, permute and shear so the ray runs along +z
+    let to_ray_space = |p: Vec3| {
+        let q = p - r.origin;
+        (q[kx] + sx * q[kz], q[ky] + sy * q[kz], q[kz] * sz)
+    };
+    let (x0, y0, z0) = to_ray_space(p0);
+    let (x1, y1, z1) = to_ray_space(p1);
+    let (x2, y2, z2) = to_ray_space(p2);
+
+    let e0 = x1 * y2 - y1 * x2;
+    let e1 = x2 * y0 - y2 * x0;
+    let e2 = x0 * y1 - y0 * x1;
+
+    if (e0 < 0. || e1 < 0. || e2 < 0.) && (e0 > 0. || e1 > 0. || e2 > 0.) {
+        return None;
+    }
+
+    let det = e0 + e1 + e2;
+    if det == 0. {
+        return None;
+    }
+
+    // Range check on the scaled t before paying for the divide
+    let t_scaled = e0 * z0 + e1 * z1 + e2 * z2;
+    if det < 0. && (t_scaled >= ray_t.min * det || t_scaled <= ray_t.max * det) {
+        return None;
+    }
+    if det > 0. && (t_scaled <= ray_t.min * det || t_scaled >= ray_t.max * det) {
+        return None;
+    }
+
+    let inv_det = 1. / det;
+    let t = t_scaled * inv_det;
+
+    Some(([e0 * inv_det, e1 * inv_det, e2 * inv_det], t))
+}
+
+fn max_dimension(v: Vec3) -> usize {
+    if v.0 > v.1 {
+        if v.0 > v.2 { 0 } else { 2 }
+    } else if v.1 > v.2 {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod mesh_tests {
+    use super::*;
+    use crate::{random::Random, texture::Texture};
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    /// Unit square in the xy plane, split along its diagonal
+    fn square() -> MeshData {
+        MeshData {
+            positions: vec![
+                Vec3(0., 0., 0.),
+                Vec3(1., 0., 0.),
+                Vec3(1., 1., 0.),
+                Vec3(0., 1., 0.),
+            ],
+            faces: vec![
+                MeshFace {
+                    v: [0, 1, 2],
+                    n: None,
+                    uv: None,
+                    material: 0,
+                },
+                MeshFace {
+                    v: [0, 2, 3],
+                    n: None,
+                    uv: None,
+                    material: 0,
+                },
+            ],
+            materials: vec![grey()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_cracks_along_shared_edge() {
+        let mesh = TriangleMesh::new(square());
+
+        // Rays aimed exactly at the diagonal shared by both triangles
+        for i in 1..100 {
+            let s = i as f64 / 100.;
+            let r = Ray::new(Vec3(s, s, 1.), Vec3(0., 0., -1.));
+            let (hit, rec) = mesh.hit(&r, Interval::new(0.001, f64::INFINITY));
+            assert!(hit, "ray through ({s}, {s}) slipped through the mesh");
+            assert_eq!(rec.unwrap().t, 1.);
+        }
+
+        // Rays from skewed directions towards random points of the shared edge
+        for _ in 0..1000 {
+            let s = f64::rnd();
+            let origin = Vec3::rnd_rng(-3., 3.) + Vec3(0., 0., 5.);
+            let r = Ray::new(origin, Vec3(s, s, 0.) - origin);
+            assert!(mesh.hit(&r, Interval::new(0.001, f64::INFINITY)).0);
+        }
+    }
+
+    #[test]
+    fn barycentrics_and_normals() {
+        let mut data = square();
+        data.normals = vec![Vec3(0., 0., 1.)];
+        data.faces[0].n = Some([0, 0, 0]);
+        let mesh = TriangleMesh::new(data);
+
+        let r = Ray::new(Vec3(0.75, 0.25, -2.), Vec3(0., 0., 1.));
+        let (_, rec) = mesh.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+
+        assert_eq!(rec.t, 2.);
+        assert!((rec.p - Vec3(0.75, 0.25, 0.)).length() < 1e-12);
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3(0., 0., -1.));
+
+        let [p0, p1, p2] = [Vec3(0., 0., 0.), Vec3(1., 0., 0.), Vec3(1., 1., 0.)];
+        let (b, _) = intersect_triangle(&r, p0, p1, p2, Interval::new(0., 10.)).unwrap();
+        assert!((b[0] - 0.25).abs() < 1e-12);
+        assert!((b[1] - 0.5).abs() < 1e-12);
+        assert!((b[2] - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn vertex_colors_become_albedo() {
+        let mut data = square();
+        data.colors = vec![
+            Vec3(1., 0., 0.),
+            Vec3(0., 1., 0.),
+            Vec3(0., 0., 1.),
+            Vec3(1., 1., 1.),
+        ];
+        let mesh = TriangleMesh::new(data);
+
+        let r = Ray::new(Vec3(0.75, 0.25, 1.), Vec3(0., 0., -1.));
+        let (_, rec) = mesh.hit(&r, Interval::new(0.001, f64::INFINITY));
+        match rec.unwrap().mat {
+            Material::Lambertian {
+                albedo: Texture::Solid(albedo),
+            } => {
+                assert!((albedo - Vec3(0.25, 0.5, 0.25)).length() < 1e-12)
+            }
+            _ => panic!("expected a lambertian material"),
+        }
+    }
+
+    #[test]
+    fn respects_ray_interval() {
+        let mesh = TriangleMesh::new(square());
+        let r = Ray::new(Vec3(0.5, 0.25, 1.), Vec3(0., 0., -1.));
+
+        assert!(!mesh.hit(&r, Interval::new(0.001, 0.5)).0);
+        assert!(!mesh.hit(&r, Interval::new(1.5, f64::INFINITY)).0);
+        assert!(mesh.hit(&r, Interval::new(0.5, 1.5)).0);
+    }
+}
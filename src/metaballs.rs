@@ -0,0 +1,294 @@
+use crate::{
+    aabb::Aabb,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    sphere::sphere_uv,
+    vec3::{Vec3, dot, unit},
+};
+
+/// Halvings of the bracket once the march has stepped over the surface
+const REFINE_STEPS: usize = 60;
+
+/// One center of a blobby field. It adds `weight * (1 - d^2 / radius^2)^3` at distance `d`, which
+/// falls smoothly to nothing at `radius`
+#[derive(Debug, Clone, Copy)]
+pub struct Ball {
+    pub center: Vec3,
+    pub radius: f64,
+    pub weight: f64,
+}
+
+#[allow(dead_code)]
+impl Ball {
+    pub fn new(center: Vec3, radius: f64, weight: f64) -> Self {
+        Ball {
+            center,
+            radius,
+            weight,
+        }
+    }
+
+    fn field(&self, p: Vec3) -> f64 {
+        let falloff = 1. - (p - self.center).length_squared() / (self.radius * self.radius);
+        if falloff <= 0. {
+            0.
+        } else {
+            self.weight * falloff * falloff * falloff
+        }
+    }
+
+    fn gradient(&self, p: Vec3) -> Vec3 {
+        let r2 = self.radius * self.radius;
+        let falloff = 1. - (p - self.center).length_squared() / r2;
+        if falloff <= 0. {
+            Vec3(0., 0., 0.)
+        } else {
+            (-6. * self.weight * falloff * falloff / r2) * (p - self.center)
+        }
+    }
+
+    /// Where the ray is inside this ball's reach, if it gets there at all
+    fn span(&self, r: &Ray) -> Option<Interval> {
+        let oc = self.center - r.origin;
+        let a = r.direction.length_squared();
+        let h = dot(r.direction, oc);
+        let c = oc.length_squared() - self.radius * self.radius;
+
+        let discriminant = h * h - a * c;
+        if discriminant <= 0. {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        Some(Interval::new((h - root) / a, (h + root) / a))
+    }
+}
+
+/// Blobby surface where the summed field of a set of balls reaches `threshold`. Balls close
+/// together fuse into one smooth shape, and pull apart again as they separate.
+///
+/// On its own, a ball of weight 1 gives a sphere of radius `radius * sqrt(1 - threshold^(1/3))`.
+/// The ray is marched only where it passes within reach of some ball, with steps a fraction of
+/// the smallest radius it meets. Every ball is checked against each ray, so big crowds are
+/// better split into several `Metaballs` under a BVH.
+pub struct Metaballs {
+    balls: Vec<Ball>,
+    threshold: f64,
+    step_scale: f64,
+    mat: Material,
+    bbox: Aabb,
+}
+
+#[allow(dead_code)]
+impl Metaballs {
+    pub fn new(balls: Vec<Ball>, threshold: f64, mat: Material) -> Self {
+        let bbox = balls.iter().fold(Aabb::default(), |acc, b| {
+            let reach = Vec3::splat(b.radius);
+            Aabb::surrounding(&acc, &Aabb::from_points(b.center - reach, b.center + reach))
+        });
+
+        Metaballs {
+            balls,
+            threshold,
+            step_scale: 0.1,
+            mat,
+            bbox,
+        }
+    }
+
+    /// Steps as a fraction of the smallest radius. Smaller catches thinner necks between balls
+    pub fn with_step_scale(mut self, scale: f64) -> Self {
+        self.step_scale = scale.clamp(1e-3, 1.);
+        self
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    /// How far the field at `p` is over the threshold, counting only `balls`
+    fn excess(balls: &[&Ball], threshold: f64, p: Vec3) -> f64 {
+        balls.iter().map(|b| b.field(p)).sum::<f64>() - threshold
+    }
+
+    pub fn field(&self, p: Vec3) -> f64 {
+        self.balls.iter().map(|b| b.field(p)).sum()
+    }
+
+    pub fn gradient(&self, p: Vec3) -> Vec3 {
+        self.balls
+            .iter()
+            .fold(Vec3(0., 0., 0.), |acc, b| acc + b.gradient(p))
+    }
+}
+
+impl Hittable for Metaballs {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        if !self.bbox.hit(r, ray_t) {
+            return (false, None);
+        }
+
+        // Only the balls the ray comes within reach of can add anything along it
+        let mut reached: Vec<(Interval, &Ball)> = self
+            .balls
+            .iter()
+            .filter_map(|b| {
+                let span = Interval::overlap(b.span(r)?, ray_t);
+                (span.size() > 0.).then_some((span, b))
+            })
+            .collect();
+        if reached.is_empty() {
+            return (false, None);
+        }
+        reached.sort_by(|a, b| a.0.min.total_cmp(&b.0.min));
+
+        let balls: Vec<&Ball> = reached.iter().map(|(_, b)| *b).collect();
+        let smallest = balls.iter().map(|b| b.radius).fold(f64::INFINITY, f64::min);
+        let step = self.step_scale * smallest / r.direction.length();
+        let excess = |t: f64| Metaballs::excess(&balls, self.threshold, r.at(t));
+
+        // March each stretch where some ball overlaps, skipping the empty gaps between them
+        let mut found = None;
+        let mut i = 0;
+        while i < reached.len() && found.is_none() {
+            let mut span = reached[i].0;
+            i += 1;
+            while i < reached.len() && reached[i].0.min <= span.max {
+                span.max = span.max.max(reached[i].0.max);
+                i += 1;
+            }
+
+            let mut t0 = span.min;
+            let inside = excess(t0) > 0.;
+            while t0 < span.max {
+                let t1 = (t0 + step).min(span.max);
+                if (excess(t1) > 0.) != inside {
+                    found = Some(refine(&excess, t0, t1, inside));
+                    break;
+                }
+                t0 = t1;
+            }
+        }
+
+        let Some(t) = found else {
+            return (false, None);
+        };
+        if !ray_t.surrounds(t) {
+            return (false, None);
+        }
+
+        // The field falls away outwards, so the surface faces down its gradient
+        let p = r.at(t);
+        let outward_normal = -unit(self.gradient(p));
+        // Like spheres, by the way the surface faces
+        let (u, v) = sphere_uv(outward_normal);
+        let rec = HitRecord::new(r, t, p, outward_normal, self.mat.clone(), u, v);
+
+        (true, Some(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+/// Bisects the step from `t0` to `t1` down to where `excess` crosses zero
+fn refine(excess: &impl Fn(f64) -> f64, mut t0: f64, mut t1: f64, inside: bool) -> f64 {
+    for _ in 0..REFINE_STEPS {
+        let mid = 0.5 * (t0 + t1);
+        if (excess(mid) > 0.) == inside {
+            t0 = mid;
+        } else {
+            t1 = mid;
+        }
+    }
+    t1
+}
+
+#[cfg(test)]
+mod metaballs_tests {
+    use super::*;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn shoot(m: &Metaballs, origin: Vec3, direction: Vec3) -> Option<HitRecord> {
+        match m.hit(
+            &Ray::new(origin, direction),
+            Interval::new(0.001, f64::INFINITY),
+        ) {
+            (true, rec) => rec,
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn lone_ball_is_a_sphere() {
+        let threshold: f64 = 0.125;
+        let m = Metaballs::new(vec![Ball::new(Vec3(0., 0., 0.), 2., 1.)], threshold, grey());
+        let radius = 2. * (1. - threshold.cbrt()).sqrt();
+
+        let rec = shoot(&m, Vec3(0., 0., -5.), Vec3(0., 0., 1.)).unwrap();
+        assert!((rec.t - (5. - radius)).abs() < 1e-9);
+        assert!((rec.normal.2 + 1.).abs() < 1e-9);
+        assert!(rec.front_face);
+
+        // From the inside, out through the far side
+        let rec = shoot(&m, Vec3(0., 0., 0.), Vec3(1., 0., 0.)).unwrap();
+        assert!((rec.t - radius).abs() < 1e-9);
+        assert!(!rec.front_face);
+
+        assert!(shoot(&m, Vec3(0., 1.5, -5.), Vec3(0., 0., 1.)).is_none());
+    }
+
+    #[test]
+    fn nearby_balls_fuse() {
+        let pair = |gap: f64| {
+            Metaballs::new(
+                vec![
+                    Ball::new(Vec3(-gap / 2., 0., 0.), 1., 1.),
+                    Ball::new(Vec3(gap / 2., 0., 0.), 1., 1.),
+                ],
+                0.5,
+                grey(),
+            )
+        };
+        // Alone, each ball gives a sphere of radius about 0.45
+        let down_the_middle = |m: &Metaballs| shoot(m, Vec3(0., 5., 0.), Vec3(0., -1., 0.));
+
+        let close = pair(1.);
+        let rec = down_the_middle(&close).unwrap();
+        assert!(rec.p.1 > 0. && (rec.normal.1 - 1.).abs() < 1e-9);
+
+        assert!(down_the_middle(&pair(1.5)).is_none());
+    }
+
+    #[test]
+    fn normal_follows_the_gradient() {
+        let m = Metaballs::new(
+            vec![
+                Ball::new(Vec3(0., 0., 0.), 1.5, 1.),
+                Ball::new(Vec3(1., 0.5, 0.), 1., 0.8),
+            ],
+            0.3,
+            grey(),
+        );
+
+        let p = Vec3(0.3, 0.2, 0.1);
+        let h = 1e-6;
+        let numeric = Vec3(
+            m.field(p + Vec3(h, 0., 0.)) - m.field(p - Vec3(h, 0., 0.)),
+            m.field(p + Vec3(0., h, 0.)) - m.field(p - Vec3(0., h, 0.)),
+            m.field(p + Vec3(0., 0., h)) - m.field(p - Vec3(0., 0., h)),
+        ) / (2. * h);
+        assert!((numeric - m.gradient(p)).length() < 1e-6);
+
+        let rec = shoot(&m, Vec3(0.5, 5., 0.), Vec3(0., -1., 0.)).unwrap();
+        assert!((m.field(rec.p) - 0.3).abs() < 1e-9);
+        assert!((rec.normal - -unit(m.gradient(rec.p))).length() < 1e-9);
+    }
+}
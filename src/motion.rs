@@ -0,0 +1,224 @@
+use crate::{aabb::Aabb, transform::Transform, vec3::Vec3};
+
+/// Number of poses sampled per keyframe segment when bounding an animated transform
+const BOUNDS_SAMPLES: usize = 64;
+
+/// Where a point is over the course of the shutter interval
+#[derive(Debug, Clone)]
+pub enum Path {
+    Fixed(Vec3),
+    /// Moves from `start` at `t0` to `end` at `t1`, resting at either end outside that range
+    Linear {
+        start: Vec3,
+        end: Vec3,
+        t0: f64,
+        t1: f64,
+    },
+    /// Piecewise-linear through (time, position) keys, sorted by time
+    Keyframes(Vec<(f64, Vec3)>),
+}
+
+#[allow(dead_code)]
+impl Path {
+    pub fn linear(start: Vec3, end: Vec3, t0: f64, t1: f64) -> Path {
+        Path::Linear { start, end, t0, t1 }
+    }
+
+    /// Panics if `keys` is empty
+    pub fn keyframes(mut keys: Vec<(f64, Vec3)>) -> Path {
+        assert!(!keys.is_empty(), "Keyframed path needs at least one key");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Path::Keyframes(keys)
+    }
+
+    pub fn at(&self, time: f64) -> Vec3 {
+        match self {
+            Path::Fixed(p) => *p,
+            Path::Linear { start, end, t0, t1 } => {
+                let s = segment_fraction(time, *t0, *t1);
+                *start + s * (*end - *start)
+            }
+            Path::Keyframes(keys) => {
+                let (a, b, s) = bracket(keys, time);
+                a + s * (b - a)
+            }
+        }
+    }
+
+    /// Positions the path passes through in straight lines, so their hull bounds the whole path
+    pub fn corners(&self) -> Vec<Vec3> {
+        match self {
+            Path::Fixed(p) => vec![*p],
+            Path::Linear { start, end, .. } => vec![*start, *end],
+            Path::Keyframes(keys) => keys.iter().map(|k| k.1).collect(),
+        }
+    }
+}
+
+/// Translation, rotation and scale of an object at one moment.
+///
+/// Applied as scale, then rotation about x, y and z (in degrees), then translation. Animated
+/// transforms interpolate these components rather than the matrices, so spins stay rigid.
+#[derive(Debug, Clone, Copy)]
+pub struct Pose {
+    pub translate: Vec3,
+    pub rotate: Vec3,
+    pub scale: Vec3,
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Pose {
+            translate: Vec3(0., 0., 0.),
+            rotate: Vec3(0., 0., 0.),
+            scale: Vec3(1., 1., 1.),
+        }
+    }
+}
+
+impl Pose {
+    pub fn transform(&self) -> Transform {
+        Transform::scale(self.scale)
+            .then(&Transform::rotate_x(self.rotate.0))
+            .then(&Transform::rotate_y(self.rotate.1))
+            .then(&Transform::rotate_z(self.rotate.2))
+            .then(&Transform::translate(self.translate))
+    }
+
+    fn lerp(&self, other: &Pose, s: f64) -> Pose {
+        Pose {
+            translate: self.translate + s * (other.translate - self.translate),
+            rotate: self.rotate + s * (other.rotate - self.rotate),
+            scale: self.scale + s * (other.scale - self.scale),
+        }
+    }
+}
+
+/// A transform that changes over the shutter interval, keyframed by `Pose`
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    keys: Vec<(f64, Pose)>,
+}
+
+#[allow(dead_code)]
+impl AnimatedTransform {
+    /// Panics if `keys` is empty
+    pub fn new(mut keys: Vec<(f64, Pose)>) -> Self {
+        assert!(
+            !keys.is_empty(),
+            "Animated transform needs at least one key"
+        );
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        AnimatedTransform { keys }
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let keys = &self.keys;
+        if time <= keys[0].0 || keys.len() == 1 {
+            return keys[0].1.transform();
+        }
+
+        let i = keys.partition_point(|k| k.0 <= time);
+        if i == keys.len() {
+            return keys[i - 1].1.transform();
+        }
+
+        let (t0, a) = keys[i - 1];
+        let (t1, b) = keys[i];
+        a.lerp(&b, segment_fraction(time, t0, t1)).transform()
+    }
+
+    /// Box around `bbox` swept through the animation, from poses sampled densely along each segment
+    pub fn bbox(&self, bbox: &Aabb) -> Aabb {
+        let mut out = self.keys[0].1.transform().bbox(bbox);
+
+        for pair in self.keys.windows(2) {
+            let (t0, t1) = (pair[0].0, pair[1].0);
+            for i in 1..=BOUNDS_SAMPLES {
+                let time = t0 + (t1 - t0) * i as f64 / BOUNDS_SAMPLES as f64;
+                out = Aabb::surrounding(&out, &self.at(time).bbox(bbox));
+            }
+        }
+
+        out
+    }
+}
+
+/// Fraction of the way from `t0` to `t1`, clamped to [0, 1]
+fn segment_fraction(time: f64, t0: f64, t1: f64) -> f64 {
+    if t1 <= t0 {
+        return if time < t0 { 0. } else { 1. };
+    }
+    ((time - t0) / (t1 - t0)).clamp(0., 1.)
+}
+
+/// The two keys around `time` and how far between them it falls
+fn bracket(keys: &[(f64, Vec3)], time: f64) -> (Vec3, Vec3, f64) {
+    let i = keys.partition_point(|k| k.0 <= time);
+    if i == 0 {
+        return (keys[0].1, keys[0].1, 0.);
+    }
+    if i == keys.len() {
+        return (keys[i - 1].1, keys[i - 1].1, 0.);
+    }
+
+    let (t0, a) = keys[i - 1];
+    let (t1, b) = keys[i];
+    (a, b, segment_fraction(time, t0, t1))
+}
+
+#[cfg(test)]
+mod motion_tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn linear_path_clamps() {
+        let path = Path::linear(Vec3(0., 0., 0.), Vec3(2., 0., 0.), 0., 1.);
+        assert!(close(path.at(0.5), Vec3(1., 0., 0.)));
+        assert!(close(path.at(-1.), Vec3(0., 0., 0.)));
+        assert!(close(path.at(3.), Vec3(2., 0., 0.)));
+    }
+
+    #[test]
+    fn keyframed_path() {
+        let path = Path::keyframes(vec![
+            (1., Vec3(0., 1., 0.)),
+            (0., Vec3(0., 0., 0.)),
+            (2., Vec3(4., 1., 0.)),
+        ]);
+        assert!(close(path.at(0.5), Vec3(0., 0.5, 0.)));
+        assert!(close(path.at(1.5), Vec3(2., 1., 0.)));
+        assert!(close(path.at(10.), Vec3(4., 1., 0.)));
+        assert_eq!(path.corners().len(), 3);
+    }
+
+    #[test]
+    fn animated_transform_interpolates_pose() {
+        let spin = AnimatedTransform::new(vec![
+            (0., Pose::default()),
+            (
+                1.,
+                Pose {
+                    rotate: Vec3(0., 0., 90.),
+                    translate: Vec3(0., 0., 10.),
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        // Halfway through: 45 degrees round z and 5 units along it
+        let p = spin.at(0.5).point(Vec3(1., 0., 0.));
+        let h = 0.5f64.sqrt();
+        assert!(close(p, Vec3(h, h, 5.)));
+
+        // A rotating unit box sweeps out a bigger one than either end pose covers
+        let unit_box = Aabb::from_points(Vec3(-1., -1., -1.), Vec3(1., 1., 1.));
+        let swept = spin.bbox(&unit_box);
+        assert!(swept.x.max >= 2f64.sqrt() - 1e-3);
+        assert!(swept.z.max >= 11.);
+    }
+}
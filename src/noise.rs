@@ -0,0 +1,252 @@
+use crate::vec3::{Vec3, dot, unit};
+
+/// Lattice points per axis before gradient noise repeats
+const POINT_COUNT: usize = 256;
+
+/// Seeded source of smooth procedural noise: Perlin gradient noise, its fractal sums, and
+/// Worley cellular noise.
+///
+/// Everything is worked out from the seed with its own small generator, so the same seed gives
+/// the same pattern on every run and every machine.
+pub struct Noise {
+    seed: u64,
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+#[allow(dead_code)]
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = SplitMix(seed);
+
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                loop {
+                    let g = Vec3(rng.signed(), rng.signed(), rng.signed());
+                    let lensq = g.length_squared();
+                    if 1e-6 < lensq && lensq <= 1. {
+                        break unit(g);
+                    }
+                }
+            })
+            .collect();
+
+        Noise {
+            seed,
+            gradients,
+            perm_x: permutation(&mut rng),
+            perm_y: permutation(&mut rng),
+            perm_z: permutation(&mut rng),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Gradient noise at `p`, varying smoothly over about one unit. Within [-1, 1], and zero at
+    /// every lattice point
+    pub fn perlin(&self, p: Vec3) -> f64 {
+        let cell = |x: f64| x.floor();
+        let (fx, fy, fz) = (cell(p.0), cell(p.1), cell(p.2));
+        let (u, v, w) = (p.0 - fx, p.1 - fy, p.2 - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        // Hermite smoothing so the blend has no creases at cell walls
+        let smooth = |t: f64| t * t * (3. - 2. * t);
+        let (su, sv, sw) = (smooth(u), smooth(v), smooth(w));
+
+        let wrap = |n: i64| n.rem_euclid(POINT_COUNT as i64) as usize;
+        let mut sum = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.perm_x[wrap(i + di)]
+                        ^ self.perm_y[wrap(j + dj)]
+                        ^ self.perm_z[wrap(k + dk)];
+                    let offset = Vec3(u - di as f64, v - dj as f64, w - dk as f64);
+
+                    let weight = |s: f64, d: i64| if d == 1 { s } else { 1. - s };
+                    sum += weight(su, di)
+                        * weight(sv, dj)
+                        * weight(sw, dk)
+                        * dot(self.gradients[index], offset);
+                }
+            }
+        }
+
+        // Unit gradients keep the blend under sqrt(3) / 2, so scale that out to fill the range
+        (sum * 2. / 3f64.sqrt()).clamp(-1., 1.)
+    }
+
+    /// Sum of `octaves` layers of absolute noise, each at twice the frequency and half the
+    /// weight of the last. Rough and billowy, in [0, 1]
+    pub fn turbulence(&self, p: Vec3, octaves: u32) -> f64 {
+        self.layered(p, octaves, |n| n.abs())
+    }
+
+    /// Fractal Brownian motion - the signed version of `turbulence`, in [-1, 1]
+    pub fn fbm(&self, p: Vec3, octaves: u32) -> f64 {
+        self.layered(p, octaves, |n| n)
+    }
+
+    /// Weighted sum of `shape(perlin)` over octaves, scaled back by the total weight
+    fn layered(&self, p: Vec3, octaves: u32, shape: impl Fn(f64) -> f64) -> f64 {
+        let mut sum = 0.;
+        let mut total = 0.;
+        let mut weight = 1.;
+        let mut p = p;
+
+        for _ in 0..octaves.max(1) {
+            sum += weight * shape(self.perlin(p));
+            total += weight;
+            weight *= 0.5;
+            p = 2. * p;
+        }
+
+        sum / total
+    }
+
+    /// Worley cellular noise: distances from `p` to the nearest and second nearest of a scatter
+    /// of feature points, one in each unit cell. Only the 27 cells around `p` are searched, so
+    /// both are capped at 2
+    pub fn worley(&self, p: Vec3) -> (f64, f64) {
+        let (i, j, k) = (p.0.floor() as i64, p.1.floor() as i64, p.2.floor() as i64);
+
+        let mut nearest = f64::INFINITY;
+        let mut second = f64::INFINITY;
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let (ci, cj, ck) = (i + di, j + dj, k + dk);
+                    let mut rng = SplitMix(self.cell_hash(ci, cj, ck));
+                    let feature = Vec3(
+                        ci as f64 + rng.unit(),
+                        cj as f64 + rng.unit(),
+                        ck as f64 + rng.unit(),
+                    );
+
+                    let d = (feature - p).length();
+                    if d < nearest {
+                        second = nearest;
+                        nearest = d;
+                    } else if d < second {
+                        second = d;
+                    }
+                }
+            }
+        }
+
+        (nearest.min(2.), second.min(2.))
+    }
+
+    fn cell_hash(&self, i: i64, j: i64, k: i64) -> u64 {
+        let mut h = self.seed ^ 0x9e37_79b9_7f4a_7c15;
+        for n in [i, j, k] {
+            h = SplitMix(h ^ n as u64).next();
+        }
+        h
+    }
+}
+
+/// Shuffled 0..POINT_COUNT
+fn permutation(rng: &mut SplitMix) -> Vec<usize> {
+    let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+    for i in (1..POINT_COUNT).rev() {
+        let target = (rng.next() % (i as u64 + 1)) as usize;
+        p.swap(i, target);
+    }
+    p
+}
+
+/// SplitMix64 - tiny, fast and fixed, so seeded patterns never change under us
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// In [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// In [-1, 1)
+    fn signed(&mut self) -> f64 {
+        2. * self.unit() - 1.
+    }
+}
+
+#[cfg(test)]
+mod noise_tests {
+    use super::*;
+    use crate::random::Random;
+
+    fn sample_points() -> Vec<Vec3> {
+        (0..5000).map(|_| Vec3::rnd_rng(-50., 50.)).collect()
+    }
+
+    #[test]
+    fn stays_in_range() {
+        let noise = Noise::new(7);
+        let (mut low, mut high) = (0f64, 0f64);
+
+        for p in sample_points() {
+            let n = noise.perlin(p);
+            assert!((-1. ..=1.).contains(&n));
+            (low, high) = (low.min(n), high.max(n));
+
+            assert!((0. ..=1.).contains(&noise.turbulence(p, 7)));
+            assert!((-1. ..=1.).contains(&noise.fbm(p, 5)));
+
+            let (f1, f2) = noise.worley(p);
+            assert!(0. <= f1 && f1 <= f2 && f2 <= 2.);
+        }
+
+        // Uses a good part of the range rather than hugging zero
+        assert!(low < -0.4 && high > 0.4, "{low} {high}");
+    }
+
+    #[test]
+    fn smooth_and_zero_on_the_lattice() {
+        let noise = Noise::new(3);
+        assert_eq!(noise.perlin(Vec3(4., -2., 17.)), 0.);
+
+        for p in sample_points() {
+            let step = Vec3(1e-4, -1e-4, 1e-4);
+            assert!((noise.perlin(p) - noise.perlin(p + step)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_noise() {
+        let a = Noise::new(42);
+        let b = Noise::new(42);
+        let c = Noise::new(43);
+
+        let points = sample_points();
+        for &p in &points {
+            assert_eq!(a.perlin(p), b.perlin(p));
+            assert_eq!(a.turbulence(p, 7), b.turbulence(p, 7));
+            assert_eq!(a.worley(p), b.worley(p));
+        }
+
+        let differs = |f: &dyn Fn(&Noise, Vec3) -> f64| {
+            points.iter().filter(|&&p| f(&a, p) != f(&c, p)).count() > points.len() / 2
+        };
+        assert!(differs(&|n, p| n.perlin(p)));
+        assert!(differs(&|n, p| n.worley(p).0));
+
+        // Pinned values, so a change to the generator doesn't slip by and alter saved scenes
+        let p = Vec3(0.3, 1.7, -2.2);
+        assert_eq!(a.perlin(p), 0.20921569611861762);
+        assert_eq!(a.worley(p), (0.7975460650532791, 0.8524969384018264));
+    }
+}
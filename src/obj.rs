@@ -0,0 +1,589 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    bvh::Bvh,
+    curve::{Curve, CurveKind},
+    hittable_list::HittableList,
+    material::Material,
+    mesh::{MeshData, MeshFace, TriangleMesh},
+    subdivision::PolyMesh,
+    vec3::Vec3,
+};
+
+/// Loads a Wavefront OBJ file, along with any MTL libraries it references.
+///
+/// Faces without a `usemtl` (or naming a material that isn't defined) get `default_mat`.
+#[allow(dead_code)]
+pub fn load_obj(path: impl AsRef<Path>, default_mat: Material) -> io::Result<TriangleMesh> {
+    let path = path.as_ref();
+    let src = fs::read_to_string(path)?;
+    let materials = load_materials(path, &src)?;
+
+    Ok(TriangleMesh::new(parse_obj(&src, &materials, default_mat)?))
+}
+
+/// Loads an OBJ file as the control cage of a subdivision surface and refines it `levels`
+/// times - see `PolyMesh::subdivide`. Texture coordinates and normals in the file are dropped;
+/// the refined mesh gets smooth normals of its own.
+#[allow(dead_code)]
+pub fn load_obj_subdivided(
+    path: impl AsRef<Path>,
+    default_mat: Material,
+    levels: u32,
+) -> io::Result<TriangleMesh> {
+    let path = path.as_ref();
+    let src = fs::read_to_string(path)?;
+    let materials = load_materials(path, &src)?;
+
+    let cage = parse_obj_cage(&src, &materials, default_mat)?;
+    Ok(TriangleMesh::new(cage.subdivide(levels).to_mesh_data()))
+}
+
+/// Loads the cubic Bézier `curv` statements of an OBJ file as hair curves, with widths going
+/// from `root_width` to `tip_width` along each one. Faces and other curve types are skipped.
+#[allow(dead_code)]
+pub fn load_obj_curves(
+    path: impl AsRef<Path>,
+    root_width: f64,
+    tip_width: f64,
+    kind: CurveKind,
+    mat: Material,
+) -> io::Result<Bvh> {
+    let src = fs::read_to_string(path)?;
+
+    let mut list = HittableList::default();
+    for curve in parse_obj_curves(&src, root_width, tip_width, kind, mat)? {
+        list.add(curve.into_box());
+    }
+    Ok(Bvh::new(list))
+}
+
+/// Materials from every MTL library `src` names, resolved relative to the OBJ file at `path`
+fn load_materials(path: &Path, src: &str) -> io::Result<HashMap<String, Material>> {
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let mut materials = HashMap::new();
+    for lib in mtl_libs(src) {
+        let mtl_src = fs::read_to_string(dir.join(&lib))?;
+        materials.extend(parse_mtl(&mtl_src)?);
+    }
+    Ok(materials)
+}
+
+/// MTL library files named by `mtllib` statements
+fn mtl_libs(src: &str) -> Vec<PathBuf> {
+    src.lines()
+        .filter_map(|line| line.trim().strip_prefix("mtllib "))
+        .flat_map(|names| names.split_whitespace().map(PathBuf::from))
+        .collect()
+}
+
+/// Position, texture coordinate and normal indices of one face corner
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// An `f` statement before triangulation
+struct Polygon {
+    corners: Vec<Corner>,
+    material: usize,
+}
+
+pub fn parse_obj(
+    src: &str,
+    materials: &HashMap<String, Material>,
+    default_mat: Material,
+) -> io::Result<MeshData> {
+    let (mut data, polygons) = read_obj(src, materials, default_mat)?;
+
+    // Fan-triangulate polygons around their first corner
+    for Polygon { corners, material } in polygons {
+        for i in 1..corners.len() - 1 {
+            let tri = [corners[0], corners[i], corners[i + 1]];
+            data.faces.push(MeshFace {
+                v: tri.map(|c| c.0),
+                uv: all_some(tri.map(|c| c.1)),
+                n: all_some(tri.map(|c| c.2)),
+                material,
+            });
+        }
+    }
+
+    Ok(data)
+}
+
+/// Reads the positions and polygons of an OBJ file without splitting them into triangles
+pub fn parse_obj_cage(
+    src: &str,
+    materials: &HashMap<String, Material>,
+    default_mat: Material,
+) -> io::Result<PolyMesh> {
+    let (data, polygons) = read_obj(src, materials, default_mat)?;
+
+    Ok(PolyMesh {
+        positions: data.positions,
+        faces: polygons
+            .iter()
+            .map(|p| p.corners.iter().map(|c| c.0).collect())
+            .collect(),
+        face_materials: polygons.iter().map(|p| p.material).collect(),
+        materials: data.materials,
+        creases: Default::default(),
+    })
+}
+
+/// Vertex data and materials, with the faces left as polygons for the caller to split or keep
+fn read_obj(
+    src: &str,
+    materials: &HashMap<String, Material>,
+    default_mat: Material,
+) -> io::Result<(MeshData, Vec<Polygon>)> {
+    let mut data = MeshData {
+        materials: vec![default_mat],
+        ..Default::default()
+    };
+    let mut polygons = Vec::new();
+    // Index into `data.materials` for each material name that's been used so far
+    let mut used: HashMap<&str, usize> = HashMap::new();
+    let mut current = 0;
+
+    for (line_no, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let err = |msg: &str| invalid_data(format!("OBJ line {}: {msg}", line_no + 1));
+
+        match tokens.next() {
+            Some("v") => data
+                .positions
+                .push(parse_vec3(&mut tokens).ok_or_else(|| err("bad vertex"))?),
+            Some("vn") => data
+                .normals
+                .push(parse_vec3(&mut tokens).ok_or_else(|| err("bad normal"))?),
+            Some("vt") => {
+                let u = tokens.next().and_then(|s| s.parse().ok());
+                let v = tokens.next().and_then(|s| s.parse().ok()).unwrap_or(0.);
+                data.uvs
+                    .push((u.ok_or_else(|| err("bad texture coordinate"))?, v));
+            }
+            Some("usemtl") => {
+                let name = tokens.next().unwrap_or("");
+                current = match (used.get(name), materials.get_key_value(name)) {
+                    (Some(&i), _) => i,
+                    (None, Some((key, mat))) => {
+                        data.materials.push(mat.clone());
+                        used.insert(key.as_str(), data.materials.len() - 1);
+                        data.materials.len() - 1
+                    }
+                    (None, None) => 0,
+                };
+            }
+            Some("f") => {
+                let corners = tokens
+                    .map(|c| parse_corner(c, &data))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| err("bad face index"))?;
+                if corners.len() < 3 {
+                    return Err(err("face needs at least three vertices"));
+                }
+                polygons.push(Polygon {
+                    corners,
+                    material: current,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok((data, polygons))
+}
+
+/// Splits each `curv` into its cubic pieces. Only `cstype bezier` with `deg 3` is supported,
+/// and the parameter range on the statement is assumed to cover the whole curve.
+pub fn parse_obj_curves(
+    src: &str,
+    root_width: f64,
+    tip_width: f64,
+    kind: CurveKind,
+    mat: Material,
+) -> io::Result<Vec<Curve>> {
+    let mut positions = Vec::new();
+    let mut curves = Vec::new();
+    let (mut bezier, mut degree) = (true, 3);
+
+    // Free-form statements tend to be long, so they can run on over lines ending in `\`
+    let mut statement = String::new();
+    let mut start = 0;
+    for (line_no, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if statement.is_empty() {
+            start = line_no;
+        }
+        if let Some(rest) = line.strip_suffix('\\') {
+            statement.push_str(rest);
+            statement.push(' ');
+            continue;
+        }
+        statement.push_str(line);
+
+        let mut tokens = statement.split_whitespace();
+        let err = |msg: &str| invalid_data(format!("OBJ line {}: {msg}", start + 1));
+
+        match tokens.next() {
+            Some("v") => positions.push(parse_vec3(&mut tokens).ok_or_else(|| err("bad vertex"))?),
+            Some("cstype") => bezier = tokens.collect::<Vec<_>>() == ["bezier"],
+            Some("deg") => degree = tokens.next().and_then(|s| s.parse().ok()).unwrap_or(0),
+            Some("curv") => {
+                if !bezier || degree != 3 {
+                    return Err(err("only cubic Bézier curves are supported"));
+                }
+                let indices = tokens
+                    .skip(2)
+                    .map(|s| resolve_index(s, positions.len()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| err("bad curve index"))?;
+                if indices.len() < 4 || (indices.len() - 1) % 3 != 0 {
+                    return Err(err("cubic curve needs 3n + 1 control points"));
+                }
+
+                // Pieces share their end points, and the width runs across the whole curve
+                let pieces = (indices.len() - 1) / 3;
+                let width = |f: f64| (1. - f) * root_width + f * tip_width;
+                for i in 0..pieces {
+                    let cp = [0, 1, 2, 3].map(|k| positions[indices[3 * i + k]]);
+                    let (from, to) = (i as f64 / pieces as f64, (i + 1) as f64 / pieces as f64);
+                    curves.push(Curve::new(cp, width(from), width(to), kind, mat.clone()));
+                }
+            }
+            _ => {}
+        }
+        statement.clear();
+    }
+
+    Ok(curves)
+}
+
+/// Parses a face corner (`v`, `v/vt`, `v//vn` or `v/vt/vn`) into zero-based indices
+fn parse_corner(corner: &str, data: &MeshData) -> Option<Corner> {
+    let mut parts = corner.split('/');
+    let v = resolve_index(parts.next()?, data.positions.len())?;
+    let vt = match parts.next() {
+        Some("") | None => None,
+        Some(s) => Some(resolve_index(s, data.uvs.len())?),
+    };
+    let vn = match parts.next() {
+        Some("") | None => None,
+        Some(s) => Some(resolve_index(s, data.normals.len())?),
+    };
+
+    Some((v, vt, vn))
+}
+
+/// OBJ indices are one-based, and negative ones count back from the latest element
+fn resolve_index(s: &str, len: usize) -> Option<usize> {
+    let i: i64 = s.parse().ok()?;
+    let resolved = if i < 0 { len as i64 + i } else { i - 1 };
+
+    if (0..len as i64).contains(&resolved) {
+        Some(resolved as usize)
+    } else {
+        None
+    }
+}
+
+fn all_some(indices: [Option<usize>; 3]) -> Option<[usize; 3]> {
+    Some([indices[0]?, indices[1]?, indices[2]?])
+}
+
+fn parse_vec3<'a>(tokens: &mut impl Iterator<Item = &'a str>) -> Option<Vec3> {
+    let mut next = || tokens.next()?.parse::<f64>().ok();
+    Some(Vec3(next()?, next()?, next()?))
+}
+
+/// Properties of one `newmtl` block that matter for picking a `Material`
+struct MtlEntry {
+    kd: Vec3,
+    ks: Vec3,
+    ns: f64,
+    ni: f64,
+    dissolve: f64,
+    illum: i32,
+}
+
+impl Default for MtlEntry {
+    fn default() -> Self {
+        MtlEntry {
+            kd: Vec3(0.8, 0.8, 0.8),
+            ks: Vec3(0., 0., 0.),
+            ns: 0.,
+            ni: 1.,
+            dissolve: 1.,
+            illum: 2,
+        }
+    }
+}
+
+impl MtlEntry {
+    /// Maps the MTL illumination model onto the closest of our materials:
+    /// transparent surfaces become `Dialectric`, reflective or specular-dominated ones `Metal`, and
+    /// everything else `Lambertian`.
+    fn to_material(&self) -> Material {
+        let transparent = self.dissolve < 1. || matches!(self.illum, 4 | 6 | 7 | 9);
+        if transparent {
+            let refraction_index = if self.ni > 1. { self.ni } else { 1.5 };
+            return Material::Dialectric { refraction_index };
+        }
+
+        let reflective = matches!(self.illum, 3 | 5 | 8);
+        if reflective || max_component(self.ks) > max_component(self.kd) {
+            // Phong exponent to an approximate roughness - Ns ranges over [0, 1000]
+            let fuzz = (2. / (self.ns.max(0.) + 2.)).sqrt().min(1.);
+            return Material::Metal {
+                albedo: self.ks.into(),
+                fuzz,
+            };
+        }
+
+        Material::Lambertian {
+            albedo: self.kd.into(),
+        }
+    }
+}
+
+fn max_component(v: Vec3) -> f64 {
+    v.0.max(v.1).max(v.2)
+}
+
+pub fn parse_mtl(src: &str) -> io::Result<HashMap<String, Material>> {
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+
+    for (line_no, line) in src.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let err = |msg: &str| invalid_data(format!("MTL line {}: {msg}", line_no + 1));
+
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            let name = tokens.next().ok_or_else(|| err("material needs a name"))?;
+            entries.push((name.to_string(), MtlEntry::default()));
+            continue;
+        }
+
+        let entry = match entries.last_mut() {
+            Some((_, entry)) => entry,
+            None => continue,
+        };
+        let mut scalar = || -> io::Result<f64> {
+            tokens
+                .next()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| err(&format!("bad value for {keyword}")))
+        };
+
+        match keyword {
+            "Kd" => entry.kd = parse_vec3(&mut tokens).ok_or_else(|| err("bad Kd"))?,
+            "Ks" => entry.ks = parse_vec3(&mut tokens).ok_or_else(|| err("bad Ks"))?,
+            "Ns" => entry.ns = scalar()?,
+            "Ni" => entry.ni = scalar()?,
+            "d" => entry.dissolve = scalar()?,
+            "Tr" => entry.dissolve = 1. - scalar()?,
+            "illum" => entry.illum = scalar()? as i32,
+            _ => {}
+        }
+    }
+
+    Ok(entries
+        .into_iter()
+        .map(|(name, entry)| (name, entry.to_material()))
+        .collect())
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod obj_tests {
+    use super::*;
+    use crate::{hittable::Hittable, interval::Interval, ray::Ray, texture::Texture};
+
+    const MTL: &str = "
+        newmtl red
+        Kd 0.8 0.1 0.1
+        Ks 0.0 0.0 0.0
+        illum 2
+
+        newmtl mirror
+        Kd 0.1 0.1 0.1
+        Ks 0.9 0.9 0.9
+        Ns 1000
+        illum 3
+
+        newmtl glass
+        Ni 1.45
+        d 0.1
+    ";
+
+    const OBJ: &str = "
+        # unit quad, one polygon
+        mtllib scene.mtl
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 1
+        usemtl red
+        f 1/1/1 2/2/1 3/3/1 4/4/1
+        usemtl mirror
+        f -4//1 -2//1 -1//1
+    ";
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    #[test]
+    fn mtl_maps_to_materials() {
+        let mats = parse_mtl(MTL).unwrap();
+
+        assert!(
+            matches!(mats["red"], Material::Lambertian { albedo: Texture::Solid(albedo) } if albedo == Vec3(0.8, 0.1, 0.1))
+        );
+        match mats["mirror"] {
+            Material::Metal {
+                albedo: Texture::Solid(albedo),
+                fuzz,
+            } => {
+                assert_eq!(albedo, Vec3(0.9, 0.9, 0.9));
+                assert!(fuzz < 0.05);
+            }
+            _ => panic!("mirror should be metal"),
+        }
+        assert!(
+            matches!(mats["glass"], Material::Dialectric { refraction_index } if refraction_index == 1.45)
+        );
+    }
+
+    #[test]
+    fn parses_polygons_and_indices() {
+        let mats = parse_mtl(MTL).unwrap();
+        let data = parse_obj(OBJ, &mats, grey()).unwrap();
+
+        assert_eq!(mtl_libs(OBJ), vec![PathBuf::from("scene.mtl")]);
+        assert_eq!(data.positions.len(), 4);
+        // Quad fans into two triangles, plus one triangle with negative indices
+        assert_eq!(data.faces.len(), 3);
+        assert_eq!(data.faces[1].v, [0, 2, 3]);
+        assert_eq!(data.faces[1].uv, Some([0, 2, 3]));
+        assert_eq!(data.faces[2].v, [0, 2, 3]);
+        assert_eq!(data.faces[2].uv, None);
+        assert_eq!(data.faces[2].n, Some([0, 0, 0]));
+
+        // Default material plus the two that were used
+        assert_eq!(data.materials.len(), 3);
+        assert!(matches!(
+            data.materials[data.faces[0].material],
+            Material::Lambertian { .. }
+        ));
+        assert!(matches!(
+            data.materials[data.faces[2].material],
+            Material::Metal { .. }
+        ));
+    }
+
+    #[test]
+    fn loaded_mesh_is_hittable() {
+        let data = parse_obj(OBJ, &HashMap::new(), grey()).unwrap();
+        let mesh = TriangleMesh::new(data);
+
+        let r = Ray::new(Vec3(0.25, 0.75, 3.), Vec3(0., 0., -1.));
+        let (hit, rec) = mesh.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+
+        assert!(hit);
+        assert_eq!(rec.t, 3.);
+        assert!((rec.u - 0.25).abs() < 1e-12);
+        assert!((rec.v - 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        let src = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+        let Err(err) = parse_obj(src, &HashMap::new(), grey()) else {
+            panic!("face referencing a missing vertex should fail");
+        };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("line 3"));
+    }
+
+    #[test]
+    fn curv_statements_become_curves() {
+        let src = "
+            v 0 0 0
+            v 0 1 0
+            v 0 2 0
+            v 0 3 0
+            v 0 4 0
+            v 0 5 0
+            v 0 6 0
+            cstype bezier
+            deg 3
+            curv 0.0 1.0 1 2 3 4 \\
+                5 6 7
+            end
+        ";
+        let curves = parse_obj_curves(src, 0.2, 0.1, CurveKind::Ribbon, grey()).unwrap();
+        assert_eq!(curves.len(), 2);
+
+        // The second piece starts halfway along, at the in-between width of 0.15
+        let r = Ray::new(Vec3(0.07, 3.3, 5.), Vec3(0., 0., -1.));
+        assert!(curves[1].hit(&r, Interval::new(0.001, f64::INFINITY)).0);
+        let r = Ray::new(Vec3(0.07, 5.9, 5.), Vec3(0., 0., -1.));
+        assert!(!curves[1].hit(&r, Interval::new(0.001, f64::INFINITY)).0);
+
+        let wrong_count = "v 0 0 0\nv 1 0 0\ncurv 0 1 1 2";
+        assert!(parse_obj_curves(wrong_count, 0.1, 0.1, CurveKind::Tube, grey()).is_err());
+        let rational = "v 0 0 0\ncstype rat bezier\ncurv 0 1 1 1 1 1";
+        assert!(parse_obj_curves(rational, 0.1, 0.1, CurveKind::Tube, grey()).is_err());
+    }
+
+    #[test]
+    fn quad_cage_subdivides_into_a_rounded_mesh() {
+        let src = "
+            v -1 -1 -1\nv 1 -1 -1\nv -1 1 -1\nv 1 1 -1
+            v -1 -1 1\nv 1 -1 1\nv -1 1 1\nv 1 1 1
+            f 1 3 4 2\nf 5 6 8 7\nf 1 2 6 5\nf 3 7 8 4\nf 1 5 7 3
+            usemtl red
+            f 2 4 8 6
+        ";
+        let materials = parse_mtl(MTL).unwrap();
+        let cage = parse_obj_cage(src, &materials, grey()).unwrap();
+        assert_eq!(cage.faces.len(), 6);
+        assert!(cage.faces.iter().all(|f| f.len() == 4));
+        assert_eq!(cage.face_materials[5], 1);
+
+        let mesh = TriangleMesh::new(cage.subdivide(2).to_mesh_data());
+        assert_eq!(mesh.len(), 6 * 16 * 2);
+
+        // The smoothed surface sits inside the cage, and the +x side keeps its material
+        let r = Ray::new(Vec3(5., 0., 0.), Vec3(-1., 0., 0.));
+        let (_, rec) = mesh.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert!(rec.t > 4.05 && rec.t < 4.5);
+        assert!((rec.normal - Vec3(1., 0., 0.)).length() < 1e-9);
+        assert!(
+            matches!(rec.mat, Material::Lambertian { albedo: Texture::Solid(albedo) } if albedo.0 == 0.8)
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use std::{array, ops};
+
+use crate::{
+    hittable::HitRecord,
+    interval::{self, Interval},
+    ray::Ray,
+    vec3::Vec3,
+};
+
+/// Rays traced side by side in a packet - four doubles fill an AVX register
+pub const LANES: usize = 4;
+
+/// One value per lane
+pub type Lanes = [f64; LANES];
+
+/// Which lanes of a packet are still being traced
+pub type Mask = [bool; LANES];
+
+/// Closest hit found so far in each lane
+pub type PacketHits = [Option<HitRecord>; LANES];
+
+/// `LANES` vectors stored one component at a time, so the same operation on every lane compiles
+/// down to a few wide instructions
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+#[repr(C, align(32))]
+pub struct Vec3Lanes {
+    pub x: Lanes,
+    pub y: Lanes,
+    pub z: Lanes,
+}
+
+#[allow(dead_code)]
+impl Vec3Lanes {
+    pub fn from_fn(mut f: impl FnMut(usize) -> Vec3) -> Self {
+        let mut v = Vec3Lanes::default();
+        for lane in 0..LANES {
+            v.set(lane, f(lane));
+        }
+        v
+    }
+
+    pub fn splat(p: Vec3) -> Self {
+        Vec3Lanes::from_fn(|_| p)
+    }
+
+    pub fn lane(&self, lane: usize) -> Vec3 {
+        Vec3(self.x[lane], self.y[lane], self.z[lane])
+    }
+
+    pub fn set(&mut self, lane: usize, p: Vec3) {
+        self.x[lane] = p.0;
+        self.y[lane] = p.1;
+        self.z[lane] = p.2;
+    }
+
+    pub fn dot(&self, other: &Vec3Lanes) -> Lanes {
+        array::from_fn(|i| self.x[i] * other.x[i] + self.y[i] * other.y[i] + self.z[i] * other.z[i])
+    }
+
+    pub fn length_squared(&self) -> Lanes {
+        self.dot(self)
+    }
+}
+
+impl ops::Sub for Vec3Lanes {
+    type Output = Vec3Lanes;
+
+    fn sub(self, other: Vec3Lanes) -> Vec3Lanes {
+        Vec3Lanes {
+            x: array::from_fn(|i| self.x[i] - other.x[i]),
+            y: array::from_fn(|i| self.y[i] - other.y[i]),
+            z: array::from_fn(|i| self.z[i] - other.z[i]),
+        }
+    }
+}
+
+/// Up to `LANES` rays traced together. Coherent rays, like a pixel's camera samples, mostly visit
+/// the same BVH nodes, so each node's box is loaded once for all of them
+pub struct RayPacket {
+    pub origin: Vec3Lanes,
+    pub direction: Vec3Lanes,
+    pub inv_direction: Vec3Lanes,
+    pub time: Lanes,
+    /// Lanes holding one of the rays the packet was built from
+    pub filled: Mask,
+}
+
+#[allow(dead_code)]
+impl RayPacket {
+    /// Packs the first `LANES` of `rays`. Any lanes left over are unfilled
+    pub fn new(rays: &[Ray]) -> Self {
+        let mut packet = RayPacket {
+            origin: Vec3Lanes::default(),
+            direction: Vec3Lanes::default(),
+            inv_direction: Vec3Lanes::default(),
+            time: [0.; LANES],
+            filled: [false; LANES],
+        };
+
+        for (lane, r) in rays.iter().take(LANES).enumerate() {
+            packet.origin.set(lane, r.origin);
+            packet.direction.set(lane, r.direction);
+            packet.time[lane] = r.time;
+            packet.filled[lane] = true;
+        }
+        packet.inv_direction.x = array::from_fn(|i| 1. / packet.direction.x[i]);
+        packet.inv_direction.y = array::from_fn(|i| 1. / packet.direction.y[i]);
+        packet.inv_direction.z = array::from_fn(|i| 1. / packet.direction.z[i]);
+
+        packet
+    }
+
+    /// The ray in one lane, on its own
+    pub fn ray(&self, lane: usize) -> Ray {
+        Ray::with_time(
+            self.origin.lane(lane),
+            self.direction.lane(lane),
+            self.time[lane],
+        )
+    }
+}
+
+/// Indices of the lanes set in `mask`
+pub fn active(mask: Mask) -> impl Iterator<Item = usize> {
+    (0..LANES).filter(move |&lane| mask[lane])
+}
+
+/// What's left of `ray_t` in each lane once it stops at the closest hit so far. Lanes outside
+/// `mask` get nothing
+pub fn lane_intervals(mask: Mask, ray_t: Interval, hits: &PacketHits) -> [Interval; LANES] {
+    array::from_fn(|lane| match &hits[lane] {
+        _ if !mask[lane] => interval::EMPTY,
+        Some(rec) => Interval::new(ray_t.min, rec.t),
+        None => ray_t,
+    })
+}
+
+#[cfg(test)]
+mod packet_tests {
+    use super::*;
+    use crate::{
+        bvh::Bvh, hittable::Hittable, hittable_list::HittableList, material::Material, quad::Quad,
+        random::Random, sphere::Sphere, torus::Torus,
+    };
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn scene() -> HittableList {
+        let mut world = HittableList::default();
+        world.add(
+            Quad::new(
+                Vec3(-50., -1., -50.),
+                Vec3(0., 0., 100.),
+                Vec3(100., 0., 0.),
+                grey(),
+            )
+            .into_box(),
+        );
+        world.add(Torus::new(Vec3(0., 0., -3.), 1., 0.3, grey()).into_box());
+        for _ in 0..200 {
+            let center = Vec3::rnd_rng(-8., 8.) - Vec3(0., 0., 10.);
+            world.add(Sphere::new(center, f64::rnd_rng(0.1, 1.), grey()).into_box());
+        }
+        world
+    }
+
+    /// Bundles of rays from one spot through neighbouring points, like a pixel's samples
+    fn coherent_rays() -> Vec<Ray> {
+        (0..2000)
+            .map(|_| {
+                let origin = Vec3(0., 2., 10.) + 0.01 * Vec3::rnd_rng(-1., 1.);
+                let toward = Vec3::rnd_rng(-1., 1.) - Vec3(0., 0.5, 1.);
+                Ray::with_time(origin, toward, f64::rnd())
+            })
+            .collect()
+    }
+
+    fn check_parity(world: &dyn Hittable) {
+        let ray_t = Interval::new(0., f64::INFINITY);
+        let rays = coherent_rays();
+        let mut hits = 0;
+
+        // A short last chunk leaves lanes unfilled
+        for chunk in rays[..rays.len() - 1].chunks(LANES) {
+            let packet = RayPacket::new(chunk);
+            let mut packet_hits = PacketHits::default();
+            world.hit_packet(&packet, packet.filled, ray_t, &mut packet_hits);
+
+            for (lane, r) in chunk.iter().enumerate() {
+                let (scalar_hit, scalar_rec) = world.hit(r, ray_t);
+                assert_eq!(scalar_hit, packet_hits[lane].is_some());
+
+                if let (Some(a), Some(b)) = (scalar_rec, &packet_hits[lane]) {
+                    hits += 1;
+                    assert_eq!(a.t, b.t);
+                    assert_eq!(a.p, b.p);
+                    assert_eq!(a.normal, b.normal);
+                    assert_eq!((a.u, a.v), (b.u, b.v));
+                }
+            }
+            assert!(packet_hits[chunk.len()..].iter().all(Option::is_none));
+        }
+
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn list_matches_scalar() {
+        check_parity(&scene());
+    }
+
+    #[test]
+    fn bvh_matches_scalar() {
+        check_parity(&Bvh::new(scene()));
+    }
+
+    #[test]
+    fn masked_lanes_are_left_alone() {
+        let world = Bvh::new(scene());
+        let rays: Vec<Ray> = (0..LANES)
+            .map(|_| Ray::new(Vec3(0., 2., 10.), Vec3(0., -1., -1.)))
+            .collect();
+        let packet = RayPacket::new(&rays);
+
+        let mask: Mask = array::from_fn(|lane| lane % 2 == 0);
+        let mut hits = PacketHits::default();
+        world.hit_packet(&packet, mask, Interval::new(0., f64::INFINITY), &mut hits);
+
+        for lane in 0..LANES {
+            assert_eq!(hits[lane].is_some(), mask[lane]);
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    material::Material,
+    mesh::{MeshData, MeshFace, TriangleMesh},
+    vec3::Vec3,
+};
+
+/// Loads a Stanford PLY mesh in `ascii` or `binary_little_endian` format.
+///
+/// Per-vertex normals, uvs and colors are picked up when present. Colors replace the albedo of
+/// `mat` when it's `Lambertian`, so colored scans render without a hand-written material.
+#[allow(dead_code)]
+pub fn load_ply(path: impl AsRef<Path>, mat: Material) -> io::Result<TriangleMesh> {
+    let bytes = fs::read(path)?;
+    Ok(TriangleMesh::new(parse_ply(&bytes, mat)?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// Linear [0, 1] value of a color channel of this type. Integer channels are gamma encoded
+    /// like image files, so undo the same gamma 2 that `write_color` applies on the way out.
+    /// Floats are taken as linear already
+    fn linear_color(self, value: f64) -> f64 {
+        let encoded = match self {
+            Scalar::U8 => value / 255.,
+            Scalar::U16 => value / 65535.,
+            _ => return value,
+        };
+        encoded * encoded
+    }
+}
+
+enum Property {
+    Scalar {
+        name: String,
+        ty: Scalar,
+    },
+    List {
+        name: String,
+        count: Scalar,
+        item: Scalar,
+    },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    body_start: usize,
+}
+
+fn parse_header(bytes: &[u8]) -> io::Result<Header> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut offset = 0;
+
+    let mut lines = bytes.split(|&b| b == b'\n');
+    match lines.next() {
+        Some(magic) if magic.trim_ascii() == b"ply" => offset += magic.len() + 1,
+        _ => return Err(invalid_data("not a PLY file".to_string())),
+    }
+
+    for line in lines {
+        offset += line.len() + 1;
+        let line = std::str::from_utf8(line)
+            .map_err(|_| invalid_data("PLY header isn't valid text".to_string()))?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.as_slice() {
+            ["end_header"] => {
+                return Ok(Header {
+                    format: format.ok_or_else(|| invalid_data("PLY format missing".to_string()))?,
+                    elements,
+                    body_start: offset,
+                });
+            }
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", other, _] => {
+                return Err(invalid_data(format!("unsupported PLY format {other}")));
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid_data(format!("bad element count {count}")))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let property = Property::List {
+                    name: name.to_string(),
+                    count: scalar_type(count)?,
+                    item: scalar_type(item)?,
+                };
+                current_element(&mut elements)?.properties.push(property);
+            }
+            ["property", ty, name] => {
+                let property = Property::Scalar {
+                    name: name.to_string(),
+                    ty: scalar_type(ty)?,
+                };
+                current_element(&mut elements)?.properties.push(property);
+            }
+            _ => {}
+        }
+    }
+
+    Err(invalid_data("PLY header has no end_header".to_string()))
+}
+
+fn scalar_type(name: &str) -> io::Result<Scalar> {
+    Scalar::parse(name).ok_or_else(|| invalid_data(format!("unknown PLY type {name}")))
+}
+
+fn current_element(elements: &mut [Element]) -> io::Result<&mut Element> {
+    elements
+        .last_mut()
+        .ok_or_else(|| invalid_data("PLY property before any element".to_string()))
+}
+
+/// Reads values out of the body of the file, whichever encoding it uses
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary(&'a [u8]),
+}
+
+impl Body<'_> {
+    fn read(&mut self, ty: Scalar) -> io::Result<f64> {
+        match self {
+            Body::Ascii(tokens) => tokens
+                .next()
+                .and_then(|t| t.parse().ok())
+                .ok_or_else(|| invalid_data("PLY body ended early or has a bad value".to_string())),
+            Body::Binary(bytes) => {
+                if bytes.len() < ty.size() {
+                    return Err(invalid_data("PLY body ended early".to_string()));
+                }
+                let (b, rest) = bytes.split_at(ty.size());
+                *bytes = rest;
+
+                Ok(match ty {
+                    Scalar::I8 => b[0] as i8 as f64,
+                    Scalar::U8 => b[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    Scalar::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Scalar::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Scalar::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    Scalar::F64 => f64::from_le_bytes(b.try_into().unwrap()),
+                })
+            }
+        }
+    }
+}
+
+pub fn parse_ply(bytes: &[u8], mat: Material) -> io::Result<MeshData> {
+    let header = parse_header(bytes)?;
+    let body = &bytes[header.body_start.min(bytes.len())..];
+
+    let mut reader = match header.format {
+        Format::Ascii => Body::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| invalid_data("ASCII PLY body isn't valid text".to_string()))?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian => Body::Binary(body),
+    };
+
+    let mut data = MeshData {
+        materials: vec![mat],
+        ..Default::default()
+    };
+
+    for element in &header.elements {
+        let has = |name: &str| {
+            element
+                .properties
+                .iter()
+                .any(|p| matches!(p, Property::Scalar { name: n, .. } if n == name))
+        };
+        let has_normals = has("nx") && has("ny") && has("nz");
+        let has_colors = has("red") && has("green") && has("blue");
+        let has_uvs = (has("u") && has("v")) || (has("s") && has("t"));
+
+        for _ in 0..element.count {
+            let mut vertex = VertexRecord::default();
+            let mut indices = Vec::new();
+
+            for property in &element.properties {
+                match property {
+                    Property::Scalar { name, ty } => {
+                        let value = reader.read(*ty)?;
+                        vertex.set(name, value, *ty);
+                    }
+                    Property::List { name, count, item } => {
+                        let n = reader.read(*count)? as usize;
+                        let wanted = name == "vertex_indices" || name == "vertex_index";
+                        for _ in 0..n {
+                            let value = reader.read(*item)?;
+                            if wanted {
+                                indices.push(value as usize);
+                            }
+                        }
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    data.positions.push(vertex.position);
+                    if has_normals {
+                        data.normals.push(vertex.normal);
+                    }
+                    if has_colors {
+                        data.colors.push(vertex.color);
+                    }
+                    if has_uvs {
+                        data.uvs.push(vertex.uv);
+                    }
+                }
+                "face" => {
+                    if indices.len() < 3 {
+                        return Err(invalid_data(
+                            "PLY face needs at least three vertices".into(),
+                        ));
+                    }
+                    if let Some(&bad) = indices.iter().find(|&&i| i >= data.positions.len()) {
+                        return Err(invalid_data(format!("PLY face references vertex {bad}")));
+                    }
+
+                    for i in 1..indices.len() - 1 {
+                        let v = [indices[0], indices[i], indices[i + 1]];
+                        data.faces.push(MeshFace {
+                            v,
+                            n: (!data.normals.is_empty()).then_some(v),
+                            uv: (!data.uvs.is_empty()).then_some(v),
+                            material: 0,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(data)
+}
+
+/// Scalar properties of the vertex element that we know what to do with
+#[derive(Default)]
+struct VertexRecord {
+    position: Vec3,
+    normal: Vec3,
+    color: Vec3,
+    uv: (f64, f64),
+}
+
+impl VertexRecord {
+    fn set(&mut self, name: &str, value: f64, ty: Scalar) {
+        match name {
+            "x" => self.position.0 = value,
+            "y" => self.position.1 = value,
+            "z" => self.position.2 = value,
+            "nx" => self.normal.0 = value,
+            "ny" => self.normal.1 = value,
+            "nz" => self.normal.2 = value,
+            "red" => self.color.0 = ty.linear_color(value),
+            "green" => self.color.1 = ty.linear_color(value),
+            "blue" => self.color.2 = ty.linear_color(value),
+            "u" | "s" => self.uv.0 = value,
+            "v" | "t" => self.uv.1 = value,
+            _ => {}
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod ply_tests {
+    use super::*;
+    use crate::{hittable::Hittable, interval::Interval, ray::Ray, texture::Texture};
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    const ASCII: &str = "ply
+format ascii 1.0
+comment unit square with colored corners
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 128 128 128
+4 0 1 2 3
+";
+
+    #[test]
+    fn ascii_with_colors() {
+        let data = parse_ply(ASCII.as_bytes(), grey()).unwrap();
+
+        assert_eq!(data.positions.len(), 4);
+        assert_eq!(data.colors[1], Vec3(0., 1., 0.));
+        // Mid grey in the file is about a quarter once linearized, as for image textures
+        assert!((data.colors[3] - Vec3::splat(0.252)).length() < 1e-3);
+        assert!(data.normals.is_empty());
+        // Quad fans into two triangles
+        assert_eq!(data.faces.len(), 2);
+        assert_eq!(data.faces[1].v, [0, 2, 3]);
+
+        let mesh = TriangleMesh::new(data);
+        let r = Ray::new(Vec3(0.75, 0.25, 1.), Vec3(0., 0., -1.));
+        let (_, rec) = mesh.hit(&r, Interval::new(0.001, f64::INFINITY));
+        match rec.unwrap().mat {
+            Material::Lambertian {
+                albedo: Texture::Solid(albedo),
+            } => {
+                assert!((albedo - Vec3(0.25, 0.5, 0.25)).length() < 1e-12)
+            }
+            _ => panic!("expected vertex colors on a lambertian material"),
+        }
+    }
+
+    #[test]
+    fn binary_little_endian_with_normals() {
+        let mut bytes = b"ply
+format binary_little_endian 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+element material 1
+property double shininess
+element face 1
+property list uchar uint vertex_index
+end_header
+"
+        .to_vec();
+
+        let vertices = [[0f32, 0., 0.], [2., 0., 0.], [0., 2., 0.]];
+        for v in vertices {
+            for c in v.iter().chain(&[0f32, 0., 1.]) {
+                bytes.extend(c.to_le_bytes());
+            }
+        }
+        // An element we don't use still has to be skipped correctly
+        bytes.extend(30f64.to_le_bytes());
+        bytes.push(3);
+        for i in [0u32, 1, 2] {
+            bytes.extend(i.to_le_bytes());
+        }
+
+        let data = parse_ply(&bytes, grey()).unwrap();
+        assert_eq!(data.positions[1], Vec3(2., 0., 0.));
+        assert_eq!(data.normals[2], Vec3(0., 0., 1.));
+        assert_eq!(data.faces.len(), 1);
+        assert_eq!(data.faces[0].n, Some([0, 1, 2]));
+
+        let mesh = TriangleMesh::new(data);
+        let r = Ray::new(Vec3(0.5, 0.5, -1.), Vec3(0., 0., 1.));
+        let (hit, rec) = mesh.hit(&r, Interval::new(0.001, f64::INFINITY));
+        assert!(hit);
+        assert_eq!(rec.unwrap().t, 1.);
+    }
+
+    #[test]
+    fn truncated_body_is_an_error() {
+        let truncated = &ASCII[..ASCII.len() - 6];
+        assert!(parse_ply(truncated.as_bytes(), grey()).is_err());
+        assert!(parse_ply(b"not a ply", grey()).is_err());
+    }
+}
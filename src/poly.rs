@@ -0,0 +1,232 @@
+//! Real root finding for the polynomials that come out of ray-surface intersection.
+//!
+//! Coefficients are in ascending order of power, so `[c0, c1, c2]` is `c0 + c1 x + c2 x^2`.
+
+/// Iteration cap for refining a single bracketed root
+const MAX_ITERATIONS: usize = 100;
+
+/// Real roots of a t^2 + b t + c in ascending order, in the form that avoids cancellation.
+/// Falls back to the linear solution when `a` is zero. When it's merely tiny, the far root is
+/// huge but the near one still comes out accurately.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> [Option<f64>; 2] {
+    if a == 0. {
+        return if b == 0. {
+            [None, None]
+        } else {
+            [Some(-c / b), None]
+        };
+    }
+
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return [None, None];
+    }
+
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (t0, t1) = if q == 0. { (0., 0.) } else { (q / a, c / q) };
+
+    [Some(t0.min(t1)), Some(t0.max(t1))]
+}
+
+/// Value of the polynomial at `x`
+pub fn eval(coeffs: &[f64], x: f64) -> f64 {
+    coeffs.iter().rev().fold(0., |acc, &c| acc * x + c)
+}
+
+/// Value and first derivative at `x`, in one Horner pass
+fn eval_with_derivative(coeffs: &[f64], x: f64) -> (f64, f64) {
+    coeffs
+        .iter()
+        .rev()
+        .fold((0., 0.), |(p, dp), &c| (p * x + c, dp * x + p))
+}
+
+/// Rough size of the rounding error when evaluating at `x`
+fn eval_error(coeffs: &[f64], x: f64) -> f64 {
+    let magnitude = coeffs
+        .iter()
+        .rev()
+        .fold(0., |acc, &c| acc * x.abs() + c.abs());
+    8. * f64::EPSILON * magnitude
+}
+
+fn derivative(coeffs: &[f64]) -> Vec<f64> {
+    coeffs
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, &c)| i as f64 * c)
+        .collect()
+}
+
+/// Drops leading coefficients that are negligible next to the rest, so the degree is honest
+fn trim(coeffs: &[f64]) -> &[f64] {
+    let largest = coeffs.iter().fold(0., |m: f64, c| m.max(c.abs()));
+    let degree = coeffs
+        .iter()
+        .rposition(|c| c.abs() > 1e-14 * largest)
+        .map_or(0, |i| i + 1);
+    &coeffs[..degree]
+}
+
+/// Every real root in [lo, hi], ascending.
+///
+/// Roots of the derivative split the interval into pieces where the polynomial is monotone, so
+/// each piece holds at most one root, which is then pinned down with safeguarded Newton steps.
+/// The derivative's roots come from the same procedure one degree down. A turning point that
+/// only touches zero - a double root, like a ray grazing a surface - has no sign change around
+/// it, so those are caught by checking the turning points themselves.
+pub fn real_roots(coeffs: &[f64], lo: f64, hi: f64) -> Vec<f64> {
+    let coeffs = trim(coeffs);
+
+    match coeffs.len() {
+        0 | 1 => return Vec::new(),
+        2 => {
+            let x = -coeffs[0] / coeffs[1];
+            return if x >= lo && x <= hi {
+                vec![x]
+            } else {
+                Vec::new()
+            };
+        }
+        _ => {}
+    }
+
+    let turning_points = real_roots(&derivative(coeffs), lo, hi);
+
+    let mut knots = Vec::with_capacity(turning_points.len() + 2);
+    knots.push(lo);
+    knots.extend(turning_points.iter().copied().filter(|&x| x > lo && x < hi));
+    knots.push(hi);
+
+    // Values near enough to zero to be rounding noise count as roots, and are snapped to zero so
+    // the pieces either side don't go looking for a spurious crossing right next to them
+    let mut roots = Vec::new();
+    let values: Vec<f64> = knots
+        .iter()
+        .map(|&x| {
+            let f = eval(coeffs, x);
+            if f.abs() <= eval_error(coeffs, x) {
+                roots.push(x);
+                0.
+            } else {
+                f
+            }
+        })
+        .collect();
+
+    for i in 0..knots.len() - 1 {
+        if values[i] * values[i + 1] < 0. {
+            roots.push(refine(coeffs, knots[i], knots[i + 1], values[i]));
+        }
+    }
+
+    roots.sort_by(f64::total_cmp);
+    roots.dedup_by(|a, b| (*a - *b).abs() <= 1e-9 * (1. + b.abs()));
+    roots
+}
+
+/// The root between `a` and `b`, where the polynomial changes sign, via Newton's method that
+/// falls back to bisection whenever a step would leave the bracket
+fn refine(coeffs: &[f64], a: f64, b: f64, fa: f64) -> f64 {
+    // Keep the bracket oriented so the polynomial is negative at `neg` and positive at `pos`
+    let (mut neg, mut pos) = if fa < 0. { (a, b) } else { (b, a) };
+    let mut x = 0.5 * (a + b);
+
+    for _ in 0..MAX_ITERATIONS {
+        let (f, df) = eval_with_derivative(coeffs, x);
+        if f == 0. {
+            return x;
+        }
+        if f < 0. {
+            neg = x;
+        } else {
+            pos = x;
+        }
+
+        let (lo, hi) = (neg.min(pos), neg.max(pos));
+        let newton = x - f / df;
+        let next = if df != 0. && newton > lo && newton < hi {
+            newton
+        } else {
+            0.5 * (lo + hi)
+        };
+
+        if (next - x).abs() <= 4. * f64::EPSILON * (1. + next.abs()) {
+            return next;
+        }
+        x = next;
+    }
+
+    x
+}
+
+#[cfg(test)]
+mod poly_tests {
+    use super::*;
+
+    /// Coefficients of the product of (x - r) over `roots`
+    fn from_roots(roots: &[f64]) -> Vec<f64> {
+        roots.iter().fold(vec![1.], |acc, &r| {
+            let mut next = vec![0.; acc.len() + 1];
+            for (i, &c) in acc.iter().enumerate() {
+                next[i + 1] += c;
+                next[i] -= r * c;
+            }
+            next
+        })
+    }
+
+    fn assert_roots(found: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(found.len(), expected.len(), "found {found:?}");
+        for (f, e) in found.iter().zip(expected) {
+            assert!(
+                (f - e).abs() < tolerance,
+                "found {found:?}, expected {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        assert_eq!(solve_quadratic(1., -3., 2.), [Some(1.), Some(2.)]);
+        assert_eq!(solve_quadratic(0., 2., -4.), [Some(2.), None]);
+        assert_eq!(solve_quadratic(1., 0., 1.), [None, None]);
+
+        // Tiny root that the textbook formula loses to cancellation
+        let [small, _] = solve_quadratic(1., -1e8, 1.);
+        assert!((small.unwrap() - 1e-8).abs() < 1e-20);
+    }
+
+    #[test]
+    fn quartic_with_simple_roots() {
+        let roots = [-3., 0.5, 1., 4.];
+        let found = real_roots(&from_roots(&roots), -10., 10.);
+        assert_roots(&found, &roots, 1e-12);
+
+        // Only the ones inside the interval
+        let found = real_roots(&from_roots(&roots), 0., 2.);
+        assert_roots(&found, &[0.5, 1.], 1e-12);
+    }
+
+    #[test]
+    fn double_and_close_roots() {
+        let found = real_roots(&from_roots(&[1., 1., 2., 3.]), -10., 10.);
+        assert_roots(&found, &[1., 2., 3.], 1e-7);
+
+        let found = real_roots(&from_roots(&[1., 1. + 1e-6, 5., 6.]), -10., 10.);
+        assert_roots(&found, &[1., 1. + 1e-6, 5., 6.], 1e-9);
+    }
+
+    #[test]
+    fn no_real_roots() {
+        // (x^2 + 1)(x^2 + 4)
+        assert!(real_roots(&[4., 0., 5., 0., 1.], -100., 100.).is_empty());
+    }
+
+    #[test]
+    fn degenerate_leading_coefficient() {
+        let found = real_roots(&[-2., 1., 0., 0., 0.], -10., 10.);
+        assert_roots(&found, &[2.], 1e-12);
+    }
+}
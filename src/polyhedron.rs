@@ -0,0 +1,226 @@
+use crate::{
+    aabb::Aabb,
+    global_stuff::gamma,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    vec3::{Vec3, cross, dot, perpendicular, unit},
+};
+
+/// Half-space `dot(normal, x) <= offset`, with `normal` pointing out of the solid
+#[derive(Debug, Clone, Copy)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub offset: f64,
+}
+
+#[allow(dead_code)]
+impl Plane {
+    /// Plane through `point`, facing along `normal`
+    pub fn through(point: Vec3, normal: Vec3) -> Plane {
+        let normal = unit(normal);
+        Plane {
+            normal,
+            offset: dot(normal, point),
+        }
+    }
+
+    fn distance(&self, p: Vec3) -> f64 {
+        dot(self.normal, p) - self.offset
+    }
+}
+
+/// Convex solid bounded by the intersection of half-spaces.
+///
+/// The planes must enclose a bounded region. Surface (u, v) are distances along two tangents of
+/// the face that was hit, in world units.
+pub struct ConvexPolyhedron {
+    planes: Vec<Plane>,
+    mat: Material,
+    bbox: Aabb,
+}
+
+#[allow(dead_code)]
+impl ConvexPolyhedron {
+    pub fn new(planes: Vec<Plane>, mat: Material) -> Self {
+        let bbox = vertices(&planes)
+            .into_iter()
+            .fold(Aabb::default(), |acc, v| {
+                Aabb::surrounding(&acc, &Aabb::from_points(v, v))
+            });
+
+        ConvexPolyhedron {
+            planes,
+            mat,
+            bbox: bbox.pad_to_minimums(),
+        }
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+}
+
+/// Corners of the solid: every point where three planes meet that's inside all the others
+fn vertices(planes: &[Plane]) -> Vec<Vec3> {
+    let mut out = Vec::new();
+    let n = planes.len();
+
+    for i in 0..n {
+        for j in i + 1..n {
+            for k in j + 1..n {
+                let (a, b, c) = (planes[i], planes[j], planes[k]);
+                let det = dot(a.normal, cross(b.normal, c.normal));
+                if det.abs() < 1e-12 {
+                    continue;
+                }
+
+                // Cramer's rule for the three plane equations
+                let p = (a.offset * cross(b.normal, c.normal)
+                    + b.offset * cross(c.normal, a.normal)
+                    + c.offset * cross(a.normal, b.normal))
+                    / det;
+
+                let tolerance = 1e-9 * (1. + p.length());
+                if planes.iter().all(|pl| pl.distance(p) <= tolerance) {
+                    out.push(p);
+                }
+            }
+        }
+    }
+
+    out
+}
+
+impl Hittable for ConvexPolyhedron {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        // Clip the line against every half-space, remembering which planes set the bounds
+        let mut t_near = f64::NEG_INFINITY;
+        let mut t_far = f64::INFINITY;
+        let (mut near_plane, mut far_plane) = (0, 0);
+
+        for (i, plane) in self.planes.iter().enumerate() {
+            let denom = dot(plane.normal, r.direction);
+            let dist = -plane.distance(r.origin);
+
+            if denom == 0. {
+                if dist < 0. {
+                    return (false, None);
+                }
+                continue;
+            }
+
+            let t = dist / denom;
+            if denom < 0. {
+                if t > t_near {
+                    t_near = t;
+                    near_plane = i;
+                }
+            } else if t < t_far {
+                t_far = t;
+                far_plane = i;
+            }
+
+            if t_near > t_far {
+                return (false, None);
+            }
+        }
+
+        let (t, plane) = if ray_t.surrounds(t_near) {
+            (t_near, self.planes[near_plane])
+        } else if ray_t.surrounds(t_far) {
+            (t_far, self.planes[far_plane])
+        } else {
+            return (false, None);
+        };
+
+        // Projecting onto the plane leaves only the rounding of the projection itself
+        let p = r.at(t);
+        let p = p - plane.distance(p) * plane.normal;
+        let tangent = perpendicular(plane.normal);
+        let bitangent = cross(plane.normal, tangent);
+
+        let (u, v) = (dot(p, tangent), dot(p, bitangent));
+        let mut rec = HitRecord::new(r, t, p, plane.normal, self.mat.clone(), u, v);
+        rec.p_error = gamma(7) * (p.abs() + Vec3::splat(plane.offset.abs()));
+        (true, Some(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod polyhedron_tests {
+    use super::*;
+    use crate::{box_shape::BoxShape, random::Random};
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn cube_planes() -> Vec<Plane> {
+        let axes = [Vec3(1., 0., 0.), Vec3(0., 1., 0.), Vec3(0., 0., 1.)];
+        axes.iter()
+            .flat_map(|&a| [Plane::through(a, a), Plane::through(-a, -a)])
+            .collect()
+    }
+
+    #[test]
+    fn cube_matches_box_shape() {
+        let poly = ConvexPolyhedron::new(cube_planes(), grey());
+        let b = BoxShape::new(Vec3(-1., -1., -1.), Vec3(1., 1., 1.), grey());
+
+        let bbox = poly.bounding_box();
+        assert!((bbox.min() - Vec3(-1., -1., -1.)).length() < 1e-9);
+        assert!((bbox.max() - Vec3(1., 1., 1.)).length() < 1e-9);
+
+        for _ in 0..1000 {
+            let r = Ray::new(Vec3::rnd_rng(-3., 3.), Vec3::rnd_rng(-1., 1.));
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+            let (poly_hit, poly_rec) = poly.hit(&r, ray_t);
+            let (box_hit, box_rec) = b.hit(&r, ray_t);
+
+            assert_eq!(poly_hit, box_hit);
+            if let (Some(a), Some(b)) = (poly_rec, box_rec) {
+                assert!((a.t - b.t).abs() < 1e-9);
+                assert!((a.normal - b.normal).length() < 1e-9);
+                assert_eq!(a.front_face, b.front_face);
+            }
+        }
+    }
+
+    #[test]
+    fn octahedron() {
+        let mut planes = Vec::new();
+        for sx in [-1., 1.] {
+            for sy in [-1., 1.] {
+                for sz in [-1., 1.] {
+                    let n = Vec3(sx, sy, sz);
+                    planes.push(Plane::through(Vec3(sx, 0., 0.), n));
+                }
+            }
+        }
+        let poly = ConvexPolyhedron::new(planes, grey());
+
+        let bbox = poly.bounding_box();
+        assert!((bbox.max() - Vec3(1., 1., 1.)).length() < 1e-9);
+
+        // Straight down onto the top vertex's face
+        let r = Ray::new(Vec3(0.1, 5., 0.1), Vec3(0., -1., 0.));
+        let (hit, rec) = poly.hit(&r, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert!(hit);
+        assert!((rec.t - 4.2).abs() < 1e-9);
+        assert!(rec.front_face);
+        assert!((rec.normal - unit(Vec3(1., 1., 1.))).length() < 1e-9);
+
+        // Inside the cube's corner region, but outside the octahedron
+        let corner = Ray::new(Vec3(0.8, 0.8, -5.), Vec3(0., 0., 1.));
+        assert!(!poly.hit(&corner, Interval::new(0.001, f64::INFINITY)).0);
+    }
+}
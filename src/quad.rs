@@ -0,0 +1,289 @@
+use crate::{
+    aabb::Aabb,
+    global_stuff::gamma,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    random::Random,
+    ray::Ray,
+    vec3::{Vec3, cross, dot, random_in_unit_disk, unit},
+};
+
+/// Which part of the (q, u, v) plane counts as the surface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanarShape {
+    /// q + a*u + b*v for a, b in [0, 1]
+    Parallelogram,
+    /// q + a*u + b*v for a, b >= 0 and a + b <= 1
+    Triangle,
+    /// q + a*u + b*v for a^2 + b^2 <= 1 - an ellipse centered on q when u and v differ
+    Disk,
+}
+
+/// Flat primitive spanned by a point `q` and two edge vectors `u` and `v`
+pub struct Quad {
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    normal: Vec3,
+    d: f64,
+    shape: PlanarShape,
+    mat: Material,
+    bbox: Aabb,
+}
+
+#[allow(dead_code)]
+impl Quad {
+    /// Parallelogram with corner `q` and edges `u`, `v`
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, mat: Material) -> Self {
+        Quad::with_shape(q, u, v, PlanarShape::Parallelogram, mat)
+    }
+
+    /// Triangle with vertices q, q + u and q + v
+    pub fn triangle(q: Vec3, u: Vec3, v: Vec3, mat: Material) -> Self {
+        Quad::with_shape(q, u, v, PlanarShape::Triangle, mat)
+    }
+
+    /// Disk around `center` whose radii are given by `u` and `v`
+    pub fn disk(center: Vec3, u: Vec3, v: Vec3, mat: Material) -> Self {
+        Quad::with_shape(center, u, v, PlanarShape::Disk, mat)
+    }
+
+    pub fn with_shape(q: Vec3, u: Vec3, v: Vec3, shape: PlanarShape, mat: Material) -> Self {
+        let n = cross(u, v);
+        let normal = unit(n);
+        let d = dot(normal, q);
+        let w = n / dot(n, n);
+
+        let bbox = match shape {
+            PlanarShape::Parallelogram => Aabb::surrounding(
+                &Aabb::from_points(q, q + u + v),
+                &Aabb::from_points(q + u, q + v),
+            ),
+            PlanarShape::Triangle => {
+                Aabb::surrounding(&Aabb::from_points(q, q + u), &Aabb::from_points(q, q + v))
+            }
+            PlanarShape::Disk => {
+                // Half-extent of an ellipse along each axis is the length of (u_i, v_i)
+                let extent = Vec3(u.0.hypot(v.0), u.1.hypot(v.1), u.2.hypot(v.2));
+                Aabb::from_points(q - extent, q + extent)
+            }
+        };
+
+        Quad {
+            q,
+            u,
+            v,
+            w,
+            normal,
+            d,
+            shape,
+            mat,
+            bbox: bbox.pad_to_minimums(),
+        }
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    pub fn area(&self) -> f64 {
+        let parallelogram = cross(self.u, self.v).length();
+        match self.shape {
+            PlanarShape::Parallelogram => parallelogram,
+            PlanarShape::Triangle => 0.5 * parallelogram,
+            PlanarShape::Disk => std::f64::consts::PI * parallelogram,
+        }
+    }
+
+    /// Uniformly random point on the surface
+    fn random_point(&self) -> Vec3 {
+        let (a, b) = match self.shape {
+            PlanarShape::Parallelogram => (f64::rnd(), f64::rnd()),
+            PlanarShape::Triangle => {
+                // Fold the far half of the unit square back over the triangle
+                let (a, b) = (f64::rnd(), f64::rnd());
+                if a + b > 1. { (1. - a, 1. - b) } else { (a, b) }
+            }
+            PlanarShape::Disk => {
+                let p = random_in_unit_disk();
+                (p.0, p.1)
+            }
+        };
+        self.q + a * self.u + b * self.v
+    }
+
+    /// Returns the surface (u, v) for plane coordinates (a, b), or None if the point is outside
+    /// the shape
+    fn interior(&self, a: f64, b: f64) -> Option<(f64, f64)> {
+        match self.shape {
+            PlanarShape::Parallelogram => {
+                let unit_interval = Interval::new(0., 1.);
+                if unit_interval.contains(a) && unit_interval.contains(b) {
+                    Some((a, b))
+                } else {
+                    None
+                }
+            }
+            PlanarShape::Triangle => {
+                if a >= 0. && b >= 0. && a + b <= 1. {
+                    Some((a, b))
+                } else {
+                    None
+                }
+            }
+            PlanarShape::Disk => {
+                if a * a + b * b <= 1. {
+                    Some((0.5 * (a + 1.), 0.5 * (b + 1.)))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        let denom = dot(self.normal, r.direction);
+
+        // Ray is parallel to the plane. Relative to the direction's length, so short directions
+        // aren't mistaken for parallel ones
+        if denom.abs() < 1e-8 * r.direction.length() {
+            return (false, None);
+        }
+
+        let t = (self.d - dot(self.normal, r.origin)) / denom;
+        if !ray_t.surrounds(t) {
+            return (false, None);
+        }
+
+        let p = r.at(t);
+        let planar_hitpt = p - self.q;
+        let alpha = dot(self.w, cross(planar_hitpt, self.v));
+        let beta = dot(self.w, cross(self.u, planar_hitpt));
+
+        let Some((u, v)) = self.interior(alpha, beta) else {
+            return (false, None);
+        };
+
+        // Rebuilt from the plane coordinates, the point lands on the plane to within a few
+        // roundings however inexact t was
+        let (along_u, along_v) = (alpha * self.u, beta * self.v);
+        let p = self.q + along_u + along_v;
+
+        let mut rec = HitRecord::new(r, t, p, self.normal, self.mat.clone(), u, v);
+        rec.p_error = gamma(5) * (self.q.abs() + along_u.abs() + along_v.abs());
+        (true, Some(rec))
+    }
+
+    fn samples_as_light(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        let (true, Some(rec)) = self.hit(r, Interval::new(0., f64::INFINITY)) else {
+            return 0.;
+        };
+
+        // Area density turned into solid angle by distance squared over the foreshortening
+        let distance_squared = rec.t * rec.t * r.direction.length_squared();
+        let cosine = dot(r.direction, self.normal).abs() / r.direction.length();
+        distance_squared / (cosine * self.area())
+    }
+
+    fn random_toward(&self, origin: Vec3, _time: f64) -> Vec3 {
+        self.random_point() - origin
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod quad_tests {
+    use super::*;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn shoot(obj: &Quad, x: f64, y: f64) -> Option<HitRecord> {
+        let r = Ray::new(Vec3(x, y, 5.), Vec3(0., 0., -1.));
+        obj.hit(&r, Interval::new(0.001, f64::INFINITY)).1
+    }
+
+    #[test]
+    fn parallelogram_hit_and_uv() {
+        let quad = Quad::new(Vec3(0., 0., 0.), Vec3(2., 0., 0.), Vec3(0., 4., 0.), grey());
+
+        let rec = shoot(&quad, 1., 1.).unwrap();
+        assert_eq!(rec.t, 5.);
+        assert!((rec.u - 0.5).abs() < 1e-12);
+        assert!((rec.v - 0.25).abs() < 1e-12);
+        // u x v points at +z, toward the ray origin
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3(0., 0., 1.));
+
+        assert!(shoot(&quad, 2.5, 1.).is_none());
+        assert!(shoot(&quad, 1., -0.1).is_none());
+    }
+
+    #[test]
+    fn back_face() {
+        let quad = Quad::new(Vec3(0., 0., 0.), Vec3(0., 4., 0.), Vec3(2., 0., 0.), grey());
+        let rec = shoot(&quad, 1., 1.).unwrap();
+
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3(0., 0., 1.));
+    }
+
+    #[test]
+    fn short_directions_still_hit() {
+        let quad = Quad::new(Vec3(0., 0., 0.), Vec3(2., 0., 0.), Vec3(0., 4., 0.), grey());
+
+        for scale in [1e-12, 1e-4, 1., 1e6] {
+            let r = Ray::new(Vec3(1., 1., 5.), Vec3(0., 0., -scale));
+            let rec = quad.hit(&r, Interval::new(0., f64::INFINITY)).1.unwrap();
+            assert!((rec.t * scale - 5.).abs() < 1e-9);
+        }
+
+        // Skimming along the plane is still a miss
+        let r = Ray::new(Vec3(-1., 1., 0.), Vec3(1e-6, 0., 1e-16));
+        assert!(quad.hit(&r, Interval::new(0., f64::INFINITY)).1.is_none());
+    }
+
+    #[test]
+    fn triangle_interior() {
+        let tri = Quad::triangle(Vec3(0., 0., 0.), Vec3(1., 0., 0.), Vec3(0., 1., 0.), grey());
+
+        assert!(shoot(&tri, 0.2, 0.2).is_some());
+        assert!(shoot(&tri, 0.6, 0.6).is_none());
+    }
+
+    #[test]
+    fn disk_interior() {
+        let disk = Quad::disk(Vec3(0., 0., 0.), Vec3(2., 0., 0.), Vec3(0., 1., 0.), grey());
+
+        let rec = shoot(&disk, 0., 0.).unwrap();
+        assert!((rec.u - 0.5).abs() < 1e-12);
+        assert!((rec.v - 0.5).abs() < 1e-12);
+        assert!(shoot(&disk, 1.9, 0.).is_some());
+        assert!(shoot(&disk, 1.9, 0.9).is_none());
+
+        let bbox = disk.bounding_box();
+        assert!((bbox.x.max - 2.).abs() < 1e-12);
+        assert!((bbox.y.max - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn parallel_ray_misses() {
+        let quad = Quad::new(Vec3(0., 0., 0.), Vec3(1., 0., 0.), Vec3(0., 1., 0.), grey());
+        let r = Ray::new(Vec3(0.5, 0.5, 1.), Vec3(1., 0., 0.));
+        assert!(!quad.hit(&r, Interval::new(0.001, f64::INFINITY)).0);
+    }
+}
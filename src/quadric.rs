@@ -0,0 +1,362 @@
+use std::f64::consts::PI;
+
+use crate::{
+    aabb::Aabb,
+    global_stuff::degrees_to_radians,
+    global_stuff::gamma,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    poly::solve_quadratic,
+    ray::Ray,
+    vec3::{Vec3, unit},
+};
+
+/// Surfaces of revolution around the y axis whose squared radius is a quadratic in height.
+///
+/// Every kind is the zero set of `x^2 + z^2 - (k0 + k1 y + k2 y^2)`, negative inside, so one
+/// intersection routine covers them all.
+#[derive(Debug, Clone, Copy)]
+pub enum QuadricKind {
+    Cylinder {
+        radius: f64,
+    },
+    /// `radius` at y = 0, narrowing to the apex at y = `height`
+    Cone {
+        radius: f64,
+        height: f64,
+    },
+    /// Cup with its tip at the origin, `radius` wide at y = `height`
+    Paraboloid {
+        radius: f64,
+        height: f64,
+    },
+    /// Hyperboloid of one sheet, `waist` wide at y = 0 and widening to `waist * sqrt(2)` at
+    /// y = +-`flare`
+    Hyperboloid {
+        waist: f64,
+        flare: f64,
+    },
+}
+
+impl QuadricKind {
+    /// (k0, k1, k2) of the squared radius at height y
+    fn coefficients(&self) -> (f64, f64, f64) {
+        match *self {
+            QuadricKind::Cylinder { radius } => (radius * radius, 0., 0.),
+            QuadricKind::Cone { radius, height } => {
+                let s = (radius / height).powi(2);
+                (s * height * height, -2. * s * height, s)
+            }
+            QuadricKind::Paraboloid { radius, height } => (0., radius * radius / height, 0.),
+            QuadricKind::Hyperboloid { waist, flare } => {
+                let a2 = waist * waist;
+                (a2, 0., a2 / (flare * flare))
+            }
+        }
+    }
+}
+
+/// A finite quadric standing on `center`, clipped to [y_min, y_max] and optionally to a partial
+/// sweep around its axis, with optional flat caps on the open ends.
+pub struct Quadric {
+    k: (f64, f64, f64),
+    center: Vec3,
+    y_min: f64,
+    y_max: f64,
+    phi_max: f64,
+    caps: bool,
+    mat: Material,
+}
+
+#[allow(dead_code)]
+impl Quadric {
+    /// Cylinder from `center` up to `center.y + height`
+    pub fn cylinder(center: Vec3, radius: f64, height: f64, mat: Material) -> Self {
+        Quadric::new(QuadricKind::Cylinder { radius }, center, 0., height, mat)
+    }
+
+    /// Cone with its base on `center` and apex `height` above it
+    pub fn cone(center: Vec3, radius: f64, height: f64, mat: Material) -> Self {
+        Quadric::new(
+            QuadricKind::Cone { radius, height },
+            center,
+            0.,
+            height,
+            mat,
+        )
+    }
+
+    /// Paraboloid with its tip on `center`, opening upward
+    pub fn paraboloid(center: Vec3, radius: f64, height: f64, mat: Material) -> Self {
+        Quadric::new(
+            QuadricKind::Paraboloid { radius, height },
+            center,
+            0.,
+            height,
+            mat,
+        )
+    }
+
+    /// Hyperboloid centered on `center`, spanning `half_height` above and below its waist
+    pub fn hyperboloid(
+        center: Vec3,
+        waist: f64,
+        flare: f64,
+        half_height: f64,
+        mat: Material,
+    ) -> Self {
+        let kind = QuadricKind::Hyperboloid { waist, flare };
+        Quadric::new(kind, center, -half_height, half_height, mat)
+    }
+
+    /// Full sweep and no caps. Heights are relative to `center`
+    pub fn new(kind: QuadricKind, center: Vec3, y_min: f64, y_max: f64, mat: Material) -> Self {
+        Quadric {
+            k: kind.coefficients(),
+            center,
+            y_min: y_min.min(y_max),
+            y_max: y_min.max(y_max),
+            phi_max: 2. * PI,
+            caps: false,
+            mat,
+        }
+    }
+
+    /// Close the open ends with flat disks
+    pub fn with_caps(mut self) -> Self {
+        self.caps = true;
+        self
+    }
+
+    /// Only keep the part between 0 and `degrees` around the axis, measured from +x towards +z
+    pub fn with_sweep(mut self, degrees: f64) -> Self {
+        self.phi_max = degrees_to_radians(degrees.clamp(0., 360.));
+        self
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    fn radius_squared(&self, y: f64) -> f64 {
+        let (k0, k1, k2) = self.k;
+        k0 + k1 * y + k2 * y * y
+    }
+
+    /// Angle of local point `p` around the axis, in [0, 2pi)
+    fn phi(p: Vec3) -> f64 {
+        let phi = p.2.atan2(p.0);
+        if phi < 0. { phi + 2. * PI } else { phi }
+    }
+
+    fn in_sweep(&self, p: Vec3) -> bool {
+        self.phi_max >= 2. * PI || Quadric::phi(p) <= self.phi_max
+    }
+
+    /// Nearest valid hit on the curved surface: (t, local point)
+    fn hit_body(&self, o: Vec3, d: Vec3, ray_t: Interval) -> Option<(f64, Vec3)> {
+        let (k0, k1, k2) = self.k;
+
+        let a = d.0 * d.0 + d.2 * d.2 - k2 * d.1 * d.1;
+        let b = 2. * (o.0 * d.0 + o.2 * d.2) - k1 * d.1 - 2. * k2 * o.1 * d.1;
+        let c = o.0 * o.0 + o.2 * o.2 - (k0 + k1 * o.1 + k2 * o.1 * o.1);
+
+        solve_quadratic(a, b, c)
+            .into_iter()
+            .flatten()
+            .find_map(|t| {
+                if !ray_t.surrounds(t) {
+                    return None;
+                }
+                let p = o + t * d;
+                let valid = p.1 >= self.y_min && p.1 <= self.y_max && self.in_sweep(p);
+                valid.then_some((t, p))
+            })
+    }
+
+    /// Nearest valid hit on either end cap: (t, local point, cap height)
+    fn hit_caps(&self, o: Vec3, d: Vec3, ray_t: Interval) -> Option<(f64, Vec3, f64)> {
+        if !self.caps || d.1 == 0. {
+            return None;
+        }
+
+        [self.y_min, self.y_max]
+            .into_iter()
+            .filter_map(|y| {
+                let t = (y - o.1) / d.1;
+                let p = o + t * d;
+                let r2 = self.radius_squared(y);
+                let inside = r2 > 0. && p.0 * p.0 + p.2 * p.2 <= r2 && self.in_sweep(p);
+                (ray_t.surrounds(t) && inside).then_some((t, p, y))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+impl Hittable for Quadric {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        let o = r.origin - self.center;
+        let d = r.direction;
+
+        let body = self.hit_body(o, d, ray_t);
+        let cap = self.hit_caps(o, d, ray_t);
+
+        let (t, p, outward_normal, u, v) = match (body, cap) {
+            (Some((t, p)), cap) if cap.is_none_or(|c| t <= c.0) => {
+                // Scale back out to the exact radius at this height
+                let current = (p.0 * p.0 + p.2 * p.2).sqrt();
+                let scale = if current > 0. {
+                    self.radius_squared(p.1).max(0.).sqrt() / current
+                } else {
+                    1.
+                };
+                let p = Vec3(p.0 * scale, p.1, p.2 * scale);
+
+                let (_, k1, k2) = self.k;
+                // Gradient of the implicit function, pointing away from the inside
+                let normal = unit(Vec3(2. * p.0, -(k1 + 2. * k2 * p.1), 2. * p.2));
+                let v = (p.1 - self.y_min) / (self.y_max - self.y_min);
+                (t, p, normal, Quadric::phi(p) / self.phi_max, v)
+            }
+            (_, Some((t, p, y))) => {
+                let p = Vec3(p.0, y, p.2);
+                let normal = if y == self.y_max {
+                    Vec3(0., 1., 0.)
+                } else {
+                    Vec3(0., -1., 0.)
+                };
+                let radial = (p.0 * p.0 + p.2 * p.2).sqrt() / self.radius_squared(y).sqrt();
+                (t, p, normal, Quadric::phi(p) / self.phi_max, radial)
+            }
+            _ => return (false, None),
+        };
+
+        let mut rec = HitRecord::new(
+            r,
+            t,
+            p + self.center,
+            outward_normal,
+            self.mat.clone(),
+            u,
+            v,
+        );
+        rec.p_error = gamma(8) * (p.abs() + self.center.abs());
+        (true, Some(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        // k2 >= 0 for every kind, so the widest point is at one of the ends
+        let r = self
+            .radius_squared(self.y_min)
+            .max(self.radius_squared(self.y_max))
+            .max(0.)
+            .sqrt();
+        let lo = self.center + Vec3(-r, self.y_min, -r);
+        let hi = self.center + Vec3(r, self.y_max, r);
+        Aabb::from_points(lo, hi).pad_to_minimums()
+    }
+}
+
+#[cfg(test)]
+mod quadric_tests {
+    use super::*;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn shoot(q: &Quadric, origin: Vec3, direction: Vec3) -> Option<HitRecord> {
+        q.hit(
+            &Ray::new(origin, direction),
+            Interval::new(0.001, f64::INFINITY),
+        )
+        .1
+    }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn cylinder_side_and_caps() {
+        let cyl = Quadric::cylinder(Vec3(0., 1., 0.), 1., 2., grey());
+
+        let rec = shoot(&cyl, Vec3(-5., 2., 0.), Vec3(1., 0., 0.)).unwrap();
+        assert_eq!(rec.t, 4.);
+        assert!(close(rec.normal, Vec3(-1., 0., 0.)));
+        assert!(rec.front_face);
+        assert!((rec.u - 0.5).abs() < 1e-12);
+        assert!((rec.v - 0.5).abs() < 1e-12);
+
+        // Far away rays don't lose the quadratic term next to the huge constant one
+        let far = shoot(&cyl, Vec3(-1e6, 2., 0.), Vec3(1., 0., 0.)).unwrap();
+        assert!((far.t - (1e6 - 1.)).abs() < 1e-6);
+
+        // Open ends let rays along the axis through...
+        assert!(shoot(&cyl, Vec3(0., 10., 0.), Vec3(0., -1., 0.)).is_none());
+
+        // ...until they're capped
+        let capped = Quadric::cylinder(Vec3(0., 1., 0.), 1., 2., grey()).with_caps();
+        let rec = shoot(&capped, Vec3(0.5, 10., 0.), Vec3(0., -1., 0.)).unwrap();
+        assert_eq!(rec.t, 7.);
+        assert!(close(rec.normal, Vec3(0., 1., 0.)));
+        assert!((rec.v - 0.5).abs() < 1e-12);
+
+        // From inside, the bottom cap is hit from behind
+        let rec = shoot(&capped, Vec3(0., 2., 0.), Vec3(0., -1., 0.)).unwrap();
+        assert_eq!(rec.t, 1.);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn cone_normals_tilt_upward() {
+        let cone = Quadric::cone(Vec3(0., 0., 0.), 1., 1., grey());
+
+        let rec = shoot(&cone, Vec3(-5., 0.5, 0.), Vec3(1., 0., 0.)).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-12);
+        let h = 0.5f64.sqrt();
+        assert!(close(rec.normal, Vec3(-h, h, 0.)));
+
+        // Above the apex there's nothing
+        assert!(shoot(&cone, Vec3(-5., 1.5, 0.), Vec3(1., 0., 0.)).is_none());
+    }
+
+    #[test]
+    fn paraboloid_from_above() {
+        let dish = Quadric::paraboloid(Vec3(0., 0., 0.), 2., 4., grey());
+
+        // y = x^2 here, so a ray down x = 1 lands at y = 1, inside the cup
+        let rec = shoot(&dish, Vec3(1., 10., 0.), Vec3(0., -1., 0.)).unwrap();
+        assert!((rec.t - 9.).abs() < 1e-12);
+        assert!(!rec.front_face);
+        assert!(close(rec.normal, unit(Vec3(-2., 1., 0.))));
+    }
+
+    #[test]
+    fn hyperboloid_waist() {
+        let hyp = Quadric::hyperboloid(Vec3(0., 0., 0.), 1., 1., 1., grey());
+
+        let waist = shoot(&hyp, Vec3(-5., 0., 0.), Vec3(1., 0., 0.)).unwrap();
+        assert!((waist.t - 4.).abs() < 1e-12);
+
+        let rim = shoot(&hyp, Vec3(-5., 1., 0.), Vec3(1., 0., 0.)).unwrap();
+        assert!((rim.t - (5. - 2f64.sqrt())).abs() < 1e-12);
+
+        let bbox = hyp.bounding_box();
+        assert!((bbox.x.max - 2f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn partial_sweep() {
+        let half = Quadric::cylinder(Vec3(0., 0., 0.), 1., 1., grey()).with_sweep(180.);
+
+        // z > 0 half is kept, so from -z the ray passes through the gap to the far wall
+        let rec = shoot(&half, Vec3(0., 0.5, -5.), Vec3(0., 0., 1.)).unwrap();
+        assert_eq!(rec.t, 6.);
+        assert!(!rec.front_face);
+        assert!((rec.u - 0.5).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,444 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    global_stuff::gamma,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    sphere::sphere_uv,
+    vec3::{Vec3, dot, unit},
+};
+
+/// Most steps a ray takes before giving up on finding the surface
+const MAX_STEPS: usize = 512;
+
+/// How close to the surface counts as touching it, relative to the half-size of the bounds
+const HIT_DISTANCE: f64 = 1e-6;
+
+/// Offset used for the finite-difference normal, relative to the hit distance
+const GRADIENT_STEP: f64 = 10.;
+
+/// A signed distance function: negative inside the shape, positive outside, and never more
+/// than the true distance to the surface so that stepping by it can't skip past it.
+///
+/// Primitives are centered on the origin. Build scenes by combining them, e.g.
+/// `Sdf::sphere(1.).smooth_union(Sdf::capsule(a, b, 0.2), 0.3).translate(offset)`.
+#[derive(Clone)]
+pub enum Sdf {
+    Sphere {
+        radius: f64,
+    },
+    /// Box with corners at +-`half`
+    Box {
+        half: Vec3,
+    },
+    /// Box with corners at +-`half`, with its edges rounded off to `radius`
+    RoundBox {
+        half: Vec3,
+        radius: f64,
+    },
+    /// Points within `radius` of the segment from `a` to `b`
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f64,
+    },
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    /// Union that blends the two shapes together over a distance of about `k`
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f64),
+    /// Copies of the shape every `period` along each axis. A zero component doesn't repeat.
+    /// The shape should fit within one cell, or the copies' distances get cut off
+    Repeat(Box<Sdf>, Vec3),
+    /// Shape twisted around the y axis by `rate` radians per unit of height.
+    ///
+    /// Twisting stretches distances, so march it with a step scale below 1 - about
+    /// `1 / sqrt(1 + (rate * r)^2)` for a shape reaching `r` from the axis.
+    Twist(Box<Sdf>, f64),
+    Translate(Box<Sdf>, Vec3),
+    Custom(Arc<dyn Fn(Vec3) -> f64 + Send + Sync>),
+}
+
+#[allow(dead_code)]
+impl Sdf {
+    pub fn sphere(radius: f64) -> Sdf {
+        Sdf::Sphere { radius }
+    }
+
+    pub fn cuboid(half: Vec3) -> Sdf {
+        Sdf::Box { half }
+    }
+
+    pub fn round_box(half: Vec3, radius: f64) -> Sdf {
+        Sdf::RoundBox { half, radius }
+    }
+
+    pub fn capsule(a: Vec3, b: Vec3, radius: f64) -> Sdf {
+        Sdf::Capsule { a, b, radius }
+    }
+
+    pub fn custom(f: impl Fn(Vec3) -> f64 + Send + Sync + 'static) -> Sdf {
+        Sdf::Custom(Arc::new(f))
+    }
+
+    pub fn union(self, other: Sdf) -> Sdf {
+        Sdf::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersection(self, other: Sdf) -> Sdf {
+        Sdf::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: Sdf, k: f64) -> Sdf {
+        Sdf::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn repeat(self, period: Vec3) -> Sdf {
+        Sdf::Repeat(Box::new(self), period)
+    }
+
+    pub fn twist(self, rate: f64) -> Sdf {
+        Sdf::Twist(Box::new(self), rate)
+    }
+
+    pub fn translate(self, offset: Vec3) -> Sdf {
+        Sdf::Translate(Box::new(self), offset)
+    }
+
+    pub fn distance(&self, p: Vec3) -> f64 {
+        match self {
+            Sdf::Sphere { radius } => p.length() - radius,
+            Sdf::Box { half } => box_distance(p, *half),
+            Sdf::RoundBox { half, radius } => {
+                box_distance(p, *half - Vec3::splat(*radius)) - radius
+            }
+            Sdf::Capsule { a, b, radius } => {
+                let (pa, ba) = (p - *a, *b - *a);
+                let h = (dot(pa, ba) / ba.length_squared()).clamp(0., 1.);
+                (pa - h * ba).length() - radius
+            }
+            Sdf::Union(a, b) => a.distance(p).min(b.distance(p)),
+            Sdf::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            Sdf::SmoothUnion(a, b, k) => smooth_min(a.distance(p), b.distance(p), *k),
+            Sdf::Repeat(shape, period) => {
+                let wrap = |x: f64, c: f64| if c > 0. { x - c * (x / c).round() } else { x };
+                let q = Vec3(
+                    wrap(p.0, period.0),
+                    wrap(p.1, period.1),
+                    wrap(p.2, period.2),
+                );
+                shape.distance(q)
+            }
+            Sdf::Twist(shape, rate) => {
+                let (s, c) = (-rate * p.1).sin_cos();
+                shape.distance(Vec3(c * p.0 - s * p.2, p.1, s * p.0 + c * p.2))
+            }
+            Sdf::Translate(shape, offset) => shape.distance(p - *offset),
+            Sdf::Custom(f) => f(p),
+        }
+    }
+
+    /// Central-difference gradient, using the four corners of a tetrahedron to save two
+    /// evaluations over the axis-aligned version. `h` is the distance to each corner
+    pub fn gradient(&self, p: Vec3, h: f64) -> Vec3 {
+        let corners = [
+            Vec3(1., -1., -1.),
+            Vec3(-1., -1., 1.),
+            Vec3(-1., 1., -1.),
+            Vec3(1., 1., 1.),
+        ];
+
+        corners.iter().fold(Vec3(0., 0., 0.), |acc, &k| {
+            acc + k * self.distance(p + h * k)
+        }) / (4. * h)
+    }
+}
+
+fn box_distance(p: Vec3, half: Vec3) -> f64 {
+    let q = Vec3(p.0.abs() - half.0, p.1.abs() - half.1, p.2.abs() - half.2);
+    let outside = Vec3(q.0.max(0.), q.1.max(0.), q.2.max(0.)).length();
+    let inside = q.0.max(q.1).max(q.2).min(0.);
+    outside + inside
+}
+
+/// Polynomial smooth minimum: equal to `min(a, b)` once they're more than `k` apart
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0. {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0., 1.);
+    b + h * (a - b) - k * h * (1. - h)
+}
+
+/// Shape described by a signed distance function, rendered by sphere tracing.
+///
+/// The ray steps forward by the distance to the nearest surface until that distance is
+/// negligible. `bounds` has to contain the whole surface - only that stretch of the ray is
+/// marched - and is what the BVH sees.
+pub struct SdfObject {
+    sdf: Sdf,
+    bounds: Aabb,
+    step_scale: f64,
+    hit_distance: f64,
+    mat: Material,
+}
+
+#[allow(dead_code)]
+impl SdfObject {
+    pub fn new(sdf: Sdf, bounds: Aabb, mat: Material) -> Self {
+        // Scale the tolerance with the shape, but keep it above the rounding error of distances
+        // measured that far from the origin
+        let half = 0.5 * bounds.axis_interval(bounds.longest_axis()).size();
+        let reach = bounds.min().length().max(bounds.max().length());
+        SdfObject {
+            sdf,
+            bounds,
+            step_scale: 1.,
+            hit_distance: (HIT_DISTANCE * half).max(gamma(64) * reach),
+            mat,
+        }
+    }
+
+    /// Take steps of only `scale` times the distance, for fields that overestimate it
+    pub fn with_step_scale(mut self, scale: f64) -> Self {
+        self.step_scale = scale.clamp(1e-3, 1.);
+        self
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for SdfObject {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        let Some(span) = self.bounds.clip(r, ray_t) else {
+            return (false, None);
+        };
+
+        // March in world units along the unit direction
+        let length = r.direction.length();
+        let d = r.direction / length;
+        let end = span.max * length;
+
+        let mut s = span.min * length;
+        let start = r.origin + s * d;
+
+        // Rays that begin inside the shape, like refracted ones, march towards its boundary
+        // from within using the negated distance
+        let sign = self.sdf.distance(start).signum();
+
+        let mut found = None;
+        for _ in 0..MAX_STEPS {
+            let distance = sign * self.sdf.distance(r.origin + s * d);
+            if distance < self.hit_distance {
+                found = Some(s);
+                break;
+            }
+            s += distance * self.step_scale;
+            if s > end {
+                break;
+            }
+        }
+
+        let Some(s) = found else {
+            return (false, None);
+        };
+        let t = s / length;
+        if !ray_t.surrounds(t) {
+            return (false, None);
+        }
+
+        let p = r.at(t);
+        let outward_normal = unit(self.sdf.gradient(p, GRADIENT_STEP * self.hit_distance));
+        // Distance fields have no natural parametrization, so map by the way the surface faces
+        let (u, v) = sphere_uv(outward_normal);
+
+        // The march stops anywhere within the hit distance of the surface. Rays leaving it need
+        // to start clear of that band on the far side too, or they'd stop on the spot
+        let mut rec = HitRecord::new(r, t, p, outward_normal, self.mat.clone(), u, v);
+        rec.p_error += Vec3::splat(3. * self.hit_distance);
+        (true, Some(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod sdf_tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn cube(half: f64) -> Aabb {
+        Aabb::from_points(Vec3::splat(-half), Vec3::splat(half))
+    }
+
+    fn shoot(obj: &SdfObject, origin: Vec3, direction: Vec3) -> Option<HitRecord> {
+        obj.hit(
+            &Ray::new(origin, direction),
+            Interval::new(0.001, f64::INFINITY),
+        )
+        .1
+    }
+
+    #[test]
+    fn primitive_distances() {
+        let p = Vec3(3., 0., 0.);
+        assert!((Sdf::sphere(1.).distance(p) - 2.).abs() < 1e-12);
+        assert!((Sdf::cuboid(Vec3(1., 2., 3.)).distance(p) - 2.).abs() < 1e-12);
+        assert!((Sdf::cuboid(Vec3::splat(1.)).distance(Vec3(0., 0., 0.)) + 1.).abs() < 1e-12);
+
+        // Off a corner, the rounded box is a sphere around the inner box's corner
+        let rounded = Sdf::round_box(Vec3::splat(1.), 0.5);
+        let corner = Vec3::splat(2.);
+        let expected = (corner - Vec3::splat(0.5)).length() - 0.5;
+        assert!((rounded.distance(corner) - expected).abs() < 1e-12);
+
+        let capsule = Sdf::capsule(Vec3(0., -1., 0.), Vec3(0., 1., 0.), 0.5);
+        assert!((capsule.distance(Vec3(2., 0.5, 0.)) - 1.5).abs() < 1e-12);
+        assert!((capsule.distance(Vec3(0., 3., 0.)) - 1.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn combinators() {
+        let a = Sdf::sphere(1.).translate(Vec3(-1., 0., 0.));
+        let b = Sdf::sphere(1.).translate(Vec3(1., 0., 0.));
+        let p = Vec3(0., 0., 0.);
+
+        assert!((a.clone().union(b.clone()).distance(p)).abs() < 1e-12);
+        let lens = a.clone().intersection(b.clone());
+        assert!((lens.distance(Vec3(0., 0.5, 0.)) - (1.25f64.sqrt() - 1.)).abs() < 1e-12);
+
+        // Blending pulls the surface out beyond either shape where they meet
+        let smooth = a.clone().smooth_union(b.clone(), 0.5);
+        let gap = Vec3(0., 1., 0.);
+        assert!(smooth.distance(gap) < a.clone().union(b).distance(gap));
+
+        let tiles = Sdf::sphere(0.5).repeat(Vec3(4., 0., 0.));
+        assert!((tiles.distance(Vec3(8., 0., 0.)) + 0.5).abs() < 1e-12);
+        assert!((tiles.distance(Vec3(8., 2., 0.)) - 1.5).abs() < 1e-12);
+
+        // Quarter turn at height 1 maps x onto z
+        let twisted = Sdf::cuboid(Vec3(2., 5., 0.1)).twist(std::f64::consts::FRAC_PI_2);
+        assert!(twisted.distance(Vec3(0., 1., 1.5)) < 0.);
+        assert!(twisted.distance(Vec3(1.5, 1., 0.)) > 0.);
+    }
+
+    #[test]
+    fn traced_sphere_matches_analytic_one() {
+        let sdf = SdfObject::new(Sdf::sphere(1.), cube(1.), grey());
+        let sphere = Sphere::new(Vec3(0., 0., 0.), 1., grey());
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+
+        // Fixed rays from a few spots around the sphere through a lattice of targets, so every
+        // run checks the same hits and misses
+        let origins = [
+            Vec3(3.2, 0.4, -0.7),
+            Vec3(-2.1, 2.6, 0.9),
+            Vec3(0.3, -1.8, 3.1),
+            Vec3(-3.4, -1.1, -1.6),
+        ];
+        let mut hits = 0;
+        for origin in origins {
+            for i in 0..7 {
+                for j in 0..7 {
+                    for k in 0..7 {
+                        let target = 0.3 * Vec3(i as f64, j as f64, k as f64) - Vec3::splat(0.9);
+                        let r = Ray::new(origin, target - origin);
+
+                        let (_, expected) = sphere.hit(&r, ray_t);
+                        let (_, rec) = sdf.hit(&r, ray_t);
+
+                        match (&rec, &expected) {
+                            (Some(a), Some(b)) => {
+                                hits += 1;
+                                assert!((a.t - b.t).abs() < 1e-5);
+                                assert!((a.normal - b.normal).length() < 1e-4);
+                                assert!(a.front_face);
+                            }
+                            (None, None) => {}
+                            _ => panic!("sdf and sphere disagree toward {target:?}"),
+                        }
+                    }
+                }
+            }
+        }
+        assert!(hits > 500, "{hits}");
+    }
+
+    #[test]
+    fn grazing_rays_settle_on_the_right_side() {
+        let sdf = SdfObject::new(Sdf::sphere(1.), cube(1.), grey());
+        let sphere = Sphere::new(Vec3(0., 0., 0.), 1., grey());
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+
+        // Rays along x that pass just outside or just inside the sphere
+        let across = unit(Vec3(0., 1., 1.));
+        for gap in [1e-1, 1e-2, 1e-3] {
+            let outside = Ray::new(Vec3(-3., 0., 0.) + (1. + gap) * across, Vec3(1., 0., 0.));
+            assert!(sdf.hit(&outside, ray_t).1.is_none(), "{gap}");
+
+            let inside = Ray::new(Vec3(-3., 0., 0.) + (1. - gap) * across, Vec3(1., 0., 0.));
+            let a = sdf.hit(&inside, ray_t).1.unwrap();
+            let b = sphere.hit(&inside, ray_t).1.unwrap();
+
+            // The march stops short of the surface, but no further off it than the hit distance,
+            // however shallow the angle
+            let off = a.p.length() - 1.;
+            assert!((-1e-12..sdf.hit_distance).contains(&off), "{gap}: {off}");
+            assert!(a.t <= b.t + 1e-12);
+            assert!((a.normal - b.normal).length() < 1e-4, "{gap}");
+            assert!(a.front_face);
+        }
+    }
+
+    #[test]
+    fn ray_from_inside() {
+        let sdf = SdfObject::new(Sdf::cuboid(Vec3::splat(1.)), cube(1.), grey());
+        let rec = shoot(&sdf, Vec3(0., 0., 0.), Vec3(0., 2., 0.)).unwrap();
+
+        assert!((rec.t - 0.5).abs() < 1e-5);
+        assert!(!rec.front_face);
+        assert!((rec.normal - Vec3(0., -1., 0.)).length() < 1e-6);
+    }
+
+    #[test]
+    fn custom_twisted_and_repeated() {
+        // A plane isn't bounded, but the object's box cuts it off
+        let floor = SdfObject::new(
+            Sdf::custom(|p| p.1),
+            Aabb::from_points(Vec3(-5., -1., -5.), Vec3(5., 1., 5.)),
+            grey(),
+        );
+        let rec = shoot(&floor, Vec3(1., 3., 1.), Vec3(0., -1., 0.)).unwrap();
+        assert!((rec.t - 3.).abs() < 1e-5);
+        assert!(shoot(&floor, Vec3(10., 3., 1.), Vec3(0., -1., 0.)).is_none());
+
+        let twisted = SdfObject::new(
+            Sdf::cuboid(Vec3(1., 2., 0.2)).twist(1.),
+            Aabb::from_points(Vec3(-1.1, -2., -1.1), Vec3(1.1, 2., 1.1)),
+            grey(),
+        )
+        .with_step_scale(0.5);
+        let rec = shoot(&twisted, Vec3(0., 10., 0.), Vec3(0., -1., 0.)).unwrap();
+        assert!((rec.t - 8.).abs() < 1e-5);
+
+        let row = SdfObject::new(
+            Sdf::sphere(0.5).repeat(Vec3(2., 0., 0.)),
+            Aabb::from_points(Vec3(-9., -1., -1.), Vec3(9., 1., 1.)),
+            grey(),
+        );
+        let rec = shoot(&row, Vec3(6., 5., 0.), Vec3(0., -1., 0.)).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-5);
+    }
+}
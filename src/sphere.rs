@@ -1,33 +1,81 @@
+use std::f64::consts::PI;
+
 use crate::{
-    hittable::{HitRecord, Hittable, set_face_normal},
+    aabb::Aabb,
+    global_stuff::gamma,
+    hittable::{HitRecord, Hittable},
     interval::Interval,
     material::Material,
-    vec3::{Vec3, dot},
+    motion::Path,
+    packet::{Lanes, Mask, PacketHits, RayPacket, Vec3Lanes, active},
+    random::Random,
+    ray::Ray,
+    vec3::{Vec3, cross, dot, perpendicular, random_unit_vector, unit},
 };
 
 pub struct Sphere {
-    center: Vec3,
+    center: Path,
     radius: f64,
     mat: Material,
+    bbox: Aabb,
 }
 
+#[allow(dead_code)]
 impl Sphere {
     pub fn new(center: Vec3, radius: f64, mat: Material) -> Self {
+        Sphere::along(Path::Fixed(center), radius, mat)
+    }
+
+    /// Sphere whose center follows `path` over time, for motion blur
+    pub fn along(center: Path, radius: f64, mat: Material) -> Self {
+        let rvec = Vec3::splat(radius);
+        let bbox = center
+            .corners()
+            .into_iter()
+            .fold(Aabb::default(), |acc, c| {
+                Aabb::surrounding(&acc, &Aabb::from_points(c - rvec, c + rvec))
+            });
+
         Sphere {
             center,
             radius,
             mat,
+            bbox,
         }
     }
 
     pub fn into_box(self) -> Box<Self> {
         Box::new(self)
     }
+
+    /// Fills in the record for a hit at `t`, once the root is known
+    fn record(&self, r: &Ray, t: f64, center: Vec3) -> HitRecord {
+        // Put the point back on the sphere, which bounds its error far tighter than r.at(t)
+        let offset = r.at(t) - center;
+        let offset = offset * (self.radius / offset.length());
+        let p = center + offset;
+
+        let outward_normal = offset / self.radius;
+        let (u, v) = sphere_uv(outward_normal);
+
+        let mut rec = HitRecord::new(r, t, p, outward_normal, self.mat.clone(), u, v);
+        rec.p_error = gamma(5) * (center.abs() + offset.abs());
+        rec
+    }
+}
+
+/// Maps a point on the unit sphere to (u, v) in [0, 1], with u going around the y axis from x = -1
+pub fn sphere_uv(p: Vec3) -> (f64, f64) {
+    let theta = (-p.1).acos();
+    let phi = (-p.2).atan2(p.0) + PI;
+
+    (phi / (2. * PI), theta / PI)
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, r: &crate::ray::Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
-        let oc = self.center - r.origin;
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        let center = self.center.at(r.time);
+        let oc = center - r.origin;
         let a = r.direction.length_squared();
         let h = dot(r.direction, oc);
         let c = oc.length_squared() - self.radius * self.radius;
@@ -46,20 +94,142 @@ impl Hittable for Sphere {
             }
         }
 
-        let t = root;
-        let p = r.at(t);
-        let outward_normal = (p - self.center) / self.radius;
-        let (normal, front_face) = set_face_normal(r, outward_normal);
-
-        (
-            true,
-            Some(HitRecord {
-                t,
-                p,
-                normal,
-                front_face,
-                mat: self.mat.clone(),
-            }),
-        )
+        (true, Some(self.record(r, root, center)))
+    }
+
+    fn hit_packet(&self, packet: &RayPacket, mask: Mask, ray_t: Interval, hits: &mut PacketHits) {
+        // The quadratic for every lane at once, in the same order as `hit` so the roots agree
+        let center = Vec3Lanes::from_fn(|lane| self.center.at(packet.time[lane]));
+        let oc = center - packet.origin;
+        let a = packet.direction.length_squared();
+        let h = packet.direction.dot(&oc);
+        let c = oc.length_squared().map(|l| l - self.radius * self.radius);
+        let discriminant: Lanes = std::array::from_fn(|i| h[i] * h[i] - a[i] * c[i]);
+
+        for lane in active(mask) {
+            if discriminant[lane] < 0. {
+                continue;
+            }
+            let closest = hits[lane].as_ref().map_or(ray_t.max, |rec| rec.t);
+            let lane_t = Interval::new(ray_t.min, closest);
+
+            let sqrtd = discriminant[lane].sqrt();
+            let mut root = (h[lane] - sqrtd) / a[lane];
+            if !lane_t.surrounds(root) {
+                root = (h[lane] + sqrtd) / a[lane];
+                if !lane_t.surrounds(root) {
+                    continue;
+                }
+            }
+
+            hits[lane] = Some(self.record(&packet.ray(lane), root, center.lane(lane)));
+        }
+    }
+
+    fn samples_as_light(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        if !self.hit(r, Interval::new(0., f64::INFINITY)).0 {
+            return 0.;
+        }
+
+        let distance_squared = (self.center.at(r.time) - r.origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 1. / (4. * PI);
+        }
+
+        let cos_theta_max = (1. - radius_squared / distance_squared).sqrt();
+        1. / (2. * PI * (1. - cos_theta_max))
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64) -> Vec3 {
+        let axis = self.center.at(time) - origin;
+        let distance_squared = axis.length_squared();
+        let radius_squared = self.radius * self.radius;
+
+        // From inside, every direction reaches the surface
+        if distance_squared <= radius_squared {
+            return random_unit_vector();
+        }
+
+        // Uniform over the cone of directions the sphere covers
+        let cos_theta_max = (1. - radius_squared / distance_squared).sqrt();
+        let z = 1. + f64::rnd() * (cos_theta_max - 1.);
+        let phi = 2. * PI * f64::rnd();
+        let sin_theta = (1. - z * z).max(0.).sqrt();
+
+        let w = unit(axis);
+        let u = perpendicular(w);
+        let v = cross(w, u);
+        (phi.cos() * sin_theta) * u + (phi.sin() * sin_theta) * v + z * w
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod sphere_tests {
+    use super::*;
+
+    #[test]
+    fn uv_at_poles_and_equator() {
+        let (u, v) = sphere_uv(Vec3(0., 1., 0.));
+        assert!((v - 1.).abs() < 1e-12);
+        assert!((0. ..=1.).contains(&u));
+
+        let (_, v) = sphere_uv(Vec3(0., -1., 0.));
+        assert!(v.abs() < 1e-12);
+
+        let (u, v) = sphere_uv(Vec3(-1., 0., 0.));
+        assert!((u - 0.).abs() < 1e-12 || (u - 1.).abs() < 1e-12);
+        assert!((v - 0.5).abs() < 1e-12);
+
+        let (u, _) = sphere_uv(Vec3(1., 0., 0.));
+        assert!((u - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn hit_from_outside_and_inside() {
+        let mat = Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        };
+        let sphere = Sphere::new(Vec3(0., 0., 0.), 1., mat);
+
+        let outside = Ray::new(Vec3(0., 0., -5.), Vec3(0., 0., 1.));
+        let (hit, rec) = sphere.hit(&outside, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert!(hit);
+        assert_eq!(rec.t, 4.);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3(0., 0., -1.));
+
+        let inside = Ray::new(Vec3(0., 0., 0.), Vec3(0., 0., 1.));
+        let (_, rec) = sphere.hit(&inside, Interval::new(0.001, f64::INFINITY));
+        let rec = rec.unwrap();
+        assert_eq!(rec.t, 1.);
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3(0., 0., -1.));
+    }
+
+    #[test]
+    fn moving_center() {
+        let mat = Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        };
+        let path = Path::linear(Vec3(0., 0., 0.), Vec3(0., 4., 0.), 0., 1.);
+        let sphere = Sphere::along(path, 1., mat);
+
+        let r = |time| Ray::with_time(Vec3(0., 4., -5.), Vec3(0., 0., 1.), time);
+        assert!(!sphere.hit(&r(0.), Interval::new(0.001, f64::INFINITY)).0);
+        assert!(sphere.hit(&r(1.), Interval::new(0.001, f64::INFINITY)).0);
+
+        let bbox = sphere.bounding_box();
+        assert_eq!(bbox.min(), Vec3(-1., -1., -1.));
+        assert_eq!(bbox.max(), Vec3(1., 5., 1.));
     }
 }
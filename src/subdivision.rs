@@ -0,0 +1,559 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    material::Material,
+    mesh::{MeshData, MeshFace},
+    vec3::{Vec3, cross, unit},
+};
+
+/// Edge between two vertices, smaller index first so both directions map to the same key
+type EdgeKey = (usize, usize);
+
+fn edge_key(a: usize, b: usize) -> EdgeKey {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+/// Polygon mesh that keeps its faces unsplit, as the control cage for subdivision surfaces.
+///
+/// Faces list their vertices counter-clockwise seen from outside. Crease edges, and edges on the
+/// boundary of an open mesh, stay sharp as the mesh is refined.
+#[derive(Clone)]
+pub struct PolyMesh {
+    pub positions: Vec<Vec3>,
+    pub faces: Vec<Vec<usize>>,
+    /// Index into `materials` for each face
+    pub face_materials: Vec<usize>,
+    pub materials: Vec<Material>,
+    pub creases: HashSet<EdgeKey>,
+}
+
+/// How the faces around one edge meet it
+struct EdgeInfo {
+    faces: Vec<usize>,
+    sharp: bool,
+}
+
+/// Connectivity the subdivision rules need, built fresh for each level
+struct Topology {
+    edges: HashMap<EdgeKey, EdgeInfo>,
+    /// Every vertex joined to each vertex by an edge
+    neighbors: Vec<Vec<usize>>,
+    /// The faces around each vertex
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+#[allow(dead_code)]
+impl PolyMesh {
+    pub fn new(positions: Vec<Vec3>, faces: Vec<Vec<usize>>, mat: Material) -> Self {
+        PolyMesh {
+            face_materials: vec![0; faces.len()],
+            positions,
+            faces,
+            materials: vec![mat],
+            creases: HashSet::new(),
+        }
+    }
+
+    /// Mark edges, given as pairs of vertex indices, to be kept sharp
+    pub fn with_creases(mut self, edges: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        self.creases
+            .extend(edges.into_iter().map(|(a, b)| edge_key(a, b)));
+        self
+    }
+
+    pub fn is_triangles(&self) -> bool {
+        self.faces.iter().all(|f| f.len() == 3)
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.topology().edges.len()
+    }
+
+    /// Refines the mesh `levels` times: Loop subdivision while it's all triangles, and
+    /// Catmull-Clark otherwise, which leaves only quads after the first step
+    pub fn subdivide(&self, levels: u32) -> PolyMesh {
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = if mesh.is_triangles() {
+                mesh.loop_step()
+            } else {
+                mesh.catmull_clark_step()
+            };
+        }
+        mesh
+    }
+
+    fn topology(&self) -> Topology {
+        let mut edges: HashMap<EdgeKey, EdgeInfo> = HashMap::new();
+        let mut vertex_faces = vec![Vec::new(); self.positions.len()];
+
+        for (f, face) in self.faces.iter().enumerate() {
+            for (i, &v) in face.iter().enumerate() {
+                let key = edge_key(v, face[(i + 1) % face.len()]);
+                edges
+                    .entry(key)
+                    .or_insert_with(|| EdgeInfo {
+                        faces: Vec::new(),
+                        sharp: self.creases.contains(&key),
+                    })
+                    .faces
+                    .push(f);
+                vertex_faces[v].push(f);
+            }
+        }
+
+        // Boundary and non-manifold edges have no well-defined smooth neighbourhood
+        let mut neighbors = vec![Vec::new(); self.positions.len()];
+        for (&(a, b), info) in edges.iter_mut() {
+            info.sharp |= info.faces.len() != 2;
+            neighbors[a].push(b);
+            neighbors[b].push(a);
+        }
+
+        Topology {
+            edges,
+            neighbors,
+            vertex_faces,
+        }
+    }
+
+    /// Neighbours of `v` across sharp edges
+    fn sharp_neighbors(topo: &Topology, v: usize) -> Vec<usize> {
+        topo.neighbors[v]
+            .iter()
+            .copied()
+            .filter(|&n| topo.edges[&edge_key(v, n)].sharp)
+            .collect()
+    }
+
+    /// Position of an old vertex on a crease or corner, or `None` if it's smooth
+    fn sharp_vertex(&self, topo: &Topology, v: usize) -> Option<Vec3> {
+        let p = self.positions[v];
+        let sharp = Self::sharp_neighbors(topo, v);
+        match sharp[..] {
+            // Where three or more creases meet it's a corner, and so is the tip of a flap with
+            // no other edges, like the corner of a square. Either way it stays put
+            _ if sharp.len() > 2 || (sharp.len() == 2 && topo.neighbors[v].len() == 2) => Some(p),
+            // On a crease line, it only feels the pull of its neighbours along the crease
+            [a, b] => Some(0.75 * p + 0.125 * (self.positions[a] + self.positions[b])),
+            _ => None,
+        }
+    }
+
+    /// Sorted edges, so new vertices are numbered the same way on every run
+    fn sorted_edges(topo: &Topology) -> Vec<EdgeKey> {
+        let mut keys: Vec<EdgeKey> = topo.edges.keys().copied().collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    fn split_creases(&self, edge_index: &HashMap<EdgeKey, usize>) -> HashSet<EdgeKey> {
+        self.creases
+            .iter()
+            .filter_map(|key| edge_index.get(key).map(|&mid| (key, mid)))
+            .flat_map(|(&(a, b), mid)| [edge_key(a, mid), edge_key(mid, b)])
+            .collect()
+    }
+
+    /// One level of Loop subdivision. Each triangle becomes four, with a new vertex on every
+    /// edge, so the mesh gains one vertex per edge.
+    pub fn loop_step(&self) -> PolyMesh {
+        assert!(
+            self.is_triangles(),
+            "Loop subdivision needs a triangle mesh"
+        );
+
+        let topo = self.topology();
+        let n_old = self.positions.len();
+        let edges = Self::sorted_edges(&topo);
+        let edge_index: HashMap<EdgeKey, usize> = edges
+            .iter()
+            .enumerate()
+            .map(|(i, &e)| (e, n_old + i))
+            .collect();
+
+        let mut positions = Vec::with_capacity(n_old + edges.len());
+
+        for v in 0..n_old {
+            let p = self.positions[v];
+            let moved = self.sharp_vertex(&topo, v).unwrap_or_else(|| {
+                let ring = &topo.neighbors[v];
+                let n = ring.len() as f64;
+                let beta = if ring.len() == 3 {
+                    3. / 16.
+                } else {
+                    3. / (8. * n)
+                };
+                let sum = ring
+                    .iter()
+                    .fold(Vec3(0., 0., 0.), |acc, &i| acc + self.positions[i]);
+                (1. - n * beta) * p + beta * sum
+            });
+            positions.push(moved);
+        }
+
+        for &(a, b) in &edges {
+            let info = &topo.edges[&(a, b)];
+            let (pa, pb) = (self.positions[a], self.positions[b]);
+            let point = if info.sharp {
+                0.5 * (pa + pb)
+            } else {
+                // Weighted towards the edge's own ends over the far corners of its triangles
+                let far = info.faces.iter().fold(Vec3(0., 0., 0.), |acc, &f| {
+                    let opposite = self.faces[f].iter().find(|&&v| v != a && v != b);
+                    acc + self.positions[*opposite.unwrap()]
+                });
+                0.375 * (pa + pb) + 0.125 * far
+            };
+            positions.push(point);
+        }
+
+        let mut faces = Vec::with_capacity(self.faces.len() * 4);
+        let mut face_materials = Vec::with_capacity(self.faces.len() * 4);
+        for (face, &mat) in self.faces.iter().zip(&self.face_materials) {
+            let [a, b, c] = [face[0], face[1], face[2]];
+            let ab = edge_index[&edge_key(a, b)];
+            let bc = edge_index[&edge_key(b, c)];
+            let ca = edge_index[&edge_key(c, a)];
+
+            faces.extend([
+                vec![a, ab, ca],
+                vec![b, bc, ab],
+                vec![c, ca, bc],
+                vec![ab, bc, ca],
+            ]);
+            face_materials.extend([mat; 4]);
+        }
+
+        PolyMesh {
+            creases: self.split_creases(&edge_index),
+            positions,
+            faces,
+            face_materials,
+            materials: self.materials.clone(),
+        }
+    }
+
+    /// One level of Catmull-Clark subdivision. Every n-sided face becomes n quads around a new
+    /// vertex at its middle, with new vertices on each edge as well - one new vertex per edge
+    /// and per face.
+    pub fn catmull_clark_step(&self) -> PolyMesh {
+        let topo = self.topology();
+        let n_old = self.positions.len();
+        let edges = Self::sorted_edges(&topo);
+        let edge_index: HashMap<EdgeKey, usize> = edges
+            .iter()
+            .enumerate()
+            .map(|(i, &e)| (e, n_old + i))
+            .collect();
+        let face_base = n_old + edges.len();
+
+        let face_points: Vec<Vec3> = self
+            .faces
+            .iter()
+            .map(|face| {
+                let sum = face
+                    .iter()
+                    .fold(Vec3(0., 0., 0.), |acc, &v| acc + self.positions[v]);
+                sum / face.len() as f64
+            })
+            .collect();
+
+        let mut positions = Vec::with_capacity(face_base + self.faces.len());
+
+        for v in 0..n_old {
+            let p = self.positions[v];
+            let moved = self.sharp_vertex(&topo, v).unwrap_or_else(|| {
+                // (F + 2R + (n - 3) P) / n, from the average of the surrounding face points and
+                // of the midpoints of the edges out of the vertex
+                let ring = &topo.neighbors[v];
+                let n = ring.len() as f64;
+                let adjacent = &topo.vertex_faces[v];
+                let f = adjacent
+                    .iter()
+                    .fold(Vec3(0., 0., 0.), |acc, &i| acc + face_points[i])
+                    / adjacent.len() as f64;
+                let r = ring.iter().fold(Vec3(0., 0., 0.), |acc, &i| {
+                    acc + 0.5 * (p + self.positions[i])
+                }) / n;
+                (f + 2. * r + (n - 3.) * p) / n
+            });
+            positions.push(moved);
+        }
+
+        for &(a, b) in &edges {
+            let info = &topo.edges[&(a, b)];
+            let mid = 0.5 * (self.positions[a] + self.positions[b]);
+            let point = if info.sharp {
+                mid
+            } else {
+                let faces = 0.5 * (face_points[info.faces[0]] + face_points[info.faces[1]]);
+                0.5 * (mid + faces)
+            };
+            positions.push(point);
+        }
+
+        positions.extend(face_points.iter().copied());
+
+        let mut faces = Vec::new();
+        let mut face_materials = Vec::new();
+        for (f, (face, &mat)) in self.faces.iter().zip(&self.face_materials).enumerate() {
+            let n = face.len();
+            for i in 0..n {
+                let (prev, v, next) = (face[(i + n - 1) % n], face[i], face[(i + 1) % n]);
+                faces.push(vec![
+                    v,
+                    edge_index[&edge_key(v, next)],
+                    face_base + f,
+                    edge_index[&edge_key(prev, v)],
+                ]);
+                face_materials.push(mat);
+            }
+        }
+
+        PolyMesh {
+            creases: self.split_creases(&edge_index),
+            positions,
+            faces,
+            face_materials,
+            materials: self.materials.clone(),
+        }
+    }
+
+    /// Splits the faces into triangles for rendering, with smooth vertex normals averaged
+    /// from the faces around each vertex
+    pub fn to_mesh_data(&self) -> MeshData {
+        let mut normals = vec![Vec3(0., 0., 0.); self.positions.len()];
+        let mut faces = Vec::new();
+
+        for (face, &material) in self.faces.iter().zip(&self.face_materials) {
+            for i in 1..face.len() - 1 {
+                let v = [face[0], face[i], face[i + 1]];
+                let [p0, p1, p2] = v.map(|i| self.positions[i]);
+
+                // Unnormalized, so bigger triangles count for more
+                let n = cross(p1 - p0, p2 - p0);
+                for &i in &v {
+                    normals[i] += n;
+                }
+
+                faces.push(MeshFace {
+                    v,
+                    n: Some(v),
+                    uv: None,
+                    material,
+                });
+            }
+        }
+
+        MeshData {
+            positions: self.positions.clone(),
+            normals: normals.into_iter().map(unit).collect(),
+            faces,
+            materials: self.materials.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod subdivision_tests {
+    use super::*;
+
+    fn grey() -> Material {
+        Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn cube() -> PolyMesh {
+        let positions = (0..8)
+            .map(|i| {
+                let bit = |b: usize| if i & (1 << b) != 0 { 1. } else { -1. };
+                Vec3(bit(0), bit(1), bit(2))
+            })
+            .collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        PolyMesh::new(positions, faces, grey())
+    }
+
+    fn octahedron() -> PolyMesh {
+        let positions = vec![
+            Vec3(1., 0., 0.),
+            Vec3(-1., 0., 0.),
+            Vec3(0., 1., 0.),
+            Vec3(0., -1., 0.),
+            Vec3(0., 0., 1.),
+            Vec3(0., 0., -1.),
+        ];
+        let faces = vec![
+            vec![0, 2, 4],
+            vec![2, 1, 4],
+            vec![1, 3, 4],
+            vec![3, 0, 4],
+            vec![2, 0, 5],
+            vec![1, 2, 5],
+            vec![3, 1, 5],
+            vec![0, 3, 5],
+        ];
+        PolyMesh::new(positions, faces, grey())
+    }
+
+    /// Every edge is shared by exactly two faces, running opposite ways in each
+    fn assert_watertight(mesh: &PolyMesh) {
+        let mut directed = HashSet::new();
+        for face in &mesh.faces {
+            for i in 0..face.len() {
+                let edge = (face[i], face[(i + 1) % face.len()]);
+                assert!(
+                    directed.insert(edge),
+                    "edge {edge:?} used twice the same way"
+                );
+            }
+        }
+        for &(a, b) in &directed {
+            assert!(
+                directed.contains(&(b, a)),
+                "edge ({a}, {b}) has only one face"
+            );
+        }
+    }
+
+    #[test]
+    fn loop_vertex_counts() {
+        let mesh = octahedron();
+        let (v, e, f) = (mesh.positions.len(), mesh.edge_count(), mesh.faces.len());
+        assert_eq!((v, e, f), (6, 12, 8));
+
+        let once = mesh.subdivide(1);
+        assert_eq!(once.positions.len(), v + e);
+        assert_eq!(once.faces.len(), 4 * f);
+        assert_eq!(once.edge_count(), 2 * e + 3 * f);
+        assert_watertight(&once);
+
+        let twice = mesh.subdivide(2);
+        assert_eq!(
+            twice.positions.len(),
+            once.positions.len() + once.edge_count()
+        );
+        assert_watertight(&twice);
+    }
+
+    #[test]
+    fn catmull_clark_vertex_counts() {
+        let mesh = cube();
+        let (v, e, f) = (mesh.positions.len(), mesh.edge_count(), mesh.faces.len());
+        assert_eq!((v, e, f), (8, 12, 6));
+
+        let once = mesh.subdivide(1);
+        assert_eq!(once.positions.len(), v + e + f);
+        assert_eq!(once.faces.len(), 24);
+        assert!(once.faces.iter().all(|face| face.len() == 4));
+        assert_watertight(&once);
+
+        let twice = mesh.subdivide(2);
+        assert_eq!(twice.positions.len(), 26 + 48 + 24);
+        assert_watertight(&twice);
+
+        // Mixed meshes go through Catmull-Clark too: a square pyramid
+        let pyramid = PolyMesh::new(
+            vec![
+                Vec3(-1., 0., -1.),
+                Vec3(1., 0., -1.),
+                Vec3(1., 0., 1.),
+                Vec3(-1., 0., 1.),
+                Vec3(0., 1., 0.),
+            ],
+            vec![
+                vec![0, 1, 2, 3],
+                vec![1, 0, 4],
+                vec![2, 1, 4],
+                vec![3, 2, 4],
+                vec![0, 3, 4],
+            ],
+            grey(),
+        );
+        let once = pyramid.subdivide(1);
+        assert_eq!(once.positions.len(), 5 + 8 + 5);
+        assert_eq!(once.faces.len(), 4 + 4 * 3);
+        assert_watertight(&once);
+    }
+
+    #[test]
+    fn surfaces_shrink_smoothly_towards_the_cage() {
+        // The limit surface lies inside the convex cage, and rounds off its corners
+        let round = cube().subdivide(3);
+        let radii: Vec<f64> = round.positions.iter().map(|p| p.length()).collect();
+        assert!(radii.iter().all(|&r| r < 3f64.sqrt()));
+        let (lo, hi) = radii
+            .iter()
+            .fold((f64::INFINITY, 0f64), |(lo, hi), &r| (lo.min(r), hi.max(r)));
+        assert!(hi - lo < 0.35, "{lo} to {hi}");
+
+        let ball = octahedron().subdivide(3);
+        assert!(ball.positions.iter().all(|p| p.length() <= 1.));
+    }
+
+    #[test]
+    fn creases_stay_sharp() {
+        // With every edge creased, the cube keeps its corners and flat faces
+        let edges: Vec<EdgeKey> = cube().topology().edges.into_keys().collect();
+        let sharp = cube().with_creases(edges).subdivide(2);
+
+        for corner in &sharp.positions[..8] {
+            assert!((corner.length() - 3f64.sqrt()).abs() < 1e-12);
+        }
+        for p in &sharp.positions {
+            let on_face = [p.0, p.1, p.2].iter().any(|c| (c.abs() - 1.).abs() < 1e-12);
+            assert!(on_face, "{p:?}");
+        }
+        assert_watertight(&sharp);
+
+        // A single creased edge keeps the middle of that edge further out than it otherwise
+        // rounds off to
+        let reach = |mesh: &PolyMesh| {
+            mesh.positions
+                .iter()
+                .filter(|p| p.0.abs() < 1e-12 && p.1 == p.2)
+                .fold(0f64, |far, p| far.max(-p.1))
+        };
+        let creased = cube().with_creases([(0, 1)]);
+        assert!(creased.subdivide(1).positions.contains(&Vec3(0., -1., -1.)));
+        assert!(reach(&creased.subdivide(3)) > reach(&cube().subdivide(3)) + 0.05);
+    }
+
+    #[test]
+    fn open_meshes_keep_their_boundary() {
+        // A flat grid of two squares: the outline stays put and everything stays in the plane
+        let patch = PolyMesh::new(
+            vec![
+                Vec3(0., 0., 0.),
+                Vec3(1., 0., 0.),
+                Vec3(2., 0., 0.),
+                Vec3(0., 0., 1.),
+                Vec3(1., 0., 1.),
+                Vec3(2., 0., 1.),
+            ],
+            vec![vec![0, 3, 4, 1], vec![1, 4, 5, 2]],
+            grey(),
+        );
+        let fine = patch.subdivide(2);
+        assert!(fine.positions.iter().all(|p| p.1 == 0.));
+        assert_eq!(fine.positions[0], Vec3(0., 0., 0.));
+
+        let data = fine.to_mesh_data();
+        assert_eq!(data.faces.len(), 2 * fine.faces.len());
+        assert!(
+            data.normals
+                .iter()
+                .all(|n| (*n - Vec3(0., 1., 0.)).length() < 1e-12)
+        );
+    }
+}
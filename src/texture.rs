@@ -0,0 +1,312 @@
+use std::{fs, io, path::Path, sync::Arc};
+
+use crate::{interval::Interval, noise::Noise, vec3::Vec3};
+
+/// Where a material gets its color from at each point of a surface
+#[derive(Clone)]
+pub enum Texture {
+    Solid(Vec3),
+    /// 3D checkerboard of cubes with sides of `scale`, alternating between `even` and `odd`.
+    /// Works from the hit point, so it needs no (u, v) and lines up across neighbouring objects
+    Checker {
+        scale: f64,
+        even: Box<Texture>,
+        odd: Box<Texture>,
+    },
+    /// Picture wrapped over the surface's (u, v), with v = 0 along the bottom row
+    Image(Arc<Picture>),
+    /// Bands of `vein` through `base` along z, warped by turbulence. Features are about
+    /// `1 / scale` across
+    Marble {
+        noise: Arc<Noise>,
+        scale: f64,
+        base: Vec3,
+        vein: Vec3,
+    },
+    /// Growth rings around the y axis, `1 / scale` apart and wobbled by noise
+    Wood {
+        noise: Arc<Noise>,
+        scale: f64,
+        light: Vec3,
+        dark: Vec3,
+    },
+    /// Speckled crystals - dark cracks where Worley cells meet, with fine turbulence over them
+    Granite {
+        noise: Arc<Noise>,
+        scale: f64,
+        light: Vec3,
+        dark: Vec3,
+    },
+}
+
+impl From<Vec3> for Texture {
+    fn from(color: Vec3) -> Self {
+        Texture::Solid(color)
+    }
+}
+
+#[allow(dead_code)]
+impl Texture {
+    pub fn checker(scale: f64, even: impl Into<Texture>, odd: impl Into<Texture>) -> Self {
+        Texture::Checker {
+            scale,
+            even: Box::new(even.into()),
+            odd: Box::new(odd.into()),
+        }
+    }
+
+    pub fn marble(seed: u64, scale: f64, base: Vec3, vein: Vec3) -> Self {
+        let noise = Arc::new(Noise::new(seed));
+        Texture::Marble {
+            noise,
+            scale,
+            base,
+            vein,
+        }
+    }
+
+    pub fn wood(seed: u64, scale: f64, light: Vec3, dark: Vec3) -> Self {
+        let noise = Arc::new(Noise::new(seed));
+        Texture::Wood {
+            noise,
+            scale,
+            light,
+            dark,
+        }
+    }
+
+    pub fn granite(seed: u64, scale: f64, light: Vec3, dark: Vec3) -> Self {
+        let noise = Arc::new(Noise::new(seed));
+        Texture::Granite {
+            noise,
+            scale,
+            light,
+            dark,
+        }
+    }
+
+    /// Color at surface coordinates (u, v), which is the point `p` in space
+    pub fn value(&self, u: f64, v: f64, p: Vec3) -> Vec3 {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Checker { scale, even, odd } => {
+                let cell = |x: f64| (x / scale).floor() as i64;
+                if (cell(p.0) + cell(p.1) + cell(p.2)).rem_euclid(2) == 0 {
+                    even.value(u, v, p)
+                } else {
+                    odd.value(u, v, p)
+                }
+            }
+            Texture::Image(picture) => picture.at(u, v),
+            Texture::Marble {
+                noise,
+                scale,
+                base,
+                vein,
+            } => {
+                let q = *scale * p;
+                let t = 0.5 * (1. + (q.2 + 10. * noise.turbulence(q, 7)).sin());
+                mix(*vein, *base, t)
+            }
+            Texture::Wood {
+                noise,
+                scale,
+                light,
+                dark,
+            } => {
+                let q = *scale * p;
+                let rings = q.0.hypot(q.2) + 0.4 * noise.fbm(Vec3(q.0, 0.1 * q.1, q.2), 4);
+                // Pale early wood fading into a sharper dark band at the end of each ring
+                let t = rings.rem_euclid(1.).powi(3);
+                mix(*light, *dark, t)
+            }
+            Texture::Granite {
+                noise,
+                scale,
+                light,
+                dark,
+            } => {
+                let q = *scale * p;
+                let (f1, f2) = noise.worley(q);
+                let cracks = ((f2 - f1) * 4.).min(1.);
+                let t = 0.7 * cracks + 0.3 * noise.turbulence(4. * q, 5);
+                mix(*dark, *light, t)
+            }
+        }
+    }
+}
+
+/// Blend from `a` at t = 0 to `b` at t = 1
+fn mix(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    (1. - t) * a + t * b
+}
+
+/// Decoded image, held as linear colors ready to multiply with light
+pub struct Picture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+#[allow(dead_code)]
+impl Picture {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Nearest pixel to (u, v), clamped to the edges
+    pub fn at(&self, u: f64, v: f64) -> Vec3 {
+        let unit = Interval::new(0., 1.);
+        let (u, v) = (unit.clamp(u), 1. - unit.clamp(v));
+
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+}
+
+/// Reads a PNG, JPEG or PPM as an image texture
+#[allow(dead_code)]
+pub fn load_image(path: impl AsRef<Path>) -> io::Result<Texture> {
+    parse_image(&fs::read(path)?)
+}
+
+pub fn parse_image(bytes: &[u8]) -> io::Result<Texture> {
+    let img = image::load_from_memory(bytes)
+        .map_err(|e| invalid_data(format!("Couldn't decode image: {e}")))?
+        .into_rgb32f();
+
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    if width == 0 || height == 0 {
+        return Err(invalid_data("Image has no pixels".to_string()));
+    }
+
+    // Files are gamma encoded. Undo the same gamma 2 that `write_color` applies on the way out
+    let linear = |c: f32| (c as f64) * (c as f64);
+    let pixels = img
+        .pixels()
+        .map(|p| Vec3(linear(p.0[0]), linear(p.0[1]), linear(p.0[2])))
+        .collect();
+
+    Ok(Texture::Image(Arc::new(Picture {
+        width,
+        height,
+        pixels,
+    })))
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod texture_tests {
+    use super::*;
+    use crate::{hittable::Hittable, material::Material, random::Random, ray::Ray, sphere::Sphere};
+
+    fn encode(img: image::RgbImage, format: image::ImageFormat) -> Vec<u8> {
+        let mut bytes = io::Cursor::new(Vec::new());
+        img.write_to(&mut bytes, format).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn checker_alternates_through_space() {
+        let black = Vec3(0., 0., 0.);
+        let white = Vec3(1., 1., 1.);
+        let checker = Texture::checker(0.5, white, black);
+
+        assert_eq!(checker.value(0., 0., Vec3(0.1, 0.1, 0.1)), white);
+        assert_eq!(checker.value(0., 0., Vec3(0.6, 0.1, 0.1)), black);
+        assert_eq!(checker.value(0., 0., Vec3(0.6, 0.6, 0.1)), white);
+        assert_eq!(checker.value(0., 0., Vec3(-0.1, 0.1, 0.1)), black);
+
+        // Surface coordinates don't matter
+        assert_eq!(checker.value(0.9, 0.3, Vec3(0.1, 0.1, 0.1)), white);
+    }
+
+    #[test]
+    fn noise_textures_repeat_from_their_seed() {
+        const LIGHT: Vec3 = Vec3(0.9, 0.8, 0.6);
+        const DARK: Vec3 = Vec3(0.3, 0.2, 0.1);
+        let makers: [fn(u64) -> Texture; 3] = [
+            |seed| Texture::marble(seed, 4., LIGHT, DARK),
+            |seed| Texture::wood(seed, 4., LIGHT, DARK),
+            |seed| Texture::granite(seed, 4., LIGHT, DARK),
+        ];
+
+        for make in makers {
+            let (a, b, other) = (make(11), make(11), make(12));
+            let mut changed = 0;
+
+            for _ in 0..500 {
+                let p = Vec3::rnd_rng(-3., 3.);
+                let c = a.value(0., 0., p);
+                assert_eq!(c, b.value(0., 0., p));
+                if c != other.value(0., 0., p) {
+                    changed += 1;
+                }
+
+                // Always some blend of the two colors
+                for (x, (lo, hi)) in [(c.0, (DARK.0, LIGHT.0)), (c.2, (DARK.2, LIGHT.2))] {
+                    assert!(lo - 1e-12 <= x && x <= hi + 1e-12);
+                }
+            }
+            assert!(changed > 250);
+        }
+    }
+
+    #[test]
+    fn image_wraps_uv() {
+        // Red top left, green top right, blue bottom left, white bottom right
+        let img = image::RgbImage::from_fn(2, 2, |x, y| match (x, y) {
+            (0, 0) => image::Rgb([255, 0, 0]),
+            (1, 0) => image::Rgb([0, 255, 0]),
+            (0, 1) => image::Rgb([0, 0, 255]),
+            _ => image::Rgb([255, 255, 255]),
+        });
+
+        for format in [image::ImageFormat::Png, image::ImageFormat::Pnm] {
+            let texture = parse_image(&encode(img.clone(), format)).unwrap();
+            let p = Vec3(0., 0., 0.);
+
+            assert_eq!(texture.value(0.25, 0.75, p), Vec3(1., 0., 0.));
+            assert_eq!(texture.value(0.75, 0.75, p), Vec3(0., 1., 0.));
+            assert_eq!(texture.value(0.25, 0.25, p), Vec3(0., 0., 1.));
+            assert_eq!(texture.value(1., 0., p), Vec3(1., 1., 1.));
+
+            // Off the edges clamps to the border
+            assert_eq!(texture.value(-3., 7., p), Vec3(1., 0., 0.));
+        }
+    }
+
+    #[test]
+    fn image_is_linearized() {
+        let grey = image::RgbImage::from_pixel(1, 1, image::Rgb([128, 128, 128]));
+        let jpeg = encode(grey, image::ImageFormat::Jpeg);
+        let texture = parse_image(&jpeg).unwrap();
+
+        let c = texture.value(0.5, 0.5, Vec3(0., 0., 0.));
+        assert!((c.0 - 0.25).abs() < 0.02, "{c:?}");
+
+        assert!(parse_image(b"not an image").is_err());
+    }
+
+    #[test]
+    fn lambertian_takes_its_color_from_the_texture() {
+        let mat = Material::Lambertian {
+            albedo: Texture::checker(1., Vec3(1., 0., 0.), Vec3(0., 0., 1.)),
+        };
+        let ball = Sphere::new(Vec3(0., 0., 0.), 0.5, mat);
+
+        for (x, color) in [(0.25, Vec3(1., 0., 0.)), (-0.25, Vec3(0., 0., 1.))] {
+            let r = Ray::new(Vec3(x, 0.25, 5.), Vec3(0., 0., -1.));
+            let rec = ball.hit(&r, Interval::new(0., f64::INFINITY)).1.unwrap();
+            assert_eq!(rec.mat.scatter(&r, &rec).unwrap().attenuation, color);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::f64::consts::PI;
+
+use crate::{
+    aabb::Aabb,
+    global_stuff::gamma,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    poly::real_roots,
+    ray::Ray,
+    vec3::{Vec3, dot, unit},
+};
+
+/// Ring around the y axis: a tube of radius `minor` whose center line is a circle of radius
+/// `major` in the xz plane.
+///
+/// The surface is the zero set of `(|p|^2 + R^2 - r^2)^2 - 4 R^2 (x^2 + z^2)`, so a ray meets
+/// it at the roots of a quartic. Surface u goes around the y axis from +x towards +z, and v
+/// around the tube starting from its outer equator.
+pub struct Torus {
+    center: Vec3,
+    major: f64,
+    minor: f64,
+    mat: Material,
+}
+
+#[allow(dead_code)]
+impl Torus {
+    pub fn new(center: Vec3, major: f64, minor: f64, mat: Material) -> Self {
+        Torus {
+            center,
+            major,
+            minor,
+            mat,
+        }
+    }
+
+    pub fn into_box(self) -> Box<Self> {
+        Box::new(self)
+    }
+
+    /// Coefficients of the quartic along the unit direction `d` from local point `o`
+    fn quartic(&self, o: Vec3, d: Vec3) -> [f64; 5] {
+        let r2 = self.major * self.major;
+        let f = dot(o, d);
+        let e = o.length_squared() + r2 - self.minor * self.minor;
+        let g = 4. * r2;
+
+        let dd = d.0 * d.0 + d.2 * d.2;
+        let od = o.0 * d.0 + o.2 * d.2;
+        let oo = o.0 * o.0 + o.2 * o.2;
+
+        [
+            e * e - g * oo,
+            4. * f * e - 2. * g * od,
+            4. * f * f + 2. * e - g * dd,
+            4. * f,
+            1.,
+        ]
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> (bool, Option<HitRecord>) {
+        // Work along the unit direction so the coefficients are in distance units
+        let length = r.direction.length();
+        let d = r.direction / length;
+        let o = r.origin - self.center;
+
+        // Only solve over the stretch inside a slightly padded bounding sphere. Starting from
+        // its near side keeps the origin close to the torus, which would otherwise cost
+        // precision for far away rays
+        let bound = 1.01 * (self.major + self.minor);
+        let h = dot(o, d);
+        let half_chord_squared = bound * bound - (o - h * d).length_squared();
+        if half_chord_squared < 0. {
+            return (false, None);
+        }
+        let half_chord = half_chord_squared.sqrt();
+        let (enter, exit) = (-h - half_chord, -h + half_chord);
+        let start = enter.max(ray_t.min * length);
+        let end = exit.min(ray_t.max * length);
+        if start > end {
+            return (false, None);
+        }
+
+        let near = o + start * d;
+        let coeffs = self.quartic(near, d);
+
+        let Some(t) = real_roots(&coeffs, 0., end - start)
+            .into_iter()
+            .map(|s| (start + s) / length)
+            .find(|&t| ray_t.surrounds(t))
+        else {
+            return (false, None);
+        };
+
+        // Pull the point onto the nearest spot of the tube, which bounds its error far tighter
+        // than the quartic's root does
+        let p = o + (t * length) * d;
+        let ring = Vec3(p.0, 0., p.2);
+        let ring = if ring.near_zero() {
+            Vec3(self.major, 0., 0.)
+        } else {
+            self.major * unit(ring)
+        };
+        let p = ring + self.minor * unit(p - ring);
+
+        // Gradient of the implicit function, pointing out of the tube
+        let radial = Vec3(p.0, 0., p.2);
+        let sum = p.length_squared() + self.major * self.major - self.minor * self.minor;
+        let outward_normal = unit(4. * sum * p - 8. * self.major * self.major * radial);
+
+        let phi = p.2.atan2(p.0);
+        let theta = p.1.atan2(radial.length() - self.major);
+        let u = if phi < 0. { phi + 2. * PI } else { phi } / (2. * PI);
+        let v = if theta < 0. { theta + 2. * PI } else { theta } / (2. * PI);
+
+        let mut rec = HitRecord::new(
+            r,
+            t,
+            p + self.center,
+            outward_normal,
+            self.mat.clone(),
+            u,
+            v,
+        );
+        rec.p_error = gamma(10) * (ring.abs() + (p - ring).abs() + self.center.abs());
+        (true, Some(rec))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let extent = Vec3(self.major + self.minor, self.minor, self.major + self.minor);
+        Aabb::from_points(self.center - extent, self.center + extent)
+    }
+}
+
+#[cfg(test)]
+mod torus_tests {
+    use super::*;
+    use crate::random::Random;
+
+    fn ring() -> Torus {
+        let mat = Material::Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+        };
+        Torus::new(Vec3(0., 0., 0.), 2., 0.5, mat)
+    }
+
+    fn shoot(torus: &Torus, origin: Vec3, direction: Vec3) -> Option<HitRecord> {
+        torus
+            .hit(
+                &Ray::new(origin, direction),
+                Interval::new(0.001, f64::INFINITY),
+            )
+            .1
+    }
+
+    #[test]
+    fn through_the_middle() {
+        let torus = ring();
+
+        let rec = shoot(&torus, Vec3(-10., 0., 0.), Vec3(1., 0., 0.)).unwrap();
+        assert!((rec.t - 7.5).abs() < 1e-9);
+        assert!((rec.normal - Vec3(-1., 0., 0.)).length() < 1e-9);
+        assert!(rec.front_face);
+        assert!((rec.u - 0.5).abs() < 1e-9);
+        assert!(rec.v.abs() < 1e-9 || (rec.v - 1.).abs() < 1e-9);
+
+        // Unnormalized directions give t in the ray's own units
+        let rec = shoot(&torus, Vec3(-10., 0., 0.), Vec3(4., 0., 0.)).unwrap();
+        assert!((rec.t - 7.5 / 4.).abs() < 1e-9);
+
+        // Starting in the hole, the inner wall is next
+        let rec = shoot(&torus, Vec3(0., 0., 0.), Vec3(1., 0., 0.)).unwrap();
+        assert!((rec.t - 1.5).abs() < 1e-9);
+
+        // Starting inside the tube, the hit is from behind
+        let rec = shoot(&torus, Vec3(2., 0., 0.), Vec3(1., 0., 0.)).unwrap();
+        assert!((rec.t - 0.5).abs() < 1e-9);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn along_the_axis() {
+        let torus = ring();
+
+        // Straight down the hole, every root is complex
+        assert!(shoot(&torus, Vec3(0., 100., 0.), Vec3(0., -1., 0.)).is_none());
+        assert!(shoot(&torus, Vec3(0., 1e6, 0.), Vec3(0., -1., 0.)).is_none());
+
+        // Parallel to the axis through the tube's center line
+        let rec = shoot(&torus, Vec3(2., 1e6, 0.), Vec3(0., -1., 0.)).unwrap();
+        assert!((rec.t - (1e6 - 0.5)).abs() < 1e-6);
+        assert!((rec.normal - Vec3(0., 1., 0.)).length() < 1e-6);
+        assert!((rec.v - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn grazing_rays() {
+        let torus = ring();
+
+        // Skimming the top of the tube touches it at x = -2, a double root
+        let rec = shoot(&torus, Vec3(-10., 0.5, 0.), Vec3(1., 0., 0.)).unwrap();
+        assert!((rec.t - 8.).abs() < 1e-3);
+
+        let below = shoot(&torus, Vec3(-10., 0.5 - 1e-6, 0.), Vec3(1., 0., 0.));
+        assert!((below.unwrap().t - 8.).abs() < 2e-3);
+
+        assert!(shoot(&torus, Vec3(-10., 0.5 + 1e-6, 0.), Vec3(1., 0., 0.)).is_none());
+
+        // Parallel to the axis, just touching the inner equator
+        let rec = shoot(&torus, Vec3(1.5, 10., 0.), Vec3(0., -1., 0.)).unwrap();
+        assert!((rec.t - 10.).abs() < 1e-3);
+        assert!(shoot(&torus, Vec3(1.5 - 1e-6, 10., 0.), Vec3(0., -1., 0.)).is_none());
+
+        // Tangent to the outer equator
+        let rec = shoot(&torus, Vec3(2.5, 0., -10.), Vec3(0., 0., 1.)).unwrap();
+        assert!((rec.t - 10.).abs() < 1e-3);
+        assert!(shoot(&torus, Vec3(2.5 + 1e-6, 0., -10.), Vec3(0., 0., 1.)).is_none());
+    }
+
+    #[test]
+    fn hits_lie_on_the_surface() {
+        let torus = ring();
+
+        for _ in 0..2000 {
+            let origin = Vec3::rnd_rng(-5., 5.);
+            let target = Vec3::rnd_rng(-2.5, 2.5);
+            let Some(rec) = shoot(&torus, origin, target - origin) else {
+                continue;
+            };
+
+            // Distance from the tube's center circle is the minor radius
+            let p = rec.p;
+            let ring_distance = (Vec3(p.0, 0., p.2).length() - 2.).hypot(p.1);
+            assert!((ring_distance - 0.5).abs() < 1e-6, "{ring_distance}");
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use std::ops;
+
+use crate::{
+    aabb::Aabb,
+    global_stuff::{degrees_to_radians, gamma},
+    vec3::Vec3,
+};
+
+/// Row-major 4x4 matrix, applied to column vectors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4(pub [[f64; 4]; 4]);
+
+#[allow(dead_code)]
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.;
+        }
+        Mat4(m)
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut t = [[0.; 4]; 4];
+        for (i, row) in t.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = self.0[j][i];
+            }
+        }
+        Mat4(t)
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting. None for singular matrices
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.0;
+        let mut inv = Mat4::identity().0;
+
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-300 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1. / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+
+        Some(Mat4(inv))
+    }
+
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.0;
+        let x = m[0][0] * p.0 + m[0][1] * p.1 + m[0][2] * p.2 + m[0][3];
+        let y = m[1][0] * p.0 + m[1][1] * p.1 + m[1][2] * p.2 + m[1][3];
+        let z = m[2][0] * p.0 + m[2][1] * p.1 + m[2][2] * p.2 + m[2][3];
+        let w = m[3][0] * p.0 + m[3][1] * p.1 + m[3][2] * p.2 + m[3][3];
+
+        if w == 1. {
+            Vec3(x, y, z)
+        } else {
+            Vec3(x, y, z) / w
+        }
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3(
+            m[0][0] * v.0 + m[0][1] * v.1 + m[0][2] * v.2,
+            m[1][0] * v.0 + m[1][1] * v.1 + m[1][2] * v.2,
+            m[2][0] * v.0 + m[2][1] * v.1 + m[2][2] * v.2,
+        )
+    }
+}
+
+impl ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.0[i][k] * other.0[k][j]).sum();
+            }
+        }
+        Mat4(m)
+    }
+}
+
+/// Affine transform, kept together with its inverse
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    m: Mat4,
+    inv: Mat4,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+#[allow(dead_code)]
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            m: Mat4::identity(),
+            inv: Mat4::identity(),
+        }
+    }
+
+    /// None if `m` can't be inverted
+    pub fn from_matrix(m: Mat4) -> Option<Transform> {
+        Some(Transform {
+            m,
+            inv: m.inverse()?,
+        })
+    }
+
+    pub fn translate(delta: Vec3) -> Transform {
+        let mut m = Mat4::identity();
+        let mut inv = Mat4::identity();
+        for axis in 0..3 {
+            m.0[axis][3] = delta[axis];
+            inv.0[axis][3] = -delta[axis];
+        }
+        Transform { m, inv }
+    }
+
+    /// Panics on a zero scale factor, which would flatten the object
+    pub fn scale(factors: Vec3) -> Transform {
+        assert!(
+            factors.0 != 0. && factors.1 != 0. && factors.2 != 0.,
+            "Scale factors must be non-zero"
+        );
+        let mut m = Mat4::identity();
+        let mut inv = Mat4::identity();
+        for axis in 0..3 {
+            m.0[axis][axis] = factors[axis];
+            inv.0[axis][axis] = 1. / factors[axis];
+        }
+        Transform { m, inv }
+    }
+
+    pub fn rotate_x(degrees: f64) -> Transform {
+        Transform::rotate(degrees, Vec3(1., 0., 0.))
+    }
+
+    pub fn rotate_y(degrees: f64) -> Transform {
+        Transform::rotate(degrees, Vec3(0., 1., 0.))
+    }
+
+    pub fn rotate_z(degrees: f64) -> Transform {
+        Transform::rotate(degrees, Vec3(0., 0., 1.))
+    }
+
+    /// Counter-clockwise rotation around `axis`, looking down the axis towards the origin
+    pub fn rotate(degrees: f64, axis: Vec3) -> Transform {
+        let a = axis.unit();
+        let theta = degrees_to_radians(degrees);
+        let (sin, cos) = theta.sin_cos();
+
+        let mut m = Mat4::identity();
+        m.0[0][0] = a.0 * a.0 + (1. - a.0 * a.0) * cos;
+        m.0[0][1] = a.0 * a.1 * (1. - cos) - a.2 * sin;
+        m.0[0][2] = a.0 * a.2 * (1. - cos) + a.1 * sin;
+        m.0[1][0] = a.0 * a.1 * (1. - cos) + a.2 * sin;
+        m.0[1][1] = a.1 * a.1 + (1. - a.1 * a.1) * cos;
+        m.0[1][2] = a.1 * a.2 * (1. - cos) - a.0 * sin;
+        m.0[2][0] = a.0 * a.2 * (1. - cos) - a.1 * sin;
+        m.0[2][1] = a.1 * a.2 * (1. - cos) + a.0 * sin;
+        m.0[2][2] = a.2 * a.2 + (1. - a.2 * a.2) * cos;
+
+        // Rotations are orthogonal, so the inverse is the transpose
+        Transform {
+            m,
+            inv: m.transpose(),
+        }
+    }
+
+    /// Applies `self` first, then `next`
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform {
+            m: next.m * self.m,
+            inv: self.inv * next.inv,
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.m
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        self.m.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.m.transform_vector(v)
+    }
+
+    /// Transforms `p` along with a bound on its error, adding the rounding of the transform itself
+    pub fn point_with_error(&self, p: Vec3, p_error: Vec3) -> (Vec3, Vec3) {
+        let m = &self.m.0;
+        let row_error = |i: usize| {
+            let spread =
+                m[i][0].abs() * p_error.0 + m[i][1].abs() * p_error.1 + m[i][2].abs() * p_error.2;
+            let terms = (m[i][0] * p.0).abs()
+                + (m[i][1] * p.1).abs()
+                + (m[i][2] * p.2).abs()
+                + m[i][3].abs();
+            (1. + gamma(3)) * spread + gamma(3) * terms
+        };
+
+        (
+            self.point(p),
+            Vec3(row_error(0), row_error(1), row_error(2)),
+        )
+    }
+
+    /// Normals transform by the inverse transpose so they stay perpendicular to the surface.
+    /// The result isn't normalized
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inv.transpose().transform_vector(n)
+    }
+
+    /// Box around all eight transformed corners of `bbox`
+    pub fn bbox(&self, bbox: &Aabb) -> Aabb {
+        let (lo, hi) = (bbox.min(), bbox.max());
+        let mut out = Aabb::default();
+
+        for i in 0..8 {
+            let corner = Vec3(
+                if i & 1 == 0 { lo.0 } else { hi.0 },
+                if i & 2 == 0 { lo.1 } else { hi.1 },
+                if i & 4 == 0 { lo.2 } else { hi.2 },
+            );
+            let p = self.point(corner);
+            out = Aabb::surrounding(&out, &Aabb::from_points(p, p));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod transform_tests {
+    use super::*;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-9
+    }
+
+    #[test]
+    fn rotation_directions() {
+        let r = Transform::rotate_y(90.);
+        assert!(close(r.vector(Vec3(1., 0., 0.)), Vec3(0., 0., -1.)));
+
+        let r = Transform::rotate_z(90.);
+        assert!(close(r.vector(Vec3(1., 0., 0.)), Vec3(0., 1., 0.)));
+
+        let r = Transform::rotate(120., Vec3(1., 1., 1.));
+        assert!(close(r.vector(Vec3(1., 0., 0.)), Vec3(0., 1., 0.)));
+    }
+
+    #[test]
+    fn composition_order() {
+        let t = Transform::scale(Vec3(2., 2., 2.)).then(&Transform::translate(Vec3(1., 0., 0.)));
+        assert!(close(t.point(Vec3(1., 1., 1.)), Vec3(3., 2., 2.)));
+        assert!(close(t.inverse().point(Vec3(3., 2., 2.)), Vec3(1., 1., 1.)));
+        // Directions ignore translation
+        assert!(close(t.vector(Vec3(1., 0., 0.)), Vec3(2., 0., 0.)));
+    }
+
+    #[test]
+    fn general_inverse() {
+        let t = Transform::rotate(33., Vec3(1., 2., 3.))
+            .then(&Transform::scale(Vec3(1., -3., 0.5)))
+            .then(&Transform::translate(Vec3(4., 5., 6.)));
+
+        let inv = t.matrix().inverse().unwrap();
+        let product = *t.matrix() * inv;
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1. } else { 0. };
+                assert!((product.0[i][j] - expected).abs() < 1e-12);
+            }
+        }
+
+        assert!(Mat4([[0.; 4]; 4]).inverse().is_none());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let t = Transform::scale(Vec3(4., 1., 1.));
+        // Tangent (1, -1, 0) with normal (1, 1, 0) on a slanted plane
+        let tangent = t.vector(Vec3(1., -1., 0.));
+        let normal = t.normal(Vec3(1., 1., 0.));
+        assert!(tangent.dot(normal).abs() < 1e-12);
+    }
+}
@@ -23,6 +23,7 @@ pub fn random_unit_vector() -> Vec3 {
     }
 }
 
+#[allow(dead_code)]
 pub fn random_on_hemisphere(normal: Vec3) -> Vec3 {
     let on_unit_sphere = random_unit_vector();
     if dot(on_unit_sphere, normal) > 0.0 {
@@ -54,6 +55,17 @@ pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
     r_out_perp + r_out_parallel
 }
 
+/// Some unit vector at right angles to `v`, for building a frame around it
+pub fn perpendicular(v: Vec3) -> Vec3 {
+    // Cross with whichever axis is furthest from parallel
+    let axis = if v.0.abs() < 0.5 {
+        Vec3(1., 0., 0.)
+    } else {
+        Vec3(0., 1., 0.)
+    };
+    unit(cross(v, axis))
+}
+
 pub fn cross(u: Vec3, w: Vec3) -> Vec3 {
     Vec3(
         u.1 * w.2 - u.2 * w.1,
@@ -80,6 +92,10 @@ impl Vec3 {
         cross(self, other)
     }
 
+    pub fn abs(self) -> Vec3 {
+        Vec3(self.0.abs(), self.1.abs(), self.2.abs())
+    }
+
     pub fn length_squared(self) -> f64 {
         dot(self, self)
     }
@@ -94,7 +110,7 @@ impl Vec3 {
 
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
-        self.0 < s && self.1 < s && self.2 < s
+        self.0.abs() < s && self.1.abs() < s && self.2.abs() < s
     }
 }
 
@@ -134,6 +150,19 @@ impl ops::SubAssign for Vec3 {
     }
 }
 
+impl ops::Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("Vec3 axis out of range: {axis}"),
+        }
+    }
+}
+
 // Multiply combinations
 
 impl ops::Mul for Vec3 {
@@ -272,6 +301,12 @@ mod vec_tests {
         assert_eq!(Vec3(2., 3., 6.).unit(), Vec3(2. / 7., 3. / 7., 6. / 7.));
     }
 
+    #[test]
+    fn vector_near_zero() {
+        assert!(Vec3(1e-9, -1e-9, 0.).near_zero());
+        assert!(!Vec3(-1., -2., -3.).near_zero());
+    }
+
     #[test]
     fn vector_cross() {
         let u = Vec3(2., 3., 0.);